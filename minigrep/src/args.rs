@@ -0,0 +1,287 @@
+// Turning the command line into a Config
+//
+// Short options can be combined (-in is -i -n) and take their value either attached or as the
+// next argument (-A3 or -A 3); long ones take it after a '=' or as the next argument
+// (--after-context=3 or --after-context 3). Options can go anywhere until a "--", after which
+// everything is the query or a path, even if it starts with '-'.
+
+use std::error::Error;
+use std::fmt;
+
+use glob::Glob;
+use color::ColorChoice;
+use {Column, Config, Output};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    Unknown(String), // the option as given
+    MissingValue(String),
+    UnexpectedValue(String),
+    InvalidValue { option: String, value: String, expected: &'static str },
+    MissingQuery,
+    // Not mistakes, but what stops the parsing short of a Config
+    Help,
+    Version,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArgsError::Unknown(ref option) => write!(f, "unknown option '{}'", option),
+            ArgsError::MissingValue(ref option) => write!(f, "'{}' needs a value", option),
+            ArgsError::UnexpectedValue(ref option) => write!(f, "'{}' doesn't take a value", option),
+            ArgsError::InvalidValue { ref option, ref value, expected } => {
+                write!(f, "invalid value '{}' for '{}': expected {}", value, option, expected)
+            },
+            ArgsError::MissingQuery => write!(f, "didn't get a query to search for"),
+            ArgsError::Help => write!(f, "help requested"),
+            ArgsError::Version => write!(f, "version requested"),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Value {
+    No,
+    Required(&'static str), // named for the help
+    Optional(&'static str), // only ever given after a '='
+}
+
+struct Opt {
+    short: Option<char>,
+    long: &'static str,
+    value: Value,
+    help: &'static str,
+}
+
+const OPTIONS: &[Opt] = &[
+    Opt { short: Some('E'), long: "regex", value: Value::No, help: "QUERY is a regular expression" },
+    Opt { short: Some('i'), long: "ignore-case", value: Value::No, help: "match regardless of case" },
+    Opt { short: None, long: "case-sensitive", value: Value::No, help: "match case exactly (the default)" },
+    Opt { short: Some('v'), long: "invert-match", value: Value::No, help: "select the lines that don't match" },
+    Opt { short: Some('A'), long: "after-context", value: Value::Required("N"), help: "print N lines after each match" },
+    Opt { short: Some('B'), long: "before-context", value: Value::Required("N"), help: "print N lines before each match" },
+    Opt { short: Some('C'), long: "context", value: Value::Required("N"), help: "print N lines before and after each match" },
+    Opt { short: Some('n'), long: "line-number", value: Value::No, help: "print line numbers" },
+    Opt { short: None, long: "column", value: Value::Optional("chars|bytes"), help: "print the column of the first match" },
+    Opt { short: Some('c'), long: "count", value: Value::No, help: "print only how many lines matched in each file" },
+    Opt { short: Some('l'), long: "files-with-matches", value: Value::No, help: "print only the names of files with a match" },
+    Opt { short: Some('L'), long: "files-without-match", value: Value::No, help: "print only the names of files without one" },
+    Opt { short: Some('o'), long: "only-matching", value: Value::No, help: "print only the matching parts of lines" },
+    Opt { short: Some('m'), long: "max-count", value: Value::Required("N"), help: "stop reading a file after N matching lines" },
+    Opt { short: None, long: "color", value: Value::Optional("WHEN"), help: "highlight with colors: auto (the default), always or never" },
+    Opt { short: None, long: "json", value: Value::No, help: "print the results as JSON Lines, for other programs" },
+    Opt { short: None, long: "include", value: Value::Required("GLOB"), help: "search only files matching GLOB" },
+    Opt { short: None, long: "exclude", value: Value::Required("GLOB"), help: "skip files matching GLOB" },
+    Opt { short: None, long: "hidden", value: Value::No, help: "search hidden files and directories" },
+    Opt { short: None, long: "follow", value: Value::No, help: "follow symbolic links" },
+    Opt { short: None, long: "no-ignore", value: Value::No, help: "don't skip what .gitignore and .minigrepignore list" },
+    Opt { short: Some('h'), long: "help", value: Value::No, help: "print this help and exit" },
+    Opt { short: Some('V'), long: "version", value: Value::No, help: "print the version and exit" },
+];
+
+pub fn usage() -> String {
+    let mut usage = String::from("\
+Usage: minigrep [OPTION]... QUERY [PATH]...
+Searches the files, and directories recursively, for lines containing QUERY.
+With no PATH, or when PATH is -, reads the standard input.
+
+Options:
+");
+
+    for opt in OPTIONS {
+        let short = opt.short.map_or("    ".to_string(), |c| format!("-{}, ", c));
+        let value = match opt.value {
+            Value::No => String::new(),
+            Value::Required(name) => format!(" {}", name),
+            Value::Optional(name) => format!("[={}]", name),
+        };
+        usage += &format!("  {:<32}{}\n", format!("{}--{}{}", short, opt.long, value), opt.help);
+    }
+
+    usage += "\nEnvironment:\n";
+    usage += &format!("  {:<32}{}\n", "CASE_INSENSITIVE", "when set, match regardless of case unless --case-sensitive is given");
+    usage
+}
+
+// The arguments, without the program's name
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Config, ArgsError> {
+    parse_over(defaults(|_| false), args)
+}
+
+// What the environment asks for, given whether each variable is set. CASE_INSENSITIVE is from
+// before there were options, and only a default: the options still have the last word
+pub fn defaults<F: Fn(&str) -> bool>(is_set: F) -> Config {
+    Config { case_sensitive: !is_set("CASE_INSENSITIVE"), ..Config::default() }
+}
+
+// The arguments over the given defaults
+pub fn parse_over<I: IntoIterator<Item = String>>(defaults: Config, args: I) -> Result<Config, ArgsError> {
+    let mut config = defaults;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional.extend(args.by_ref());
+        } else if arg.starts_with("--") {
+            let (given, attached) = match arg.find('=') {
+                Some(i) => (&arg[..i], Some(arg[i + 1..].to_string())),
+                None => (&arg[..], None),
+            };
+            let opt = OPTIONS.iter()
+                             .find(|opt| opt.long == &given[2..])
+                             .ok_or_else(|| ArgsError::Unknown(given.to_string()))?;
+
+            let value = match (opt.value, attached) {
+                (Value::No, Some(_)) => return Err(ArgsError::UnexpectedValue(given.to_string())),
+                (Value::Required(_), None) => Some(args.next().ok_or_else(|| ArgsError::MissingValue(given.to_string()))?),
+                (_, attached) => attached,
+            };
+            apply(&mut config, opt, given, value)?;
+        } else if arg.starts_with('-') && arg != "-" {
+            let cluster = &arg[1..];
+            for (i, c) in cluster.char_indices() {
+                let given = format!("-{}", c);
+                let opt = OPTIONS.iter()
+                                 .find(|opt| opt.short == Some(c))
+                                 .ok_or_else(|| ArgsError::Unknown(given.clone()))?;
+
+                if let Value::Required(_) = opt.value {
+                    // The rest of the cluster is the value, or else the next argument
+                    let rest = &cluster[i + c.len_utf8()..];
+                    let value = if rest.is_empty() { args.next() } else { Some(rest.to_string()) };
+                    let value = value.ok_or_else(|| ArgsError::MissingValue(given.clone()))?;
+                    apply(&mut config, opt, &given, Some(value))?;
+                    break;
+                }
+                apply(&mut config, opt, &given, None)?;
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    config.query = positional.next().ok_or(ArgsError::MissingQuery)?;
+    config.paths = positional.collect();
+    if config.paths.is_empty() {
+        config.paths.push("-".to_string()); // The standard input
+    }
+
+    Ok(config)
+}
+
+// Sets what the option stands for; given is how it was written, for the errors
+fn apply(config: &mut Config, opt: &Opt, given: &str, value: Option<String>) -> Result<(), ArgsError> {
+    let number = |value: Option<String>| {
+        let value = value.unwrap_or_default();
+        value.parse().map_err(|_| ArgsError::InvalidValue { option: given.to_string(), value, expected: "a number" })
+    };
+
+    match opt.long {
+        "regex" => config.regex = true,
+        "ignore-case" => config.case_sensitive = false,
+        "case-sensitive" => config.case_sensitive = true,
+        "invert-match" => config.invert = true,
+        "after-context" => config.after = number(value)?,
+        "before-context" => config.before = number(value)?,
+        "context" => {
+            config.before = number(value)?;
+            config.after = config.before;
+        },
+        "line-number" => config.line_numbers = true,
+        "column" => config.column = match value.as_deref() {
+            None | Some("chars") => Some(Column::Chars),
+            Some("bytes") => Some(Column::Bytes),
+            Some(other) => {
+                return Err(ArgsError::InvalidValue { option: given.to_string(), value: other.to_string(), expected: "chars or bytes" });
+            },
+        },
+        "color" => config.color = match value.as_deref() {
+            None | Some("auto") => ColorChoice::Auto,
+            Some("always") => ColorChoice::Always,
+            Some("never") => ColorChoice::Never,
+            Some(other) => {
+                return Err(ArgsError::InvalidValue { option: given.to_string(), value: other.to_string(), expected: "auto, always or never" });
+            },
+        },
+        "json" => config.json = true,
+        "count" => config.output = Output::Count,
+        "files-with-matches" => config.output = Output::FilesWithMatches,
+        "files-without-match" => config.output = Output::FilesWithoutMatch,
+        "only-matching" => config.only_matching = true,
+        "max-count" => config.max_count = Some(number(value)?),
+        "include" => config.walk.include.push(Glob::new(&value.unwrap_or_default())),
+        "exclude" => config.walk.exclude.push(Glob::new(&value.unwrap_or_default())),
+        "hidden" => config.walk.hidden = true,
+        "follow" => config.walk.follow_links = true,
+        "no-ignore" => config.walk.no_ignore = true,
+        "help" => return Err(ArgsError::Help),
+        "version" => return Err(ArgsError::Version),
+        _ => unreachable!("option --{} isn't handled", opt.long),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Result<Config, ArgsError> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn flags_anywhere() {
+        let config = args("-inA2 duct poem.txt --max-count 3 src --column=bytes").unwrap();
+        assert_eq!(("duct", vec!["poem.txt", "src"]), (config.query.as_str(), config.paths.iter().map(String::as_str).collect()));
+        assert!(!config.case_sensitive && config.line_numbers);
+        assert_eq!((0, 2, Some(3), Some(Column::Bytes)), (config.before, config.after, config.max_count, config.column));
+
+        let config = args("-C 1 -c --include=*.rs --include *.txt -- -v -").unwrap();
+        assert_eq!(("-v", vec!["-"]), (config.query.as_str(), config.paths.iter().map(String::as_str).collect()));
+        assert_eq!((1, 1, Output::Count, 2, false), (config.before, config.after, config.output, config.walk.include.len(), config.invert));
+
+        assert_eq!(vec!["-"], args("nobody").unwrap().paths);
+        assert_eq!(ColorChoice::Never, args("--color=never nobody").unwrap().color);
+    }
+
+    #[test]
+    fn options_override_the_environment() {
+        let case_insensitive = |line: &str| {
+            let config = parse_over(defaults(|name| name == "CASE_INSENSITIVE"), line.split_whitespace().map(String::from));
+            !config.unwrap().case_sensitive
+        };
+        assert!(case_insensitive("duct"));
+        assert!(!case_insensitive("--case-sensitive duct"));
+        assert!(case_insensitive("--case-sensitive -i duct"));
+        assert!(args("duct").unwrap().case_sensitive);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Some(ArgsError::Unknown("-x".to_string())), args("-nx duct").err());
+        assert_eq!(Some(ArgsError::Unknown("--colour".to_string())), args("--colour duct").err());
+        assert_eq!(Some(ArgsError::MissingValue("-m".to_string())), args("duct -m").err());
+        assert_eq!(Some(ArgsError::UnexpectedValue("--count".to_string())), args("--count=2 duct").err());
+        assert_eq!("invalid value 'x' for '--context': expected a number", args("--context=x duct").err().unwrap().to_string());
+        assert_eq!("invalid value 'lines' for '--column': expected chars or bytes", args("--column=lines duct").err().unwrap().to_string());
+        assert_eq!("invalid value 'yes' for '--color': expected auto, always or never", args("--color=yes duct").err().unwrap().to_string());
+        assert_eq!(Some(ArgsError::MissingQuery), args("-n").err());
+        assert_eq!(Some(ArgsError::Help), args("duct --help").err());
+        assert_eq!(Some(ArgsError::Version), args("-V").err());
+    }
+
+    #[test]
+    fn documents_every_option() {
+        let usage = usage();
+        assert!(OPTIONS.iter().all(|opt| usage.contains(&format!("--{}", opt.long))));
+        assert!(usage.contains("  -A, --after-context N "));
+        assert!(usage.contains("CASE_INSENSITIVE"));
+    }
+}
@@ -0,0 +1,50 @@
+// Unicode case folding, for comparing text regardless of case
+//
+// Folding goes through uppercase and back to lowercase, which maps every case variant of a letter
+// to the same characters: 'ß' and 'ẞ' fold to "ss" like "SS" does, and final 'ς' to 'σ'. The Turkish
+// dotless 'ı' is the exception, since it's a letter of its own and not an 'i' without its dot.
+
+// The characters c folds to, usually just one
+pub fn fold_char(c: char) -> Vec<char> {
+    match c {
+        'ı' => return vec![c],
+        'ẞ' => return vec!['s', 's'], // its own uppercase, but folds like 'ß'
+        _ => {},
+    }
+
+    c.to_uppercase().flat_map(|upper| upper.to_lowercase()).collect()
+}
+
+pub fn fold(text: &str) -> String {
+    text.chars().flat_map(fold_char).collect()
+}
+
+// For matching a character at a time: c's folding when it's a single character, its lowercase otherwise
+pub fn simple_fold(c: char) -> char {
+    match fold_char(c).as_slice() {
+        [folded] => *folded,
+        _ => {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(lower), None) => lower,
+                _ => c,
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn folds_case_variants_together() {
+        assert_eq!(fold("strasse"), fold("Straße"));
+        assert_eq!(fold("STRASSE"), fold("STRAẞE"));
+        assert_eq!(fold("ΣΊΣΥΦΟΣ"), fold("σίσυφος"));
+        assert_eq!("i\u{307}stanbul", fold("İSTANBUL"));
+        assert_ne!(fold("ı"), fold("I"));
+        assert_eq!('ß', simple_fold('ẞ'));
+        assert_eq!("नमस्ते", fold("नमस्ते"));
+    }
+}
@@ -0,0 +1,134 @@
+// Highlighting with ANSI colors
+//
+// The colors can be changed with the MINIGREP_COLORS environment variable, which works like grep's
+// GREP_COLORS: a ':' separated list of capability=SGR pairs, e.g. "ms=01;32:fn=34". Capabilities:
+//   ms  matching text (mt sets it too, for grep's sake)
+//   fn  file names
+//   ln  line numbers
+//   cn  columns
+//   se  separators: the ':' and '-' after the fields, and the "--" between groups of lines
+// An empty SGR leaves that part uncolored. Anything not understood is skipped.
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+
+pub const ENV: &str = "MINIGREP_COLORS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorChoice {
+    #[default]
+    Auto, // when writing to a terminal
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colors {
+    pub matched: String,
+    pub file_name: String,
+    pub line_number: String,
+    pub column: String,
+    pub separator: String,
+}
+
+// grep's
+impl Default for Colors {
+    fn default() -> Colors {
+        Colors {
+            matched: "01;31".to_string(),
+            file_name: "35".to_string(),
+            line_number: "32".to_string(),
+            column: "32".to_string(),
+            separator: "36".to_string(),
+        }
+    }
+}
+
+impl Colors {
+    // No colors at all
+    pub fn plain() -> Colors {
+        Colors {
+            matched: String::new(),
+            file_name: String::new(),
+            line_number: String::new(),
+            column: String::new(),
+            separator: String::new(),
+        }
+    }
+
+    // The default colors, changed as the spec says
+    pub fn parse(spec: &str) -> Colors {
+        let mut colors = Colors::default();
+
+        for pair in spec.split(':') {
+            let (name, sgr) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => continue,
+            };
+            if !sgr.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                continue; // Anything else could mess up the terminal
+            }
+
+            let color = match name {
+                "ms" | "mt" => &mut colors.matched,
+                "fn" => &mut colors.file_name,
+                "ln" => &mut colors.line_number,
+                "cn" => &mut colors.column,
+                "se" => &mut colors.separator,
+                _ => continue,
+            };
+            *color = sgr.to_string();
+        }
+        colors
+    }
+
+    // The colors to use for standard output: those of MINIGREP_COLORS if colors are wanted there
+    pub fn for_stdout(choice: ColorChoice) -> Colors {
+        let wanted = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stdout().is_terminal() && env::var("TERM").map_or(true, |term| term != "dumb"),
+        };
+
+        match env::var(ENV) {
+            _ if !wanted => Colors::plain(),
+            Ok(spec) => Colors::parse(&spec),
+            Err(_) => Colors::default(),
+        }
+    }
+}
+
+// Writes the text in the given color
+pub fn paint<W: Write>(out: &mut W, sgr: &str, text: &[u8]) -> io::Result<()> {
+    if sgr.is_empty() {
+        return out.write_all(text);
+    }
+
+    write!(out, "\x1b[{}m", sgr)?;
+    out.write_all(text)?;
+    out.write_all(b"\x1b[0m")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_like_grep_colors() {
+        let colors = Colors::parse("mt=01;32:fn=:ln=34:bogus=1:se=5m:cn");
+        assert_eq!(Colors {
+            matched: "01;32".to_string(),
+            file_name: String::new(),
+            line_number: "34".to_string(),
+            ..Colors::default()
+        }, colors);
+    }
+
+    #[test]
+    fn paints() {
+        let mut out = Vec::new();
+        paint(&mut out, "01;31", b"frog").unwrap();
+        paint(&mut out, "", b"bog").unwrap();
+        assert_eq!(&b"\x1b[01;31mfrog\x1b[0mbog"[..], &out[..]);
+    }
+}
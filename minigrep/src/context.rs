@@ -0,0 +1,117 @@
+// Context lines around matches (-A, -B and -C)
+//
+// Lines are fed in one at a time, and only the last few are kept for when a match comes and wants
+// the lines before it, so a file never has to be held whole. Windows that overlap or touch are
+// merged, and groups of lines that aren't next to each other are told apart by a "--" line.
+// Lines are bytes, as read, since they needn't be valid UTF-8.
+
+use std::collections::VecDeque;
+use std::io;
+
+// What to print, in order
+#[derive(Debug, PartialEq)]
+pub enum Line<'a> {
+    Match(usize, &'a [u8]), // with its number, counting from 1
+    Context(usize, &'a [u8]),
+    Separator,
+}
+
+pub struct Context {
+    before: usize,
+    after: usize,
+    buffer: VecDeque<(usize, Vec<u8>)>, // up to `before` lines not printed yet
+    after_left: usize,
+    last: Option<usize>, // the number of the last line printed from this file
+    printed: bool, // anything, from any file
+}
+
+impl Context {
+    pub fn new(before: usize, after: usize) -> Context {
+        Context { before, after, buffer: VecDeque::new(), after_left: 0, last: None, printed: false }
+    }
+
+    // Before the first line of the next file, whose groups are never next to this one's
+    pub fn next_file(&mut self) {
+        self.buffer.clear();
+        self.after_left = 0;
+        self.last = None;
+    }
+
+    // Whether lines after a match are still to be printed
+    pub fn pending(&self) -> bool {
+        self.after_left > 0
+    }
+
+    // Passes what's to be printed on to emit, stopping at its first error
+    pub fn line<F>(&mut self, number: usize, text: &[u8], matched: bool, mut emit: F) -> io::Result<()>
+        where F: FnMut(Line) -> io::Result<()>
+    {
+        if matched {
+            let first = self.buffer.front().map_or(number, |(n, _)| *n);
+            let adjacent = self.last.is_some_and(|last| last + 1 == first);
+            if self.printed && !adjacent && (self.before > 0 || self.after > 0) {
+                emit(Line::Separator)?;
+            }
+
+            for (n, line) in self.buffer.drain(..) {
+                emit(Line::Context(n, &line))?;
+            }
+            emit(Line::Match(number, text))?;
+            self.after_left = self.after;
+        } else if self.after_left > 0 {
+            emit(Line::Context(number, text))?;
+            self.after_left -= 1;
+        } else {
+            if self.before > 0 {
+                if self.buffer.len() == self.before {
+                    self.buffer.pop_front();
+                }
+                self.buffer.push_back((number, text.to_vec()));
+            }
+            return Ok(());
+        }
+
+        self.last = Some(number);
+        self.printed = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The lines printed for the given lines, with the matching ones marked ':' and context '-'
+    fn show(context: &mut Context, lines: &[&str]) -> Vec<String> {
+        let mut shown = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            context.line(i + 1, line.as_bytes(), line.contains('!'), |line| {
+                shown.push(match line {
+                    Line::Match(n, text) => format!("{}:{}", n, String::from_utf8_lossy(text)),
+                    Line::Context(n, text) => format!("{}-{}", n, String::from_utf8_lossy(text)),
+                    Line::Separator => "--".to_string(),
+                });
+                Ok(())
+            }).unwrap();
+        }
+        shown
+    }
+
+    #[test]
+    fn merges_windows() {
+        let lines = ["a", "b!", "c", "d", "e", "f!", "g", "h!", "i", "j", "k"];
+
+        assert_eq!(vec!["2:b!", "6:f!", "8:h!"], show(&mut Context::new(0, 0), &lines));
+        assert_eq!(vec!["1-a", "2:b!", "3-c", "--", "5-e", "6:f!", "7-g", "8:h!", "9-i"], show(&mut Context::new(1, 1), &lines));
+        assert_eq!(vec!["2:b!", "3-c", "4-d", "5-e", "6:f!", "7-g", "8:h!", "9-i", "10-j", "11-k"], show(&mut Context::new(0, 3), &lines));
+    }
+
+    #[test]
+    fn separates_files() {
+        let mut context = Context::new(1, 0);
+        assert_eq!(vec!["1:a!"], show(&mut context, &["a!", "b"]));
+
+        context.next_file();
+        assert_eq!(vec!["--", "1:c!"], show(&mut context, &["c!"]));
+    }
+}
@@ -0,0 +1,200 @@
+// Shell-style wildcards for picking files by name
+//
+//   *      any run of characters but '/'
+//   ?      any single character but '/'
+//   [a-z]  a character from a class, negated with [!a-z] or [^a-z]
+//   **/    any number of directories, including none, so **/*.rs matches main.rs and src/main.rs
+//   **     anywhere else, anything at all, so src/** matches everything under src
+//   \*     a '*' (or whatever other character follows the backslash)
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Dirs, // **/
+    Everything, // **
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    // Anything is a valid glob: a '[' without its ']' is just a '['
+    pub fn new(pattern: &str) -> Glob {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let token = match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    i += 1;
+                    if chars.get(i + 1) == Some(&'/') {
+                        i += 1;
+                        Token::Dirs
+                    } else {
+                        Token::Everything
+                    }
+                },
+                '*' => Token::Star,
+                '?' => Token::Any,
+                '[' => match class(&chars, i) {
+                    Some((class, end)) => {
+                        i = end;
+                        class
+                    },
+                    None => Token::Char('['),
+                },
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    Token::Char(chars[i])
+                },
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+
+        Glob { pattern: pattern.to_string(), tokens }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // Whether the glob is about paths rather than just names
+    pub fn has_slash(&self) -> bool {
+        self.tokens.iter().any(|token| *token == Token::Char('/') || *token == Token::Dirs)
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let mut failed = vec![false; (self.tokens.len() + 1) * (text.len() + 1)];
+        matches(&self.tokens, &text, 0, 0, &mut failed)
+    }
+}
+
+// The class starting with the '[' at chars[start], and the position of its ']'
+fn class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = i < chars.len() && (chars[i] == '!' || chars[i] == '^');
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let first = i;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && i > first { // a ']' right after the '[' is one of the characters
+            return Some((Token::Class { negated, ranges }, i));
+        }
+        if i + 2 < chars.len() && chars[i + 1] == '-' && chars[i + 2] != ']' {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+
+    None
+}
+
+// Whether tokens[t..] match text[i..]. Wildcards try every way of splitting what's left between
+// them, which with several of them tries the same (t, i) over and over, so those that failed are
+// remembered and the work stays polynomial however many stars there are
+fn matches(tokens: &[Token], text: &[char], t: usize, i: usize, failed: &mut [bool]) -> bool {
+    let key = t * (text.len() + 1) + i;
+    if failed[key] {
+        return false;
+    }
+
+    let matched = match tokens.get(t) {
+        None => i == text.len(),
+        Some(&Token::Star) => {
+            // Try the shortest runs first, never going past a '/'
+            let mut matched = false;
+            for j in i..=text.len() {
+                if matches(tokens, text, t + 1, j, failed) {
+                    matched = true;
+                    break;
+                }
+                if j < text.len() && text[j] == '/' {
+                    break;
+                }
+            }
+            matched
+        },
+        Some(&Token::Everything) => (i..=text.len()).any(|j| matches(tokens, text, t + 1, j, failed)),
+        Some(&Token::Dirs) => {
+            // Either no directories at all or everything up to one of the following slashes
+            matches(tokens, text, t + 1, i, failed)
+                || (i..text.len()).any(|j| text[j] == '/' && matches(tokens, text, t + 1, j + 1, failed))
+        },
+        Some(token) => i < text.len() && matches_char(token, text[i]) && matches(tokens, text, t + 1, i + 1, failed),
+    };
+
+    if !matched {
+        failed[key] = true;
+    }
+    matched
+}
+
+fn matches_char(token: &Token, c: char) -> bool {
+    match *token {
+        Token::Char(expected) => c == expected,
+        Token::Any => c != '/',
+        Token::Class { negated, ref ranges } => {
+            c != '/' && ranges.iter().any(|&(low, high)| low <= c && c <= high) != negated
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(Glob::new("*.rs").is_match("main.rs"));
+        assert!(!Glob::new("*.rs").is_match("src/main.rs"));
+        assert!(!Glob::new("*.rs").is_match("main.rsx"));
+        assert!(Glob::new("poem.tx?").is_match("poem.txt"));
+        assert!(Glob::new("[a-c]at.[!o]*").is_match("bat.txt"));
+        assert!(!Glob::new("[a-c]at.[!o]*").is_match("cat.orig"));
+        assert!(Glob::new("[]x]").is_match("]"));
+        assert!(Glob::new("file[").is_match("file["));
+        assert!(Glob::new(r"\*.md").is_match("*.md"));
+        assert!(!Glob::new(r"\*.md").is_match("README.md"));
+    }
+
+    #[test]
+    fn directories() {
+        let glob = Glob::new("**/*.rs");
+        assert!(glob.has_slash());
+        assert!(glob.is_match("main.rs"));
+        assert!(glob.is_match("src/bin/main.rs"));
+        assert!(Glob::new("src/**/test.rs").is_match("src/test.rs"));
+        assert!(Glob::new("src/**/test.rs").is_match("src/a/b/test.rs"));
+        assert!(!Glob::new("src/**/test.rs").is_match("src/a/btest.rs"));
+        assert!(Glob::new("target/**").is_match("target/debug/minigrep"));
+        assert!(!Glob::new("*.txt").has_slash());
+    }
+
+    #[test]
+    fn many_wildcards() {
+        // Each of these used to try every way of splitting the name between the stars
+        let name = "a".repeat(62);
+        assert!(!Glob::new("*a*a*a*a*a*a*a*a*a*b").is_match(&name));
+        assert!(!Glob::new("**a**a**a**a**a**a**a**a**a**b").is_match(&name));
+        assert!(!Glob::new("**/**/**/**/**/**/**/**/*b").is_match(&"a/".repeat(31)));
+        assert!(Glob::new("*a*a*a*a*a*a*a*a*a*").is_match(&name));
+    }
+}
@@ -0,0 +1,169 @@
+// Ignore files, so that searching a repository skips what it declares as not its own
+//
+// Each directory can have a .gitignore, and a .minigrepignore for what only minigrep should skip,
+// which wins over the .gitignore. Both follow the .gitignore rules:
+//   blank lines and lines starting with '#' are skipped (use \# for a pattern starting with '#')
+//   !pattern brings back what an earlier pattern ignored
+//   a pattern ending with '/' only matches directories
+//   a pattern with a '/' at its start or middle is relative to the ignore file's directory,
+//   the others match at any depth
+//   *, ?, [...] and ** as in glob.rs
+// Ignore files deeper down the tree win over those above them.
+
+use std::borrow::Borrow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glob::Glob;
+
+pub const FILES: [&str; 2] = [".gitignore", ".minigrepignore"]; // the later the file, the more it counts
+
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+}
+
+// The rules of the ignore files found in a directory
+#[derive(Debug)]
+pub struct Ignore {
+    dir: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    pub fn parse(dir: &Path, text: &str) -> Ignore {
+        let mut ignore = Ignore { dir: dir.to_path_buf(), rules: Vec::new() };
+        ignore.add(text);
+        ignore
+    }
+
+    // The directory's ignore files, or None when it has none
+    pub fn from_dir(dir: &Path) -> io::Result<Option<Ignore>> {
+        let mut ignore = Ignore { dir: dir.to_path_buf(), rules: Vec::new() };
+
+        for file in &FILES {
+            match fs::read_to_string(dir.join(file)) {
+                Ok(text) => ignore.add(&text),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(if ignore.rules.is_empty() { None } else { Some(ignore) })
+    }
+
+    fn add(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut pattern = trim_end(line);
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+
+            let negated = pattern.starts_with('!');
+            if negated {
+                pattern = &pattern[1..];
+            }
+            let dir_only = pattern.ends_with('/');
+            if dir_only {
+                pattern = &pattern[..pattern.len() - 1];
+            }
+            if pattern.is_empty() {
+                continue;
+            }
+
+            // A leading \# or \! is left to the glob, which takes it as a plain '#' or '!'
+            let glob = if pattern.contains('/') {
+                Glob::new(pattern.trim_start_matches('/')) // Anchored to the ignore file's directory
+            } else {
+                Glob::new(&format!("**/{}", pattern))
+            };
+            self.rules.push(Rule { glob, negated, dir_only });
+        }
+    }
+
+    // Some(true) when the last rule matching the path ignores it, Some(false) when it brings it back
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+        let relative: Vec<_> = relative.components().map(|part| part.as_os_str().to_string_lossy()).collect();
+        let relative = relative.join("/");
+
+        self.rules.iter()
+                  .rev()
+                  .find(|rule| (is_dir || !rule.dir_only) && rule.glob.is_match(&relative))
+                  .map(|rule| !rule.negated)
+    }
+}
+
+// Whether the path is ignored, going by the deepest ignore file with something to say about it
+pub fn is_ignored<I: Borrow<Ignore>>(ignores: &[I], path: &Path, is_dir: bool) -> bool {
+    ignores.iter()
+           .rev()
+           .filter_map(|ignore| ignore.borrow().matched(path, is_dir))
+           .next()
+           .unwrap_or(false)
+}
+
+// Trailing spaces don't count, unless escaped with a backslash
+fn trim_end(line: &str) -> &str {
+    let trimmed = line.trim_end_matches([' ', '\t', '\r']);
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gitignore_rules() {
+        let root = Path::new("repo");
+        let ignore = Ignore::parse(root, "\
+# Build output
+target/
+*.log
+!keep.log
+/TODO
+doc/**/*.html
+\\#notes
+");
+
+        let ignored = |path: &str, is_dir: bool| is_ignored(&[&ignore], &root.join(path), is_dir);
+        assert!(ignored("target", true));
+        assert!(ignored("sub/target", true));
+        assert!(!ignored("target", false)); // only directories
+        assert!(ignored("debug.log", false));
+        assert!(ignored("sub/dir/debug.log", false));
+        assert!(!ignored("keep.log", false));
+        assert!(ignored("TODO", false));
+        assert!(!ignored("src/TODO", false)); // anchored
+        assert!(ignored("doc/index.html", false));
+        assert!(ignored("doc/api/fn.html", false));
+        assert!(!ignored("index.html", false));
+        assert!(ignored("#notes", false));
+        assert!(!ignored("Build output", false));
+    }
+
+    #[test]
+    fn deeper_files_win() {
+        let top = Ignore::parse(Path::new("repo"), "*.txt\n");
+        let sub = Ignore::parse(Path::new("repo/docs"), "!*.txt\n");
+
+        assert!(is_ignored(&[&top, &sub], Path::new("repo/notes.txt"), false));
+        assert!(!is_ignored(&[&top, &sub], Path::new("repo/docs/notes.txt"), false));
+    }
+
+    #[test]
+    fn hostile_patterns_finish() {
+        // Ignore files come with the tree being searched, so one can't be allowed to hang the search
+        let ignore = Ignore::parse(Path::new("repo"), "*a*a*a*a*a*a*a*a*a*a*a*b\n**a**a**a**a**a**a**a**a**a**b\n");
+        let name = "a".repeat(62);
+        assert!(!is_ignored(&[&ignore], &Path::new("repo/src").join(&name), false));
+        assert!(!is_ignored(&[&ignore], &Path::new("repo").join(format!("{}/{}", name, name)), true));
+    }
+}
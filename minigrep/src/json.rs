@@ -0,0 +1,133 @@
+// JSON Lines output (--json), for tools rather than people: one object per line for each event
+//
+//   {"type":"begin","data":{"path":{"text":"poem.txt"}}}
+//   {"type":"match","data":{"path":{"text":"poem.txt"},"lines":{"text":"Are you nobody, too?"},"line_number":2,
+//       "submatches":[{"match":{"text":"nobody"},"start":8,"end":14}]}}
+//   {"type":"context","data":{...the same, with no submatches}}
+//   {"type":"end","data":{"path":{"text":"poem.txt"},"stats":{"matched_lines":2,"matches":2,"bytes_searched":230}}}
+//   {"type":"summary","data":{"stats":{"searches":1,"searches_with_match":1,"matched_lines":2,"matches":2,"bytes_searched":230}}}
+//
+// A file only gets its begin and end when it has something to show in between. Lines come without
+// their line ending, and submatches are byte offsets into them. Text that isn't valid UTF-8 comes
+// as {"bytes":"<base64>"} instead of {"text":"..."}.
+
+use std::fmt::Write;
+use std::str;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub searches: usize,
+    pub searches_with_match: usize,
+    pub matched_lines: usize,
+    pub matches: usize,
+    pub bytes_searched: usize,
+}
+
+impl Stats {
+    // Adds up a file's stats into the totals
+    pub fn add(&mut self, file: &Stats) {
+        self.searches += 1;
+        if file.matched_lines > 0 {
+            self.searches_with_match += 1;
+        }
+        self.matched_lines += file.matched_lines;
+        self.matches += file.matches;
+        self.bytes_searched += file.bytes_searched;
+    }
+}
+
+// Paths are the bytes the OS has for them, so one that isn't UTF-8 still names its file
+pub fn begin(path: &[u8]) -> String {
+    format!(r#"{{"type":"begin","data":{{"path":{}}}}}"#, data(path))
+}
+
+// A "match" or "context" line
+pub fn line(kind: &str, path: &[u8], number: usize, text: &[u8], submatches: &[(usize, usize)]) -> String {
+    let submatches: Vec<String> = submatches.iter()
+                                            .map(|&(start, end)| format!(r#"{{"match":{},"start":{},"end":{}}}"#, data(&text[start..end]), start, end))
+                                            .collect();
+
+    format!(r#"{{"type":"{}","data":{{"path":{},"lines":{},"line_number":{},"submatches":[{}]}}}}"#,
+            kind, data(path), data(text), number, submatches.join(","))
+}
+
+pub fn end(path: &[u8], stats: &Stats) -> String {
+    format!(r#"{{"type":"end","data":{{"path":{},"stats":{{"matched_lines":{},"matches":{},"bytes_searched":{}}}}}}}"#,
+            data(path), stats.matched_lines, stats.matches, stats.bytes_searched)
+}
+
+pub fn summary(stats: &Stats) -> String {
+    format!(r#"{{"type":"summary","data":{{"stats":{{"searches":{},"searches_with_match":{},"matched_lines":{},"matches":{},"bytes_searched":{}}}}}}}"#,
+            stats.searches, stats.searches_with_match, stats.matched_lines, stats.matches, stats.bytes_searched)
+}
+
+// {"text":"..."} for UTF-8, {"bytes":"..."} for anything else
+pub fn data(bytes: &[u8]) -> String {
+    match str::from_utf8(bytes) {
+        Ok(text) => format!(r#"{{"text":{}}}"#, string(text)),
+        Err(_) => format!(r#"{{"bytes":"{}"}}"#, base64(bytes)),
+    }
+}
+
+pub fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Standard base64, padded
+pub fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encodes() {
+        assert_eq!(r#""say \"hi\"\\n\n\u0007""#, string("say \"hi\"\\n\n\u{7}"));
+        assert_eq!(vec!["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg=="], ["", "f", "fo", "foo", "foob"].iter().map(|s| base64(s.as_bytes())).collect::<Vec<_>>());
+        assert_eq!(r#"{"text":"café"}"#, data("café".as_bytes()));
+        assert_eq!(r#"{"bytes":"Y2Fm6Q=="}"#, data(b"caf\xe9"));
+    }
+
+    #[test]
+    fn events() {
+        assert_eq!(r#"{"type":"match","data":{"path":{"text":"poem.txt"},"lines":{"text":"a frog"},"line_number":7,"submatches":[{"match":{"text":"frog"},"start":2,"end":6}]}}"#,
+                   line("match", b"poem.txt", 7, b"a frog", &[(2, 6)]));
+        assert_eq!(r#"{"type":"begin","data":{"path":{"bytes":"Y2Fm6S50eHQ="}}}"#, begin(b"caf\xe9.txt"));
+
+        let mut total = Stats::default();
+        total.add(&Stats { matched_lines: 2, matches: 3, bytes_searched: 10, ..Stats::default() });
+        total.add(&Stats { bytes_searched: 5, ..Stats::default() });
+        assert_eq!(r#"{"type":"summary","data":{"stats":{"searches":2,"searches_with_match":1,"matched_lines":2,"matches":3,"bytes_searched":15}}}"#,
+                   summary(&total));
+    }
+}
@@ -1,51 +1,452 @@
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
+use std::io::BufReader;
+use std::iter;
+use std::slice;
 use std::error::Error;
-use std::env;
+use std::path::Path;
 
+pub mod args;
+pub mod case;
+pub mod color;
+pub mod context;
+pub mod glob;
+pub mod ignore;
+pub mod json;
+pub mod regex;
+pub mod walk;
+
+#[cfg(test)]
+mod temp;
+
+use args::ArgsError;
+use color::{paint, ColorChoice, Colors};
+use context::{Context, Line};
+use json::Stats;
+use regex::Regex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Output {
+    #[default]
+    Lines,
+    Count, // -c, how many lines matched in each file
+    FilesWithMatches, // -l
+    FilesWithoutMatch, // -L
+}
+
+// What --column counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    Chars,
+    Bytes,
+}
+
+#[derive(Default)]
 pub struct Config {
     pub query: String,
-    pub filename: String,
+    pub paths: Vec<String>, // files, and directories to search recursively; none is the standard input
     pub case_sensitive: bool,
+    pub regex: bool, // the query is a regular expression rather than plain text
+    pub invert: bool, // pick the lines that don't match
+    pub before: usize, // lines of context
+    pub after: usize,
+    pub output: Output,
+    pub line_numbers: bool,
+    pub column: Option<Column>, // where the first match starts, counting from 1
+    pub only_matching: bool, // just the matching parts of the lines, each on a line of its own
+    pub max_count: Option<usize>, // stop reading a file after this many matching lines
+    pub color: ColorChoice,
+    pub json: bool, // JSON Lines instead of text, see json.rs
+    pub walk: walk::Options,
 }
 
 impl Config {
-    pub fn new(mut args: env::Args) -> Result<Config, &'static str> { // string literals have type &'static str
-        
-        args.next(); // Skip the executable
+    pub fn new<I: IntoIterator<Item = String>>(args: I) -> Result<Config, ArgsError> {
+        args::parse(args.into_iter().skip(1)) // Skip the executable
+    }
+}
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+// How lines are picked, decided once for all the files
+enum Matcher {
+    Text(String),
+    CaseInsensitive(String), // folded
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn is_match(&self, line: &str) -> bool {
+        match *self {
+            Matcher::Text(ref query) => line.contains(query.as_str()),
+            Matcher::CaseInsensitive(ref folded) => case::fold(line).contains(folded.as_str()),
+            Matcher::Regex(ref regex) => regex.is_match(line),
+        }
+    }
+
+    // Where the matches are in the line, as byte offsets
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        match *self {
+            Matcher::Text(ref query) => line.match_indices(query.as_str()).map(|(i, m)| (i, i + m.len())).collect(),
+            Matcher::CaseInsensitive(ref folded) => {
+                // Found in the folded line, then mapped back to the characters they were folded from
+                let mut folding = String::new();
+                let mut origins = Vec::new(); // for each byte of the folded line, where its character starts
+                for (i, c) in line.char_indices() {
+                    for f in case::fold_char(c) {
+                        folding.push(f);
+                        origins.extend(iter::repeat_n(i, f.len_utf8()));
+                    }
+                }
+
+                // Characters that fold to several, like 'ß' to "ss", can hold more than one match, but
+                // are only highlighted once: matches starting inside what's already taken are dropped
+                let end_of = |i: usize| i + line[i..].chars().next().map_or(0, char::len_utf8);
+                let mut taken = 0;
+                folding.match_indices(folded.as_str())
+                       .map(|(i, m)| match m.len() {
+                           0 => {
+                               let at = origins.get(i).cloned().unwrap_or(line.len());
+                               (at, at)
+                           },
+                           len => (origins[i], end_of(origins[i + len - 1])),
+                       })
+                       .filter(|&(start, end)| {
+                           let fresh = start >= taken;
+                           if fresh {
+                               taken = end;
+                           }
+                           fresh
+                       })
+                       .collect()
+            },
+            Matcher::Regex(ref regex) => regex.find_iter(line).map(|m| (m.start, m.end)).collect(),
+        }
+    }
+}
+
+// What stopped searching an input: not being able to read it only stops that input, but not being
+// able to write the results stops everything
+enum Failure {
+    Read(io::Error),
+    Write(io::Error),
+}
+
+pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let colors = Colors::for_stdout(config.color);
+    let mut searcher = Searcher::new(&config, colors, io::stdout().lock())?;
+
+    let stdin = ["-".to_string()];
+    let paths = if config.paths.is_empty() { &stdin[..] } else { &config.paths[..] };
+
+    match searcher.paths(paths, &config.walk) {
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()), // Whoever was reading has seen enough, e.g. head
+        Err(e) => Err(Box::new(e)),
+        Ok(()) => Ok(()),
+    }
+}
+
+struct Searcher<W: Write> {
+    matcher: Matcher,
+    context: Context,
+    with_path: bool,
+    invert: bool,
+    output: Output,
+    line_numbers: bool,
+    column: Option<Column>,
+    only_matching: bool,
+    max_count: Option<usize>,
+    colors: Colors,
+    json: bool,
+    begun: bool, // whether the JSON begin event for the current file is out
+    file: Stats, // the current file's
+    total: Stats,
+    out: W,
+}
+
+impl<W: Write> Searcher<W> {
+    fn new(config: &Config, colors: Colors, out: W) -> Result<Searcher<W>, regex::RegexError> {
+        let matcher = if config.regex {
+            let pattern = if config.case_sensitive { config.query.clone() } else { format!("(?i){}", config.query) };
+            Matcher::Regex(Regex::new(&pattern)?) // Compiled once for all the files
+        } else if config.case_sensitive {
+            Matcher::Text(config.query.clone())
+        } else {
+            Matcher::CaseInsensitive(case::fold(&config.query))
         };
 
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file name"),
+        // JSON has all there is to know about every line, so the other ways of printing them go
+        let (output, only_matching) = if config.json { (Output::Lines, false) } else { (config.output, config.only_matching) };
+
+        // Context only goes with whole lines
+        let context = if output == Output::Lines && !only_matching {
+            Context::new(config.before, config.after)
+        } else {
+            Context::new(0, 0)
+        };
+
+        Ok(Searcher {
+            matcher,
+            context,
+            // Like grep, lines only say which file they're from when there can be more than one
+            with_path: config.paths.len() > 1 || config.paths.first().is_some_and(|path| Path::new(path).is_dir()),
+            invert: config.invert,
+            output,
+            line_numbers: config.line_numbers,
+            column: config.column,
+            only_matching,
+            max_count: config.max_count,
+            colors: if config.json { Colors::plain() } else { colors },
+            json: config.json,
+            begun: false,
+            file: Stats::default(),
+            total: Stats::default(),
+            out,
+        })
+    }
+
+    // '-' is the standard input, so that minigrep can be used in pipelines. Inputs that can't be
+    // read are reported and the rest searched; only failing to write stops the search
+    fn paths(&mut self, paths: &[String], options: &walk::Options) -> io::Result<()> {
+        for path in paths {
+            if path == "-" {
+                let stdin = io::stdin();
+                report(self.input("(standard input)", b"(standard input)", stdin.lock()))?;
+                continue;
+            }
+
+            for file in walk::walk(slice::from_ref(path), options) {
+                let searched = match file {
+                    Ok(file) => {
+                        let name = file.display().to_string();
+                        match File::open(&file) {
+                            Ok(f) => self.input(&name, &raw_path(&file), BufReader::new(f)),
+                            Err(e) => Err(Failure::Read(named(&name, e))),
+                        }
+                    },
+                    Err(e) => Err(Failure::Read(e)),
+                };
+                report(searched)?;
+            }
+        }
+
+        if self.json {
+            writeln!(self.out, "{}", json::summary(&self.total))?;
+        }
+        Ok(())
+    }
+
+    // Searches a line at a time, writing the matching lines as they're found along with their
+    // context. A single buffer is reused for all the lines, so memory only grows with the longest
+    // line. Lines that aren't valid UTF-8 are matched as if the invalid bytes were U+FFFD, but
+    // written as they were.
+    // The name is for people, the path for JSON, which can carry the bytes of paths that aren't UTF-8
+    fn input<R: BufRead>(&mut self, name: &str, path: &[u8], mut reader: R) -> Result<(), Failure> {
+        let prefix = if self.with_path { Some(name) } else { None };
+        let mut line = Vec::new();
+        let (mut number, mut count) = (0, 0);
+        self.context.next_file();
+        self.file = Stats::default();
+        self.begun = false;
+
+        loop {
+            let done = self.max_count.is_some_and(|max| count >= max);
+            if done && !self.context.pending() {
+                break; // Only reading on for the context after the last match
+            }
+
+            line.clear();
+            if reader.read_until(b'\n', &mut line).map_err(|e| Failure::Read(named(name, e)))? == 0 {
+                break;
+            }
+            number += 1;
+            self.file.bytes_searched += line.len();
+
+            let text = trim_newline(&line);
+            let lossy = String::from_utf8_lossy(text); // Only copies invalid lines
+            let matched = !done && self.matcher.is_match(&lossy) != self.invert;
+            if matched {
+                count += 1;
+            }
+
+            match self.output {
+                Output::Lines => self.lines(name, path, number, text, &lossy, matched).map_err(Failure::Write)?,
+                Output::Count => {},
+                Output::FilesWithMatches | Output::FilesWithoutMatch => if matched {
+                    break; // That's all there was to find out
+                },
+            }
+        }
+
+        self.file.matched_lines = count;
+        self.total.add(&self.file);
+
+        let (out, colors) = (&mut self.out, &self.colors);
+        match self.output {
+            Output::Lines if self.json && self.begun => writeln!(out, "{}", json::end(path, &self.file)),
+            Output::Lines => Ok(()),
+            Output::Count => print(out, colors, &Fields { name: prefix, marker: ":", ..Fields::default() }, count.to_string().as_bytes()),
+            Output::FilesWithMatches if count > 0 => paint(out, &colors.file_name, name.as_bytes()).and_then(|_| writeln!(out)),
+            Output::FilesWithoutMatch if count == 0 => paint(out, &colors.file_name, name.as_bytes()).and_then(|_| writeln!(out)),
+            _ => Ok(()),
+        }.map_err(Failure::Write)
+    }
+
+    // Writes what the line brings in Output::Lines
+    fn lines(&mut self, name: &str, path: &[u8], number: usize, text: &[u8], lossy: &str, matched: bool) -> io::Result<()> {
+        let prefix = if self.with_path { Some(name) } else { None };
+        let line_number = if self.line_numbers { Some(number) } else { None };
+        let counting = self.column;
+        let column = |start: usize| match counting {
+            Some(Column::Chars) => Some(lossy[..start].chars().count() + 1),
+            Some(Column::Bytes) => Some(start + 1),
+            None => None,
         };
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err(); // We don't care about the value, just if it's set
 
-        Ok(Config { query, filename, case_sensitive })
+        if self.only_matching {
+            if !matched || self.invert {
+                return Ok(()); // Inverted matches are lines that have no matching parts to show
+            }
+            for (start, end) in self.matcher.find_iter(lossy).into_iter().filter(|(start, end)| start < end) {
+                let whole = [(0, end - start)];
+                let fields = Fields { name: prefix, line_number, column: column(start), marker: ":", highlights: &whole };
+                print(&mut self.out, &self.colors, &fields, &lossy.as_bytes()[start..end])?;
+            }
+            return Ok(());
+        }
+
+        let wanted = !self.colors.matched.is_empty() || self.column.is_some() || self.json;
+        let found = if matched && !self.invert && wanted { self.matcher.find_iter(lossy) } else { Vec::new() };
+        let match_column = found.first().and_then(|&(start, _)| column(start));
+        let in_bytes: Vec<_> = found.iter().map(|&(start, end)| (byte_offset(text, lossy, start), byte_offset(text, lossy, end))).collect();
+        self.file.matches += found.len();
+
+        if self.json {
+            let (out, begun) = (&mut self.out, &mut self.begun);
+            return self.context.line(number, text, matched, |line| {
+                let (kind, n, text, submatches) = match line {
+                    Line::Match(n, text) => ("match", n, text, &in_bytes[..]),
+                    Line::Context(n, text) => ("context", n, text, &[][..]),
+                    Line::Separator => return Ok(()),
+                };
+                if !*begun {
+                    writeln!(out, "{}", json::begin(path))?;
+                    *begun = true;
+                }
+                writeln!(out, "{}", json::line(kind, path, n, text, submatches))
+            });
+        }
+
+        let highlights = &in_bytes[..];
+        let (out, colors) = (&mut self.out, &self.colors);
+
+        self.context.line(number, text, matched, |line| match line {
+            Line::Match(n, text) => {
+                let fields = Fields { name: prefix, line_number: line_number.map(|_| n), column: match_column, marker: ":", highlights };
+                print(out, colors, &fields, text)
+            },
+            Line::Context(n, text) => {
+                let fields = Fields { name: prefix, line_number: line_number.map(|_| n), marker: "-", ..Fields::default() };
+                print(out, colors, &fields, text)
+            },
+            Line::Separator => paint(out, &colors.separator, b"--").and_then(|_| writeln!(out)),
+        })
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<Error>> {
-    let mut f = File::open(config.filename)?; // Return error if any
+// What goes before a line, each followed by the marker: ':' for matching lines and '-' for context,
+// like grep does. And which parts of the line to highlight as matches
+#[derive(Default)]
+struct Fields<'a> {
+    name: Option<&'a str>,
+    line_number: Option<usize>,
+    column: Option<usize>,
+    marker: &'a str,
+    highlights: &'a [(usize, usize)],
+}
 
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?; // Return error if any
+fn print<W: Write>(out: &mut W, colors: &Colors, fields: &Fields, text: &[u8]) -> io::Result<()> {
+    let numbers = fields.line_number.map(|n| (&colors.line_number, n)).into_iter()
+                        .chain(fields.column.map(|n| (&colors.column, n)));
+    let prefix = fields.name.map(|name| (&colors.file_name, name.to_string())).into_iter()
+                       .chain(numbers.map(|(color, n)| (color, n.to_string())));
+    for (color, field) in prefix {
+        paint(out, color, field.as_bytes())?;
+        paint(out, &colors.separator, fields.marker.as_bytes())?;
+    }
+
+    // Whatever of a highlight overlaps the one before has already been painted
+    let mut at = 0;
+    for &(start, end) in fields.highlights.iter() {
+        let start = start.max(at);
+        if start >= end {
+            continue;
+        }
+        out.write_all(&text[at..start])?;
+        paint(out, &colors.matched, &text[start..end])?;
+        at = end;
+    }
+    out.write_all(&text[at..])?;
+    out.write_all(b"\n")
+}
+
+// Reports a failure to read and carries on, giving back only the failures that stop everything
+fn report(searched: Result<(), Failure>) -> io::Result<()> {
+    match searched {
+        Ok(()) => Ok(()),
+        Err(Failure::Read(e)) => {
+            eprintln!("minigrep: {}", e);
+            Ok(())
+        },
+        Err(Failure::Write(e)) => Err(e),
+    }
+}
+
+// The bytes the OS has for the path, which only unix lets us at; elsewhere paths are Unicode anyway
+#[cfg(unix)]
+fn raw_path(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
 
-    let results = if config.case_sensitive {
-        search(&config.query, &contents) // No ';' since we need an expression to assign to results
-    } else {
-        search_case_insensitive(&config.query, &contents)
-    }; // Notice the ';' here since it is the end of the let statement
+#[cfg(not(unix))]
+fn raw_path(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
 
-    for line in results {
-        println!("{}", line);
+fn named(name: &str, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", name, e))
+}
+
+// Where an offset into the lossy text falls in the bytes it was decoded from, every U+FFFD there
+// standing for a run of invalid bytes
+fn byte_offset(text: &[u8], lossy: &str, offset: usize) -> usize {
+    if lossy.len() == text.len() {
+        return offset; // Nothing was replaced
+    }
+
+    let (mut decoded, mut read) = (0, 0);
+    for chunk in text.utf8_chunks() {
+        let valid = chunk.valid().len();
+        if offset <= decoded + valid {
+            return read + offset - decoded;
+        }
+        decoded += valid;
+        read += valid;
+
+        if !chunk.invalid().is_empty() {
+            if offset < decoded + '\u{fffd}'.len_utf8() {
+                return read;
+            }
+            decoded += '\u{fffd}'.len_utf8();
+            read += chunk.invalid().len();
+        }
     }
+    read
+}
 
-    Ok(())
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
 }
 
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
@@ -61,10 +462,16 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
 }
 
 pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
+    let query = case::fold(query); // Folding both sides, so 'STRASSE' finds 'Straße'
     
     contents.lines() // Not only is this more concise than 'search' but also allows parallelization
-            .filter(|line| line.contains(&query))
+            .filter(|line| case::fold(line).contains(&query))
+            .collect()
+}
+
+pub fn search_regex<'a>(regex: &Regex, contents: &'a str) -> Vec<&'a str> {
+    contents.lines()
+            .filter(|line| regex.is_match(line))
             .collect()
 }
 
@@ -101,4 +508,192 @@ Trust me.";
             search_case_insensitive(query, contents)
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let contents = "\
+Die Straße ist lang.
+Die STRASSE ist breit.
+Istanbul, İzmir ve Diyarbakır.
+नमस्ते दुनिया";
+
+        assert_eq!(
+            vec!["Die Straße ist lang.", "Die STRASSE ist breit."],
+            search_case_insensitive("strasse", contents)
+        );
+        assert_eq!(
+            vec!["Istanbul, İzmir ve Diyarbakır."],
+            search_case_insensitive("BAKıR", contents)
+        );
+        assert!(search_case_insensitive("bakir", contents).is_empty()); // 'ı' is a letter of its own
+        assert_eq!(
+            vec!["नमस्ते दुनिया"],
+            search_case_insensitive("नमस्ते", contents)
+        );
+    }
+
+    #[test]
+    fn finds_folded_matches_once() {
+        let matcher = Matcher::CaseInsensitive(case::fold("s"));
+        assert_eq!(vec![(0, 1), (4, 6)], matcher.find_iter("Straße"));
+        assert_eq!(vec![(4, 6), (6, 8)], Matcher::CaseInsensitive(case::fold("ss")).find_iter("Straßsse"));
+
+        let config = Config { query: "s".to_string(), paths: vec!["-".to_string()], only_matching: true, ..Config::default() };
+        let mut searcher = Searcher::new(&config, Colors::plain(), Vec::new()).unwrap();
+        assert!(searcher.input("street", b"street", &b"Stra\xc3\x9fe\n"[..]).is_ok());
+        assert_eq!("S\nß\n", String::from_utf8(searcher.out).unwrap());
+    }
+
+    #[test]
+    fn regex() {
+        let regex = Regex::new(r"^\w+ (tape|three)\.$").unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+
+        assert_eq!(
+            vec!["Pick three.", "Duct tape."],
+            search_regex(&regex, contents)
+        );
+    }
+
+    #[test]
+    fn searches_bytes() {
+        let config = Config { query: "latte".to_string(), paths: vec!["-".to_string()], case_sensitive: true, ..Config::default() };
+        let mut searcher = Searcher::new(&config, Colors::plain(), Vec::new()).unwrap();
+        searcher.with_path = true;
+
+        let input: &[u8] = b"caf\xe9 latte\r\nplain tea\nlatte, no newline";
+        assert!(searcher.input("menu", b"menu", input).is_ok());
+        assert_eq!(&b"menu:caf\xe9 latte\nmenu:latte, no newline\n"[..], &searcher.out[..]);
+    }
+
+    #[test]
+    fn takes_no_paths() {
+        let searcher = Searcher::new(&Config::default(), Colors::plain(), Vec::new()).unwrap();
+        assert!(!searcher.with_path);
+    }
+
+    // Searching poem.txt for the query, case-sensitively unless told otherwise
+    fn poem(query: &str) -> Config {
+        Config { query: query.to_string(), paths: vec!["poem.txt".to_string()], case_sensitive: true, ..Config::default() }
+    }
+
+    fn output(config: Config) -> String {
+        let mut searcher = Searcher::new(&config, Colors::plain(), Vec::new()).unwrap();
+        searcher.paths(&config.paths, &config.walk).unwrap();
+        String::from_utf8(searcher.out).unwrap()
+    }
+
+    #[test]
+    fn line_numbers_and_columns() {
+        assert_eq!("\
+1:I’m nobody! Who are you?
+2:Are you nobody, too?
+", output(Config { line_numbers: true, ..poem("nobody") }));
+
+        // The apostrophe before 'm' takes three bytes
+        assert_eq!("\
+1:5:I’m nobody! Who are you?
+2:9:Are you nobody, too?
+", output(Config { line_numbers: true, column: Some(Column::Chars), ..poem("nobody") }));
+        assert_eq!("\
+1:7:I’m nobody! Who are you?
+2:9:Are you nobody, too?
+", output(Config { line_numbers: true, column: Some(Column::Bytes), ..poem("nobody") }));
+    }
+
+    #[test]
+    fn counts_and_files() {
+        assert_eq!("2\n", output(Config { output: Output::Count, ..poem("nobody") }));
+        assert_eq!("7\n", output(Config { output: Output::Count, invert: true, ..poem("nobody") }));
+        assert_eq!("1\n", output(Config { output: Output::Count, max_count: Some(1), ..poem("nobody") }));
+
+        let both = |output| Config { output, paths: vec!["poem.txt".to_string(), "Cargo.toml".to_string()], ..poem("frog") };
+        assert_eq!("poem.txt:1\nCargo.toml:0\n", self::output(both(Output::Count)));
+        assert_eq!("poem.txt\n", self::output(both(Output::FilesWithMatches)));
+        assert_eq!("Cargo.toml\n", self::output(both(Output::FilesWithoutMatch)));
+    }
+
+    #[test]
+    fn only_matching() {
+        assert_eq!("\
+6:How
+7:How
+", output(Config { line_numbers: true, only_matching: true, case_sensitive: false, ..poem("how") }));
+
+        assert_eq!("\
+7:5:public
+7:20:frog
+", output(Config { regex: true, line_numbers: true, column: Some(Column::Chars), only_matching: true, ..poem(r"\b(frog|public)\b") }));
+    }
+
+    #[test]
+    fn max_count_and_invert() {
+        assert_eq!("\
+I’m nobody! Who are you?
+", output(Config { max_count: Some(1), ..poem("nobody") }));
+
+        // The lines after the last match still get their context
+        assert_eq!("\
+6:How dreary to be somebody!
+7-How public, like a frog
+", output(Config { line_numbers: true, max_count: Some(1), after: 1, ..poem("How") }));
+
+        assert_eq!("\
+Then there’s a pair of us — don’t tell!
+They’d banish us, you know.
+", output(Config { invert: true, max_count: Some(2), ..poem("nobody") }));
+    }
+
+    #[test]
+    fn colors() {
+        let config = Config { line_numbers: true, max_count: Some(1), after: 1, ..poem("nobody") };
+        let mut searcher = Searcher::new(&config, Colors::default(), Vec::new()).unwrap();
+        searcher.with_path = true;
+        searcher.paths(&config.paths, &config.walk).unwrap();
+
+        assert_eq!("\
+\x1b[35mpoem.txt\x1b[0m\x1b[36m:\x1b[0m\x1b[32m1\x1b[0m\x1b[36m:\x1b[0mI’m \x1b[01;31mnobody\x1b[0m! Who are you?
+\x1b[35mpoem.txt\x1b[0m\x1b[36m-\x1b[0m\x1b[32m2\x1b[0m\x1b[36m-\x1b[0mAre you nobody, too?
+", String::from_utf8(searcher.out).unwrap());
+    }
+
+    #[test]
+    fn colors_overlapping_highlights() {
+        let config = Config { query: "s".to_string(), paths: vec!["-".to_string()], ..Config::default() };
+        let mut searcher = Searcher::new(&config, Colors::default(), Vec::new()).unwrap();
+        assert!(searcher.input("street", b"street", &b"Stra\xc3\x9fe\n"[..]).is_ok());
+        assert_eq!("\x1b[01;31mS\x1b[0mtra\x1b[01;31mß\x1b[0me\n", String::from_utf8(searcher.out).unwrap());
+
+        let mut out = Vec::new();
+        let fields = Fields { marker: ":", highlights: &[(4, 6), (4, 6), (5, 7)], ..Fields::default() };
+        print(&mut out, &Colors::default(), &fields, "Straße".as_bytes()).unwrap();
+        assert_eq!("Stra\x1b[01;31mß\x1b[0m\x1b[01;31me\x1b[0m\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn json_lines() {
+        let config = Config { json: true, before: 1, output: Output::Count, ..poem("frog") };
+        assert_eq!(r#"{"type":"begin","data":{"path":{"text":"poem.txt"}}}
+{"type":"context","data":{"path":{"text":"poem.txt"},"lines":{"text":"How dreary to be somebody!"},"line_number":6,"submatches":[]}}
+{"type":"match","data":{"path":{"text":"poem.txt"},"lines":{"text":"How public, like a frog"},"line_number":7,"submatches":[{"match":{"text":"frog"},"start":19,"end":23}]}}
+{"type":"end","data":{"path":{"text":"poem.txt"},"stats":{"matched_lines":1,"matches":1,"bytes_searched":230}}}
+{"type":"summary","data":{"stats":{"searches":1,"searches_with_match":1,"matched_lines":1,"matches":1,"bytes_searched":230}}}
+"#, output(config));
+
+        // Nothing to show, so just the summary
+        assert_eq!(r#"{"type":"summary","data":{"stats":{"searches":1,"searches_with_match":0,"matched_lines":0,"matches":0,"bytes_searched":230}}}
+"#, output(Config { json: true, ..poem("toad") }));
+    }
+
+    #[test]
+    fn maps_lossy_offsets_to_bytes() {
+        let text = b"\xff\xfelatte \xf0\x9f\x98de";
+        let lossy = String::from_utf8_lossy(text);
+        let offsets: Vec<_> = [0, 3, 6, 11, 12, 15, 17].iter().map(|&offset| byte_offset(text, &lossy, offset)).collect();
+        assert_eq!(vec![0, 1, 2, 7, 8, 11, 13], offsets);
+    }
+}
@@ -3,14 +3,22 @@ extern crate minigrep;
 use std::env;
 use std::process;
 
-use minigrep::Config;
+use minigrep::args::{self, ArgsError};
 
 fn main() {
-    let args = env::args();
+    let defaults = args::defaults(|name| env::var_os(name).is_some());
 
-    let config = Config::new(args).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {}", err);
-        process::exit(1);
+    let config = args::parse_over(defaults, env::args().skip(1)).unwrap_or_else(|err| {
+        match err {
+            ArgsError::Help => print!("{}", args::usage()),
+            ArgsError::Version => println!("minigrep {}", args::VERSION),
+            err => {
+                eprintln!("Problem parsing arguments: {}", err);
+                eprintln!("Try 'minigrep --help' for more information.");
+                process::exit(2);
+            },
+        }
+        process::exit(0);
     });
 
     if let Err(e) = minigrep::run(config) {
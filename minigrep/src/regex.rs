@@ -0,0 +1,896 @@
+// Regular expressions for the -E mode
+//
+// Supported syntax:
+//   literals, and '.' for any character but a newline
+//   classes: [abc] [^a-z] [[:alpha:]], with \d \w \s and their negations \D \W \S
+//   Unicode properties: \p{L} \p{Lu} \p{Greek} \pN, negated with \P
+//   anchors: ^ $ \b \B
+//   alternation a|b and groups (ab) (?:ab)
+//   repetition: * + ? {n} {n,} {n,m}, lazy when followed by ?
+//   (?i) to match the rest of the pattern case-insensitively
+//
+// Patterns are compiled once into a program for a Pike VM, which runs all the ways a pattern can
+// match in lockstep, so matching a line takes time proportional to its length whatever the pattern.
+
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+use case::simple_fold as fold; // how case-insensitive matching compares characters
+
+const MAX_REPEAT: u32 = 1000;
+const MAX_PROGRAM: usize = 100_000; // instructions, so that a{1000}{1000} is refused instead of eating memory
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegexError {
+    pub pattern: String,
+    pub position: usize, // in characters
+    pub message: String,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pattern '{}': {} at position {}", self.pattern, self.message, self.position)
+    }
+}
+
+impl Error for RegexError {}
+
+// Where a match was found, as byte offsets into the searched text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+}
+
+// Sets of characters known by name, such as \d or \p{Greek}
+#[derive(Debug, Clone, Copy)]
+enum Named {
+    Digit,
+    Word,
+    Space,
+    Blank,
+    Alphabetic,
+    Alphanumeric,
+    Uppercase,
+    Lowercase,
+    Numeric,
+    Control,
+    Punctuation,
+    HexDigit,
+    Any,
+    Script(&'static [(char, char)]),
+}
+
+impl Named {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Named::Digit => is_digit(c),
+            Named::Word => is_word(c),
+            Named::Space => c.is_whitespace(),
+            Named::Blank => c == ' ' || c == '\t',
+            Named::Alphabetic => c.is_alphabetic(),
+            Named::Alphanumeric => c.is_alphanumeric(),
+            Named::Uppercase => c.is_uppercase(),
+            Named::Lowercase => c.is_lowercase(),
+            Named::Numeric => c.is_numeric(),
+            Named::Control => c.is_control(),
+            Named::Punctuation => c.is_ascii_punctuation(),
+            Named::HexDigit => c.is_ascii_hexdigit(),
+            Named::Any => true,
+            Named::Script(ranges) => ranges.iter().any(|&(from, to)| from <= c && c <= to),
+        }
+    }
+
+    // \p{...} names, compared ignoring case, spaces, '-' and '_'
+    fn property(name: &str) -> Option<Named> {
+        let name: String = name.chars().filter(|c| !" -_".contains(*c)).collect::<String>().to_lowercase();
+
+        Some(match name.as_str() {
+            "l" | "letter" | "alpha" | "alphabetic" => Named::Alphabetic,
+            "lu" | "uppercaseletter" | "upper" | "uppercase" => Named::Uppercase,
+            "ll" | "lowercaseletter" | "lower" | "lowercase" => Named::Lowercase,
+            "n" | "number" | "numeric" => Named::Numeric,
+            "nd" | "decimalnumber" | "digit" => Named::Digit,
+            "alnum" | "alphanumeric" => Named::Alphanumeric,
+            "whitespace" | "space" | "wspace" => Named::Space,
+            "cc" | "control" => Named::Control,
+            "any" => Named::Any,
+            "latin" => Named::Script(LATIN),
+            "greek" => Named::Script(GREEK),
+            "cyrillic" => Named::Script(CYRILLIC),
+            "armenian" => Named::Script(ARMENIAN),
+            "hebrew" => Named::Script(HEBREW),
+            "arabic" => Named::Script(ARABIC),
+            "devanagari" => Named::Script(DEVANAGARI),
+            "bengali" => Named::Script(BENGALI),
+            "thai" => Named::Script(THAI),
+            "hangul" => Named::Script(HANGUL),
+            "hiragana" => Named::Script(HIRAGANA),
+            "katakana" => Named::Script(KATAKANA),
+            "han" => Named::Script(HAN),
+            _ => return None,
+        })
+    }
+
+    // [[:name:]] inside brackets
+    fn posix(name: &str) -> Option<Named> {
+        Some(match name {
+            "alpha" => Named::Alphabetic,
+            "digit" => Named::Digit,
+            "alnum" => Named::Alphanumeric,
+            "upper" => Named::Uppercase,
+            "lower" => Named::Lowercase,
+            "space" => Named::Space,
+            "blank" => Named::Blank,
+            "punct" => Named::Punctuation,
+            "xdigit" => Named::HexDigit,
+            "cntrl" => Named::Control,
+            "word" => Named::Word,
+            _ => return None,
+        })
+    }
+}
+
+// Scripts by their main Unicode blocks, which is close enough for searching text
+const LATIN: &[(char, char)] = &[('A', 'Z'), ('a', 'z'), ('\u{AA}', '\u{AA}'), ('\u{BA}', '\u{BA}'), ('\u{C0}', '\u{D6}'),
+                                 ('\u{D8}', '\u{F6}'), ('\u{F8}', '\u{24F}'), ('\u{1E00}', '\u{1EFF}'), ('\u{FF21}', '\u{FF3A}'),
+                                 ('\u{FF41}', '\u{FF5A}')];
+const GREEK: &[(char, char)] = &[('\u{370}', '\u{373}'), ('\u{375}', '\u{377}'), ('\u{37A}', '\u{37D}'), ('\u{37F}', '\u{37F}'),
+                                 ('\u{384}', '\u{384}'), ('\u{386}', '\u{386}'), ('\u{388}', '\u{3E1}'), ('\u{3F0}', '\u{3FF}'),
+                                 ('\u{1F00}', '\u{1FFE}')];
+const CYRILLIC: &[(char, char)] = &[('\u{400}', '\u{52F}'), ('\u{1C80}', '\u{1C88}'), ('\u{2DE0}', '\u{2DFF}'), ('\u{A640}', '\u{A69F}')];
+const ARMENIAN: &[(char, char)] = &[('\u{531}', '\u{58A}'), ('\u{58D}', '\u{58F}'), ('\u{FB13}', '\u{FB17}')];
+const HEBREW: &[(char, char)] = &[('\u{591}', '\u{5F4}'), ('\u{FB1D}', '\u{FB4F}')];
+const ARABIC: &[(char, char)] = &[('\u{600}', '\u{6FF}'), ('\u{750}', '\u{77F}'), ('\u{8A0}', '\u{8FF}'), ('\u{FB50}', '\u{FDFF}'),
+                                  ('\u{FE70}', '\u{FEFC}')];
+const DEVANAGARI: &[(char, char)] = &[('\u{900}', '\u{97F}'), ('\u{A8E0}', '\u{A8FF}')];
+const BENGALI: &[(char, char)] = &[('\u{980}', '\u{9FE}')];
+const THAI: &[(char, char)] = &[('\u{E01}', '\u{E3A}'), ('\u{E40}', '\u{E5B}')];
+const HANGUL: &[(char, char)] = &[('\u{1100}', '\u{11FF}'), ('\u{3131}', '\u{318E}'), ('\u{AC00}', '\u{D7A3}')];
+const HIRAGANA: &[(char, char)] = &[('\u{3041}', '\u{3096}'), ('\u{309D}', '\u{309F}')];
+const KATAKANA: &[(char, char)] = &[('\u{30A1}', '\u{30FA}'), ('\u{30FD}', '\u{30FF}'), ('\u{31F0}', '\u{31FF}'), ('\u{FF66}', '\u{FF9D}')];
+const HAN: &[(char, char)] = &[('\u{2E80}', '\u{2FD5}'), ('\u{3005}', '\u{3005}'), ('\u{3007}', '\u{3007}'), ('\u{3021}', '\u{3029}'),
+                               ('\u{3400}', '\u{4DBF}'), ('\u{4E00}', '\u{9FFF}'), ('\u{F900}', '\u{FAFF}'), ('\u{20000}', '\u{3134A}')];
+
+// The zero of each run of ten decimal digits Unicode has, ASCII first
+const DIGIT_ZEROS: &[u32] = &[0x30, 0x660, 0x6F0, 0x7C0, 0x966, 0x9E6, 0xA66, 0xAE6, 0xB66, 0xBE6, 0xC66, 0xCE6, 0xD66,
+                              0xDE6, 0xE50, 0xED0, 0xF20, 0x1040, 0x1090, 0x17E0, 0x1810, 0x1946, 0x19D0, 0x1A80, 0x1A90,
+                              0x1B50, 0x1BB0, 0x1C40, 0x1C50, 0xA620, 0xA8D0, 0xA900, 0xA9D0, 0xA9F0, 0xAA50, 0xABF0, 0xFF10,
+                              0x104A0, 0x11066, 0x110F0, 0x11136, 0x111D0, 0x112F0, 0x11450, 0x114D0, 0x11650, 0x116C0,
+                              0x11730, 0x118E0, 0x16A60, 0x16B50, 0x1D7CE, 0x1D7D8, 0x1D7E2, 0x1D7EC, 0x1D7F6, 0x1E950];
+
+fn is_digit(c: char) -> bool {
+    let c = c as u32;
+    DIGIT_ZEROS.iter().any(|&zero| zero <= c && c < zero + 10)
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Named(Named, bool), // negated when true
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+    case_insensitive: bool,
+}
+
+impl Class {
+    fn named(named: Named, negated: bool) -> Class {
+        Class { items: vec![ClassItem::Named(named, negated)], negated: false, case_insensitive: false }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        let contains = |c: char| self.items.iter().any(|item| match *item {
+            ClassItem::Range(from, to) => from <= c && c <= to,
+            ClassItem::Named(named, negated) => named.matches(c) != negated,
+        });
+
+        let found = contains(c) || (self.case_insensitive && (contains(fold(c)) || c.to_uppercase().any(contains)));
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Assertion {
+    fn holds(self, text: &str, at: usize) -> bool {
+        let word_before = || text[..at].chars().next_back().is_some_and(is_word);
+        let word_after = || text[at..].chars().next().is_some_and(is_word);
+
+        match self {
+            Assertion::Start => at == 0,
+            Assertion::End => at == text.len(),
+            Assertion::WordBoundary => word_before() != word_after(),
+            Assertion::NotWordBoundary => word_before() == word_after(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char, bool), // case-insensitive when true
+    Class(Class),
+    Assert(Assertion),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat { node: Box<Node>, min: u32, max: Option<u32>, greedy: bool },
+}
+
+struct Parser<'a> {
+    pattern: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+    case_insensitive: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, position: usize, message: &str) -> Result<T, RegexError> {
+        Err(RegexError { pattern: self.pattern.to_string(), position, message: message.to_string() })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(&mut self) -> Result<Node, RegexError> {
+        let node = self.parse_alternation()?;
+
+        if self.pos < self.chars.len() {
+            return self.error(self.pos, "unmatched ')'"); // the only thing an alternation stops at
+        }
+        Ok(node)
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+
+        Ok(if branches.len() == 1 { branches.pop().unwrap() } else { Node::Alternate(branches) })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = Vec::new();
+
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_repeat(atom)?);
+        }
+
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, RegexError> {
+        let start = self.pos;
+        let c = self.chars[self.pos];
+        self.pos += 1;
+
+        Ok(match c {
+            '(' => self.parse_group(start)?,
+            '[' => Node::Class(self.parse_class(start)?),
+            '.' => Node::Class(Class { items: vec![ClassItem::Range('\n', '\n')], negated: true, case_insensitive: false }),
+            '^' => Node::Assert(Assertion::Start),
+            '$' => Node::Assert(Assertion::End),
+            '\\' => self.parse_escape(start)?,
+            '*' | '+' | '?' => return self.error(start, "nothing to repeat"),
+            '{' if self.repetition_ahead(start) => return self.error(start, "nothing to repeat"),
+            c => Node::Char(c, self.case_insensitive),
+        })
+    }
+
+    fn parse_group(&mut self, start: usize) -> Result<Node, RegexError> {
+        if self.eat('?') {
+            if self.eat('i') {
+                if !self.eat(')') {
+                    return self.error(start, "expected ')' after (?i");
+                }
+                self.case_insensitive = true;
+                return Ok(Node::Empty);
+            }
+            if !self.eat(':') {
+                return self.error(start, "unsupported group syntax");
+            }
+        }
+
+        let node = self.parse_alternation()?;
+        if !self.eat(')') {
+            return self.error(start, "unclosed group");
+        }
+        Ok(node)
+    }
+
+    fn parse_repeat(&mut self, atom: Node) -> Result<Node, RegexError> {
+        let start = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') if self.repetition_ahead(start) => {
+                self.pos += 1;
+                self.parse_counts(start)?
+            },
+            _ => return Ok(atom),
+        };
+        self.pos += 1; // the operator, or the closing brace of counts
+
+        let greedy = !self.eat('?');
+        if let Some(c) = self.peek() {
+            if c == '*' || c == '+' || (c == '?' && greedy) || (c == '{' && self.repetition_ahead(self.pos)) {
+                return self.error(self.pos, "nothing to repeat");
+            }
+        }
+
+        Ok(Node::Repeat { node: Box::new(atom), min, max, greedy })
+    }
+
+    // Whether a '{' starts counts such as {2}, {2,} or {2,5}, rather than standing for itself
+    fn repetition_ahead(&self, brace: usize) -> bool {
+        let rest: String = self.chars[brace + 1..].iter().take_while(|&&c| c != '}').collect();
+        let closed = self.chars[brace + 1..].contains(&'}');
+        let mut parts = rest.splitn(2, ',');
+        let first = parts.next().unwrap_or("");
+
+        closed && !first.is_empty() && first.chars().all(|c| c.is_ascii_digit())
+                && parts.next().is_none_or(|second| second.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    // After the '{', up to but not including the '}'
+    fn parse_counts(&mut self, start: usize) -> Result<(u32, Option<u32>), RegexError> {
+        let min = self.number();
+        let max = if self.chars[self.pos] == ',' {
+            self.pos += 1;
+            self.number()
+        } else {
+            min
+        };
+
+        let min = match min {
+            Some(min) if min <= MAX_REPEAT && max.is_none_or(|max| max <= MAX_REPEAT) => min,
+            _ => return self.error(start, "repetition count too large"),
+        };
+        if max.is_some_and(|max| max < min) {
+            return self.error(start, "invalid repetition range");
+        }
+        Ok((min, max))
+    }
+
+    // None when there are no digits, too large to bother when there are too many
+    fn number(&mut self) -> Option<u32> {
+        let digits: String = self.chars[self.pos..].iter().take_while(|c| c.is_ascii_digit()).collect();
+        self.pos += digits.len();
+
+        if digits.is_empty() {
+            None
+        } else {
+            Some(digits.parse().unwrap_or(u32::MAX))
+        }
+    }
+
+    fn parse_escape(&mut self, start: usize) -> Result<Node, RegexError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return self.error(start, "trailing backslash"),
+        };
+
+        Ok(match c {
+            'b' => {
+                self.pos += 1;
+                Node::Assert(Assertion::WordBoundary)
+            },
+            'B' => {
+                self.pos += 1;
+                Node::Assert(Assertion::NotWordBoundary)
+            },
+            _ => match self.parse_class_escape(start)? {
+                ClassItem::Range(c, _) => Node::Char(c, self.case_insensitive),
+                ClassItem::Named(named, negated) => Node::Class(Class::named(named, negated)),
+            },
+        })
+    }
+
+    // Escapes meaning the same inside and outside brackets: a set of characters or a single one
+    fn parse_class_escape(&mut self, start: usize) -> Result<ClassItem, RegexError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return self.error(start, "trailing backslash"),
+        };
+        self.pos += 1;
+
+        let single = |c: char| Ok(ClassItem::Range(c, c));
+        match c {
+            'd' | 'D' => Ok(ClassItem::Named(Named::Digit, c == 'D')),
+            'w' | 'W' => Ok(ClassItem::Named(Named::Word, c == 'W')),
+            's' | 'S' => Ok(ClassItem::Named(Named::Space, c == 'S')),
+            'p' | 'P' => {
+                let name: String = if self.eat('{') {
+                    let name: String = self.chars[self.pos..].iter().take_while(|&&c| c != '}').collect();
+                    self.pos += name.chars().count();
+                    if !self.eat('}') {
+                        return self.error(start, "unclosed Unicode property");
+                    }
+                    name
+                } else {
+                    match self.peek() {
+                        Some(letter) => {
+                            self.pos += 1;
+                            letter.to_string()
+                        },
+                        None => return self.error(start, "missing Unicode property name"),
+                    }
+                };
+
+                match Named::property(&name) {
+                    Some(named) => Ok(ClassItem::Named(named, c == 'P')),
+                    None => self.error(start, &format!("unknown Unicode property '{}'", name)),
+                }
+            },
+            'n' => single('\n'),
+            't' => single('\t'),
+            'r' => single('\r'),
+            'f' => single('\u{C}'),
+            'v' => single('\u{B}'),
+            'x' => {
+                let digits: String = if self.eat('{') {
+                    let digits: String = self.chars[self.pos..].iter().take_while(|&&c| c != '}').collect();
+                    self.pos += digits.chars().count();
+                    if !self.eat('}') {
+                        return self.error(start, "unclosed hexadecimal escape");
+                    }
+                    digits
+                } else {
+                    let digits: String = self.chars[self.pos..].iter().take(2).collect();
+                    self.pos += digits.chars().count();
+                    digits
+                };
+
+                match u32::from_str_radix(&digits, 16).ok().and_then(::std::char::from_u32) {
+                    Some(c) => single(c),
+                    None => self.error(start, "invalid hexadecimal escape"),
+                }
+            },
+            c if !c.is_alphanumeric() => single(c), // escaped punctuation stands for itself
+            c => self.error(start, &format!("unknown escape '\\{}'", c)),
+        }
+    }
+
+    // After the '['
+    fn parse_class(&mut self, start: usize) -> Result<Class, RegexError> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+
+        loop {
+            let item_start = self.pos;
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.error(start, "unclosed character class"),
+            };
+            self.pos += 1;
+
+            let item = match c {
+                ']' if !first => break,
+                '[' if self.peek() == Some(':') => {
+                    let rest: String = self.chars[self.pos + 1..].iter().collect();
+                    match rest.find(":]") {
+                        Some(end) => {
+                            let name = &rest[..end];
+                            self.pos += 1 + name.chars().count() + 2;
+                            match Named::posix(name) {
+                                Some(named) => ClassItem::Named(named, false),
+                                None => return self.error(item_start, &format!("unknown class '[:{}:]'", name)),
+                            }
+                        },
+                        None => ClassItem::Range('[', '['),
+                    }
+                },
+                '\\' => self.parse_class_escape(item_start)?,
+                c => ClassItem::Range(c, c),
+            };
+            first = false;
+
+            // A '-' between two single characters makes a range, elsewhere it stands for itself
+            let from = match item {
+                ClassItem::Range(from, _) if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') => from,
+                item => {
+                    items.push(item);
+                    continue;
+                },
+            };
+            self.pos += 1;
+
+            let to = match self.chars[self.pos] {
+                '\\' => {
+                    let escape_start = self.pos;
+                    self.pos += 1;
+                    match self.parse_class_escape(escape_start)? {
+                        ClassItem::Range(to, _) => to,
+                        ClassItem::Named(..) => return self.error(item_start, "invalid class range"),
+                    }
+                },
+                to => {
+                    self.pos += 1;
+                    to
+                },
+            };
+            if to < from {
+                return self.error(item_start, "invalid class range");
+            }
+            items.push(ClassItem::Range(from, to));
+        }
+
+        Ok(Class { items, negated, case_insensitive: self.case_insensitive })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    CharFolded(char), // compares fold(c) with it
+    Class(Class),
+    Assert(Assertion),
+    Split(usize, usize), // tries the first before the second
+    Jmp(usize),
+    Match,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, ()> {
+        if self.program.len() >= MAX_PROGRAM {
+            return Err(());
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    // Sets where a Split or Jmp emitted earlier goes
+    fn patch(&mut self, at: usize, first: Option<usize>, second: Option<usize>) {
+        match self.program[at] {
+            Inst::Split(ref mut x, ref mut y) => {
+                if let Some(first) = first {
+                    *x = first;
+                }
+                if let Some(second) = second {
+                    *y = second;
+                }
+            },
+            Inst::Jmp(ref mut x) => *x = first.unwrap_or(*x),
+            _ => unreachable!(),
+        }
+    }
+
+    // A split to be patched, preferring what comes right after it unless lazy
+    fn split(&mut self, greedy: bool, elsewhere: usize) -> Result<usize, ()> {
+        let here = self.program.len();
+        if greedy {
+            self.emit(Inst::Split(here + 1, elsewhere))
+        } else {
+            self.emit(Inst::Split(elsewhere, here + 1))
+        }
+    }
+
+    fn patch_split(&mut self, at: usize, greedy: bool, elsewhere: usize) {
+        if greedy {
+            self.patch(at, None, Some(elsewhere));
+        } else {
+            self.patch(at, Some(elsewhere), None);
+        }
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), ()> {
+        match *node {
+            Node::Empty => {},
+            Node::Char(c, true) => {
+                self.emit(Inst::CharFolded(fold(c)))?;
+            },
+            Node::Char(c, false) => {
+                self.emit(Inst::Char(c))?;
+            },
+            Node::Class(ref class) => {
+                self.emit(Inst::Class(class.clone()))?;
+            },
+            Node::Assert(assertion) => {
+                self.emit(Inst::Assert(assertion))?;
+            },
+            Node::Concat(ref nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            },
+            Node::Alternate(ref branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    let split = if i + 1 < branches.len() { Some(self.split(true, 0)?) } else { None };
+                    self.compile(branch)?;
+                    if let Some(split) = split {
+                        jumps.push(self.emit(Inst::Jmp(0))?);
+                        let next = self.program.len();
+                        self.patch(split, None, Some(next));
+                    }
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.patch(jump, Some(end), None);
+                }
+            },
+            Node::Repeat { ref node, min, max, greedy } => {
+                for _ in 0..min {
+                    self.compile(node)?;
+                }
+
+                match max {
+                    None => {
+                        let split = self.split(greedy, 0)?;
+                        self.compile(node)?;
+                        self.emit(Inst::Jmp(split))?;
+                        let end = self.program.len();
+                        self.patch_split(split, greedy, end);
+                    },
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in min..max {
+                            splits.push(self.split(greedy, 0)?);
+                            self.compile(node)?;
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.patch_split(split, greedy, end);
+                        }
+                    },
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// Threads of the VM at one position, in priority order, at most one per instruction
+struct Threads {
+    list: Vec<(usize, usize)>, // instruction and where the match started
+    marks: Vec<usize>,
+    generation: usize,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads { list: Vec::new(), marks: vec![0; size], generation: 1 }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.generation += 1;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let mut parser = Parser { pattern, chars: pattern.chars().collect(), pos: 0, case_insensitive: false };
+        let node = parser.parse()?;
+
+        let mut compiler = Compiler { program: Vec::new() };
+        if compiler.compile(&node).and_then(|_| compiler.emit(Inst::Match)).is_err() {
+            return parser.error(0, "pattern too large");
+        }
+
+        Ok(Regex { program: compiler.program })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    pub fn find(&self, text: &str) -> Option<Match> {
+        self.find_at(text, 0)
+    }
+
+    // The leftmost match starting at or after the given byte offset; anchors and word boundaries
+    // still look at the whole text
+    pub fn find_at(&self, text: &str, start: usize) -> Option<Match> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut found = None;
+        let mut at = start;
+
+        loop {
+            if found.is_none() {
+                self.add(&mut current, 0, at, text, at); // a match starting here, behind those started earlier
+            }
+            if current.list.is_empty() && found.is_some() {
+                break; // Nothing left that could do better; without a match yet, later starts still could
+            }
+
+            let c = text[at..].chars().next();
+            let after = at + c.map_or(0, |c| c.len_utf8());
+
+            for i in 0..current.list.len() {
+                let (pc, started) = current.list[i];
+                let advances = match (&self.program[pc], c) {
+                    (Inst::Match, _) => {
+                        found = Some(Match { start: started, end: at });
+                        break; // threads after this one have lower priority
+                    },
+                    (_, None) => false,
+                    (Inst::Char(x), Some(c)) => c == *x,
+                    (Inst::CharFolded(x), Some(c)) => fold(c) == *x,
+                    (Inst::Class(class), Some(c)) => class.matches(c),
+                    _ => false,
+                };
+                if advances {
+                    self.add(&mut next, pc + 1, started, text, after);
+                }
+            }
+
+            if c.is_none() {
+                break;
+            }
+            mem::swap(&mut current, &mut next);
+            next.clear();
+            at = after;
+        }
+
+        found
+    }
+
+    pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> Matches<'r, 't> {
+        Matches { regex: self, text, at: 0, last_end: None }
+    }
+
+    // Follows jumps, splits and assertions from pc, adding the threads waiting for a character
+    fn add(&self, threads: &mut Threads, pc: usize, started: usize, text: &str, at: usize) {
+        let mut stack = vec![pc];
+
+        while let Some(pc) = stack.pop() {
+            if threads.marks[pc] == threads.generation {
+                continue;
+            }
+            threads.marks[pc] = threads.generation;
+
+            match self.program[pc] {
+                Inst::Jmp(to) => stack.push(to),
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                },
+                Inst::Assert(assertion) => {
+                    if assertion.holds(text, at) {
+                        stack.push(pc + 1);
+                    }
+                },
+                _ => threads.list.push((pc, started)),
+            }
+        }
+    }
+}
+
+// Successive non-overlapping matches
+pub struct Matches<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    at: usize,
+    last_end: Option<usize>,
+}
+
+impl<'r, 't> Iterator for Matches<'r, 't> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        loop {
+            if self.at > self.text.len() {
+                return None;
+            }
+            let found = self.regex.find_at(self.text, self.at)?;
+
+            // An empty match right where the previous one ended would repeat forever
+            if found.start == found.end && Some(found.end) == self.last_end {
+                self.at = found.end + self.text[found.end..].chars().next().map_or(1, |c| c.len_utf8());
+                continue;
+            }
+
+            self.at = if found.start == found.end {
+                found.end + self.text[found.end..].chars().next().map_or(1, |c| c.len_utf8())
+            } else {
+                found.end
+            };
+            self.last_end = Some(found.end);
+            return Some(found);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find<'t>(pattern: &str, text: &'t str) -> Option<&'t str> {
+        Regex::new(pattern).unwrap().find(text).map(|m| &text[m.start..m.end])
+    }
+
+    #[test]
+    fn matches_leftmost_first() {
+        assert_eq!(Some("abc"), find("a.c", "xxabcxx"));
+        assert_eq!(Some("2018"), find(r"\d+", "Since 2018"));
+        assert_eq!(Some("Rust"), find("^Rust|Trust", "Rust: Trust"));
+        assert_eq!(Some("tape."), find(r"\w+\.$", "Duct tape."));
+        assert_eq!(Some("<b>"), find("<.+?>", "<b>bold</b>"));
+        assert_eq!(Some("<b>bold</b>"), find("<.+>", "<b>bold</b>"));
+        assert_eq!(Some("color"), find("colou?r", "what color?"));
+        assert_eq!(Some("abab"), find("(?:ab){2,3}", "ababxab"));
+        assert_eq!(None, find("^fast", "safe, fast, productive."));
+        assert_eq!(None, find(r"\bduct", "productive"));
+        assert_eq!(Some("tape"), find(r"\btape\b", "Duct tape."));
+    }
+
+    #[test]
+    fn understands_classes() {
+        assert_eq!(Some("x-9"), find("[a-z][-][0-9]", "X-1 x-9"));
+        assert_eq!(Some("you"), find("[^ !?]+u", "Who are you?"));
+        assert_eq!(Some("]"), find("[]]", "a]"));
+        assert_eq!(Some("ñandú"), find(r"[[:alpha:]]+", "¡ñandú!"));
+        assert_eq!(Some("λόγος"), find(r"\p{Greek}+", "logos λόγος"));
+        assert_eq!(Some("नमस्ते"), find(r"\p{Devanagari}+", "hi नमस्ते"));
+        assert_eq!(Some("४२"), find(r"\d+", "संख्या ४२"));
+        assert_eq!(Some("NOBODY"), find(r"(?i)nobody", "I'm NOBODY!"));
+    }
+
+    #[test]
+    fn finds_every_match() {
+        let regex = Regex::new("o+|x*").unwrap();
+        let found: Vec<(usize, usize)> = regex.find_iter("foo o").map(|m| (m.start, m.end)).collect();
+        assert_eq!(vec![(0, 0), (1, 3), (4, 5)], found); // no empty matches right after another
+    }
+
+    #[test]
+    fn reports_invalid_patterns() {
+        let error = |pattern: &str| Regex::new(pattern).unwrap_err();
+
+        assert_eq!((1, "unclosed group".to_string()), (error("a(b|c").position, error("a(b|c").message));
+        assert_eq!("unmatched ')'", error("a)").message);
+        assert_eq!("nothing to repeat", error("*a").message);
+        assert_eq!("invalid class range", error("[z-a]").message);
+        assert_eq!("unclosed character class", error("[abc").message);
+        assert_eq!("invalid repetition range", error("a{3,1}").message);
+        assert_eq!("unknown Unicode property 'Klingon'", error(r"\p{Klingon}").message);
+        assert_eq!("invalid pattern 'a\\': trailing backslash at position 1", error("a\\").to_string());
+        assert_eq!("pattern too large", error("(?:a{1000}){1000}").message);
+    }
+}
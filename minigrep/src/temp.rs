@@ -0,0 +1,40 @@
+// A directory for a test's files, removed with everything in it when the test ends, even when it
+// fails
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    // The name only tells where a directory left by a killed run comes from
+    pub fn new(name: &str) -> TempDir {
+        let unique = CREATED.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("minigrep_{}_{}_{}", name, process::id(), unique));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+// So it can be used as the path it is
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,232 @@
+// Finding the files to search: the ones named, and those in the directories named, recursively
+//
+// Inside directories, hidden files and directories (whose names start with '.') are skipped unless
+// asked for, and so are symbolic links unless they are to be followed, and whatever the ignore files
+// of the directories on the way say to ignore (see ignore.rs). Paths given by the user are always
+// searched. Directories are read in name order, so the output doesn't change between runs.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use glob::Glob;
+use ignore::{self, Ignore};
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub include: Vec<Glob>, // when not empty, only files matching one of these are searched
+    pub exclude: Vec<Glob>,
+    pub hidden: bool,
+    pub follow_links: bool,
+    pub no_ignore: bool, // search what ignore files say to skip
+}
+
+impl Options {
+    // Whether the include and exclude globs let a file through. Globs with a '/' are matched
+    // against the path from the directory named on the command line (like ignore files' are from
+    // theirs), or the path as given for files named themselves; the others against just the name
+    pub fn selects(&self, path: &Path, root: &Path) -> bool {
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) if relative.as_os_str().is_empty() => path,
+            Ok(relative) => relative,
+            Err(_) => path,
+        };
+        let relative: Vec<_> = relative.components()
+                                       .filter(|part| part != &Component::CurDir)
+                                       .map(|part| part.as_os_str().to_string_lossy())
+                                       .collect();
+        let relative = relative.join("/");
+        let matches = |glob: &Glob| glob.is_match(if glob.has_slash() { &relative } else { &name });
+
+        (self.include.is_empty() || self.include.iter().any(matches)) && !self.exclude.iter().any(matches)
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    named: bool, // given by the user rather than found in a directory
+    root: Rc<PathBuf>, // the path given by the user it was found under
+    ancestors: Vec<PathBuf>, // the real paths of the directories it was found in, to notice loops
+    ignores: Vec<Rc<Ignore>>, // those of the directories it was found in, top down
+}
+
+pub struct Walk<'a> {
+    options: &'a Options,
+    pending: Vec<Entry>, // the next one last
+}
+
+// The files to search, in order, along with the errors found on the way
+pub fn walk<'a>(paths: &[String], options: &'a Options) -> Walk<'a> {
+    let pending = paths.iter()
+                       .rev()
+                       .map(|path| Entry {
+                           path: PathBuf::from(path),
+                           named: true,
+                           root: Rc::new(PathBuf::from(path)),
+                           ancestors: Vec::new(),
+                           ignores: Vec::new(),
+                       })
+                       .collect();
+
+    Walk { options, pending }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<io::Result<PathBuf>> {
+        while let Some(entry) = self.pending.pop() {
+            let path = entry.path.clone();
+            match self.visit(entry) {
+                Ok(Some(file)) => return Some(Ok(file)),
+                Ok(None) => {},
+                Err(e) => return Some(Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))),
+            }
+        }
+        None
+    }
+}
+
+impl<'a> Walk<'a> {
+    // The entry's path if it's a file to search; a directory's entries are queued instead
+    fn visit(&mut self, entry: Entry) -> io::Result<Option<PathBuf>> {
+        if !entry.named {
+            if !self.options.hidden && is_hidden(&entry.path) {
+                return Ok(None);
+            }
+            if !self.options.follow_links && fs::symlink_metadata(&entry.path)?.file_type().is_symlink() {
+                return Ok(None);
+            }
+        }
+
+        let metadata = fs::metadata(&entry.path)?; // Through any links
+        if !entry.named && ignore::is_ignored(&entry.ignores, &entry.path, metadata.is_dir()) {
+            return Ok(None);
+        }
+
+        if metadata.is_dir() {
+            let real = fs::canonicalize(&entry.path)?;
+            if entry.ancestors.contains(&real) {
+                return Err(io::Error::other("recursive directory loop"));
+            }
+
+            let mut children = fs::read_dir(&entry.path)?
+                                 .map(|child| child.map(|child| child.path()))
+                                 .collect::<io::Result<Vec<_>>>()?;
+            children.sort();
+
+            let mut ancestors = entry.ancestors;
+            ancestors.push(real);
+            let mut ignores = entry.ignores;
+            if !self.options.no_ignore {
+                ignores.extend(Ignore::from_dir(&entry.path)?.map(Rc::new));
+            }
+
+            for child in children.into_iter().rev() {
+                self.pending.push(Entry {
+                    path: child,
+                    named: false,
+                    root: Rc::clone(&entry.root),
+                    ancestors: ancestors.clone(),
+                    ignores: ignores.clone(),
+                });
+            }
+            Ok(None)
+        } else if (entry.named || metadata.is_file()) && self.options.selects(&entry.path, &entry.root) {
+            Ok(Some(entry.path)) // Named devices and pipes are searched too, but not those found in directories
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use temp::TempDir;
+
+    // A tree of empty files under a fresh temporary directory
+    fn tree(test: &str, files: &[&str]) -> TempDir {
+        let root = TempDir::new(&format!("walk_{}", test));
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        root
+    }
+
+    // The files found, relative to the root, and how many errors
+    fn found(root: &Path, options: &Options) -> (Vec<String>, usize) {
+        let results: Vec<_> = walk(&[root.to_string_lossy().into_owned()], options).collect();
+        let files = results.iter()
+                           .filter_map(|result| result.as_ref().ok())
+                           .map(|path| path.strip_prefix(root).unwrap().to_string_lossy().into_owned())
+                           .collect();
+        (files, results.iter().filter(|result| result.is_err()).count())
+    }
+
+    #[test]
+    fn filters_files() {
+        let root = tree("filters", &["b.rs", "a.txt", ".hidden.rs", ".git/config", "src/main.rs", "src/test.rs"]);
+
+        let mut options = Options::default();
+        assert_eq!((vec!["a.txt".to_string(), "b.rs".to_string(), "src/main.rs".to_string(), "src/test.rs".to_string()], 0), found(&root, &options));
+
+        options.include.push(Glob::new("*.rs"));
+        options.exclude.push(Glob::new("test.rs"));
+        assert_eq!(vec!["b.rs", "src/main.rs"], found(&root, &options).0);
+
+        options.hidden = true;
+        assert_eq!(vec![".hidden.rs", "b.rs", "src/main.rs"], found(&root, &options).0);
+    }
+
+    #[test]
+    fn matches_slash_globs_from_the_named_root() {
+        let root = tree("slash", &["main.rs", "src/main.rs", "src/lib/walk.rs"]);
+        let options = Options { exclude: vec![Glob::new("src/**")], ..Options::default() };
+        assert_eq!(vec!["main.rs"], found(&root, &options).0);
+
+        let options = Options { include: vec![Glob::new("src/*.rs")], ..Options::default() };
+        assert_eq!(vec!["src/main.rs"], found(&root, &options).0);
+
+        // Named files are taken as given, with or without a leading ./
+        let options = Options { exclude: vec![Glob::new("src/**")], ..Options::default() };
+        assert!(!options.selects(Path::new("./src/main.rs"), Path::new("./src/main.rs")));
+        assert!(options.selects(Path::new("main.rs"), Path::new("main.rs")));
+    }
+
+    #[test]
+    fn respects_ignore_files() {
+        let root = tree("ignore", &["main.rs", "debug.log", "target/out.rs", "docs/notes.log", "docs/draft.rs"]);
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join(".minigrepignore"), "draft.rs\n").unwrap();
+        fs::write(root.join("docs/.gitignore"), "!notes.log\n").unwrap();
+
+        assert_eq!(vec!["docs/notes.log", "main.rs"], found(&root, &Options::default()).0);
+
+        let options = Options { no_ignore: true, ..Options::default() };
+        assert_eq!(vec!["debug.log", "docs/draft.rs", "docs/notes.log", "main.rs", "target/out.rs"], found(&root, &options).0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_links_without_looping() {
+        use std::os::unix::fs::symlink;
+
+        let root = tree("links", &["dir/file.txt"]);
+        symlink(root.join("dir"), root.join("dir/again")).unwrap();
+        symlink(root.join("dir/file.txt"), root.join("link.txt")).unwrap();
+
+        assert_eq!((vec!["dir/file.txt".to_string()], 0), found(&root, &Options::default()));
+
+        let options = Options { follow_links: true, ..Options::default() };
+        assert_eq!((vec!["dir/file.txt".to_string(), "link.txt".to_string()], 1), found(&root, &options));
+    }
+}
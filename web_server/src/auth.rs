@@ -0,0 +1,370 @@
+// HTTP Basic authentication and per-path access control
+//
+// Protected paths are declared in the configuration file:
+//
+//     [auth]
+//     credentials = users.passwd
+//
+//     [protect /admin]
+//     realm = Admin tools
+//     users = alice bob              # any user in the credentials file if missing
+//     allow = 127.0.0.1 192.168.0.0/16
+//     deny = 192.168.1.13
+//
+// The credentials file holds one 'user:salt:hash' line per user, where hash is the hex encoded
+// SHA-256 of the salt followed by the password (e.g. `echo -n "$SALT$PASSWORD" | sha256sum`).
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use base64;
+use config::{Config, ConfigError, Section};
+use handler::Handler;
+use http::{self, Request, Response};
+use sha256;
+
+pub fn hash_password(salt: &str, password: &str) -> String {
+    let mut salted = salt.as_bytes().to_vec();
+    salted.extend_from_slice(password.as_bytes());
+
+    sha256::hex(&sha256::digest(&salted))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    users: HashMap<String, (String, String)>, // user -> (salt, hash)
+}
+
+impl Credentials {
+    pub fn new() -> Credentials {
+        Credentials { users: HashMap::new() }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Credentials, ConfigError> {
+        Credentials::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Credentials, ConfigError> {
+        let mut credentials = Credentials::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() != 3 || fields[0].is_empty() || fields[2].len() != 64 {
+                return Err(ConfigError::Invalid { line: i + 1, message: "expected 'user:salt:sha256'".to_string() });
+            }
+
+            credentials.users.insert(fields[0].to_string(), (fields[1].to_string(), fields[2].to_ascii_lowercase()));
+        }
+
+        Ok(credentials)
+    }
+
+    pub fn add(&mut self, user: &str, salt: &str, password: &str) {
+        self.users.insert(user.to_string(), (salt.to_string(), hash_password(salt, password)));
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some((salt, hash)) => constant_time_eq(hash.as_bytes(), hash_password(salt, password).as_bytes()),
+            None => false,
+        }
+    }
+}
+
+// Doesn't bail out on the first difference so response times don't leak how much of a hash matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// A single address or a CIDR block like 192.168.0.0/16
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, normalize(addr)) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_eq(&net.octets(), &addr.octets(), self.prefix_len)
+            },
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_eq(&net.octets(), &addr.octets(), self.prefix_len)
+            },
+            _ => false,
+        }
+    }
+}
+
+// IPv4 clients of a dual-stack listener show up as ::ffff:a.b.c.d
+pub(crate) fn normalize(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = (prefix_len / 8) as usize;
+    let rest_bits = prefix_len % 8;
+
+    if a[..full_bytes] != b[..full_bytes] {
+        return false;
+    }
+    if rest_bits == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - rest_bits);
+    a[full_bytes] & mask == b[full_bytes] & mask
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IpRange, String> {
+        let mut parts = s.splitn(2, '/');
+        let network = parts.next()
+                           .and_then(|a| a.parse::<IpAddr>().ok())
+                           .ok_or_else(|| format!("invalid IP address '{}'", s))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match parts.next() {
+            Some(len) => match len.parse::<u8>() {
+                Ok(len) if len <= max_len => len,
+                _ => return Err(format!("invalid prefix length in '{}'", s)),
+            },
+            None => max_len,
+        };
+
+        Ok(IpRange { network: normalize(network), prefix_len })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessRule {
+    pub prefix: String,
+    pub realm: Option<String>, // no authentication required when None
+    pub users: Vec<String>,    // any authenticated user when empty
+    pub allow: Vec<IpRange>,   // any address when empty
+    pub deny: Vec<IpRange>,
+}
+
+impl AccessRule {
+    pub fn new(prefix: &str) -> AccessRule {
+        AccessRule {
+            prefix: prefix.to_string(),
+            realm: None,
+            users: Vec::new(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+
+    pub fn from_section(section: &Section) -> Result<AccessRule, ConfigError> {
+        let prefix = match section.argument {
+            Some(ref prefix) if prefix.starts_with('/') => prefix,
+            _ => return Err(ConfigError::Invalid { line: section.line, message: "expected [protect /path]".to_string() }),
+        };
+
+        let ranges = |key: &str| -> Result<Vec<IpRange>, ConfigError> {
+            section.get_list(key).iter()
+                   .map(|r| r.parse().map_err(|message| ConfigError::Invalid { line: section.line, message }))
+                   .collect()
+        };
+
+        Ok(AccessRule {
+            prefix: prefix.clone(),
+            realm: section.get("realm").map(String::from),
+            users: section.get_list("users"),
+            allow: ranges("allow")?,
+            deny: ranges("deny")?,
+        })
+    }
+
+    fn permits_address(&self, addr: Option<IpAddr>) -> bool {
+        if self.allow.is_empty() && self.deny.is_empty() {
+            return true;
+        }
+
+        match addr {
+            Some(addr) => !self.deny.iter().any(|r| r.contains(addr))
+                          && (self.allow.is_empty() || self.allow.iter().any(|r| r.contains(addr))),
+            None => false, // can't tell who's asking, so be conservative
+        }
+    }
+}
+
+// Middleware checking the most specific rule matching the request path before calling the inner handler
+pub struct AccessControl<H> {
+    rules: Vec<AccessRule>,
+    credentials: Credentials,
+    inner: H,
+}
+
+impl<H: Handler> AccessControl<H> {
+    pub fn new(inner: H, credentials: Credentials) -> AccessControl<H> {
+        AccessControl {
+            rules: Vec::new(),
+            credentials,
+            inner,
+        }
+    }
+
+    pub fn from_config(inner: H, config: &Config) -> Result<AccessControl<H>, ConfigError> {
+        let credentials = match config.section("auth").and_then(|s| s.get("credentials")) {
+            Some(path) => Credentials::load(path)?,
+            None => Credentials::new(),
+        };
+
+        let mut access = AccessControl::new(inner, credentials);
+        for section in config.sections("protect") {
+            access = access.rule(AccessRule::from_section(section)?);
+        }
+
+        Ok(access)
+    }
+
+    pub fn rule(mut self, rule: AccessRule) -> AccessControl<H> {
+        self.rules.push(rule);
+        self.rules.sort_by_key(|r| ::std::cmp::Reverse(r.prefix.len())); // longest prefix first
+        self
+    }
+
+    fn authenticate(&self, request: &Request) -> Option<String> {
+        let header = request.header("Authorization")?;
+        let mut parts = header.splitn(2, ' ');
+
+        if !parts.next()?.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+
+        let decoded = String::from_utf8(base64::decode(parts.next()?.trim())?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+
+        if self.credentials.verify(user, password) {
+            Some(user.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+impl<H: Handler> Handler for AccessControl<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        let rule = match self.rules.iter().find(|r| http::matches_prefix(&request.path, &r.prefix)) {
+            Some(rule) => rule,
+            None => return self.inner.handle(request),
+        };
+
+        if !rule.permits_address(request.remote_addr.map(|a| a.ip())) {
+            return Response::error(403, "Forbidden");
+        }
+
+        if let Some(ref realm) = rule.realm {
+            let user = match self.authenticate(request) {
+                Some(user) => user,
+                None => {
+                    let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm.replace('"', "'"));
+                    return Response::error(401, "Unauthorized").with_header("WWW-Authenticate", &challenge);
+                }
+            };
+
+            if !rule.users.is_empty() && !rule.users.contains(&user) {
+                return Response::error(403, "Forbidden");
+            }
+
+            request.remote_user = Some(user);
+        }
+
+        self.inner.handle(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn protected() -> AccessControl<fn(&mut Request) -> Response> {
+        fn ok(request: &mut Request) -> Response {
+            Response::text(200, request.remote_user.clone().unwrap_or_default())
+        }
+
+        let mut credentials = Credentials::new();
+        credentials.add("alice", "s4lt", "secret");
+        credentials.add("bob", "pepper", "hunter2");
+
+        let mut admin = AccessRule::new("/admin");
+        admin.realm = Some("Admin".to_string());
+        admin.users = vec!["alice".to_string()];
+
+        let mut lan = AccessRule::new("/lan");
+        lan.allow = vec!["192.168.0.0/16".parse().unwrap()];
+        lan.deny = vec!["192.168.1.13".parse().unwrap()];
+
+        AccessControl::new(ok as fn(&mut Request) -> Response, credentials).rule(admin).rule(lan)
+    }
+
+    fn get(path: &str, authorization: Option<&str>, from: &str) -> Response {
+        let mut request = Request::new("GET", path);
+        request.remote_addr = Some(SocketAddr::new(from.parse().unwrap(), 5000));
+        if let Some(value) = authorization {
+            request.headers.add("Authorization", value);
+        }
+
+        protected().handle(&mut request)
+    }
+
+    #[test]
+    fn challenges_anonymous_requests() {
+        let response = get("/admin/users", None, "127.0.0.1");
+
+        assert_eq!(401, response.status);
+        assert_eq!(Some("Basic realm=\"Admin\", charset=\"UTF-8\""), response.header("WWW-Authenticate"));
+        assert_eq!(200, get("/administrator", None, "127.0.0.1").status);
+        assert_eq!(401, get("//admin/x", None, "127.0.0.1").status);
+        assert_eq!(401, get("/%2Fadmin/x", None, "127.0.0.1").status);
+    }
+
+    #[test]
+    fn checks_credentials_and_users() {
+        let alice = "Basic YWxpY2U6c2VjcmV0"; // alice:secret
+        let bob = "Basic Ym9iOmh1bnRlcjI="; // bob:hunter2
+        let wrong = "Basic YWxpY2U6Z3Vlc3M="; // alice:guess
+
+        let response = get("/admin", Some(alice), "127.0.0.1");
+        assert_eq!(200, response.status);
+        assert_eq!(Some(&b"alice"[..]), response.body.bytes());
+
+        assert_eq!(403, get("/admin", Some(bob), "127.0.0.1").status);
+        assert_eq!(401, get("/admin", Some(wrong), "127.0.0.1").status);
+    }
+
+    #[test]
+    fn applies_ip_lists() {
+        assert_eq!(200, get("/lan", None, "192.168.4.2").status);
+        assert_eq!(200, get("/lan", None, "::ffff:192.168.4.2").status);
+        assert_eq!(403, get("/lan", None, "192.168.1.13").status);
+        assert_eq!(403, get("/lan", None, "10.0.0.1").status);
+    }
+
+    #[test]
+    fn parses_credentials_file() {
+        let line = format!("carol:abc:{}\n", hash_password("abc", "pw"));
+        let credentials = Credentials::parse(&format!("# users\n{}", line)).unwrap();
+
+        assert!(credentials.verify("carol", "pw"));
+        assert!(!credentials.verify("carol", "nope"));
+        assert!(Credentials::parse("carol:abc").is_err());
+    }
+}
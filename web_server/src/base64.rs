@@ -0,0 +1,44 @@
+// Decoding of the standard base64 alphabet (RFC 4648), padding optional
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+
+    for c in text.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        bits = (bits << 6) | value;
+        bit_count += 6;
+
+        if bit_count >= 8 {
+            bit_count -= 8;
+            out.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+
+    if bit_count >= 6 {
+        return None; // a lone trailing character can't encode a whole byte
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_padded_and_unpadded() {
+        assert_eq!(Some(b"".to_vec()), decode(""));
+        assert_eq!(Some(b"f".to_vec()), decode("Zg=="));
+        assert_eq!(Some(b"fo".to_vec()), decode("Zm8="));
+        assert_eq!(Some(b"foobar".to_vec()), decode("Zm9vYmFy"));
+        assert_eq!(Some(b"fooba".to_vec()), decode("Zm9vYmE"));
+        assert_eq!(Some(b"alice:secret".to_vec()), decode("YWxpY2U6c2VjcmV0"));
+        assert_eq!(None, decode("not base64!"));
+    }
+}
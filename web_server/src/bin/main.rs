@@ -1,54 +1,209 @@
-use std::io::prelude::*;
-use std::fs::File;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
 use std::net::TcpListener;
-use std::net::TcpStream;
+use std::process;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+extern crate ctrlc;
 extern crate web_server;
 use web_server::ThreadPool;
+use web_server::auth::AccessControl;
+use web_server::cache::FileCache;
+use web_server::cgi::Cgi;
+use web_server::config::{Config, Section};
+use web_server::cors::Cors;
+use web_server::errors::{ErrorPages, Errors};
+use web_server::files::{Listing, StaticFiles};
+use web_server::handler::Handler;
+use web_server::http::{Request, Response};
+use web_server::limit::{ConnectionLimiter, RateLimiter};
+use web_server::log::AccessLog;
+use web_server::metrics::{Metrics, Monitor};
+use web_server::proxy::Proxy;
+use web_server::rewrite::Rewrites;
+use web_server::router::Router;
+use web_server::server::{Server, Shutdown};
+use web_server::session::Sessions;
+use web_server::tls::Certificates;
+use web_server::vhost::{self, VirtualHosts};
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:8080").expect("Couldn't open port");
-    let pool = ThreadPool::new(4);
+    let config = match env::args().nth(1) { // optional configuration file
+        Some(path) => or_exit(Config::load(&path), &format!("loading {}", path)),
+        None => Config::default(),
+    };
+
+    let hosts: Vec<&Section> = config.sections("host").collect();
+    let cache = Arc::new(match config.section("cache") {
+        Some(section) => or_exit(FileCache::from_section(section), "setting up the file cache"),
+        None => FileCache::new(64 * 1024 * 1024),
+    });
+
+    let mut handler: Box<dyn Handler> = if hosts.is_empty() {
+        let (hello, sleep) = (Arc::clone(&cache), Arc::clone(&cache));
+        let pages = Router::new().get("/", move |_: &mut Request| page(&hello, 200, "hello.html"))
+                                 .get("/sleep", move |_: &mut Request| {
+                                     thread::sleep(Duration::from_secs(5));
+                                     page(&sleep, 200, "hello.html")
+                                 })
+                                 .not_found(|_: &mut Request| Response::error(404, "Not found"));
+
+        rewrites(&config, routes(&config, pages, None), None)
+    } else {
+        let mut sites = VirtualHosts::new();
+
+        for host in hosts {
+            let name = or_exit(host.argument.clone().ok_or("expected [host name]"), "setting up virtual hosts");
+            let root = or_exit(host.get("root").ok_or("missing root"), &format!("setting up host {}", name));
+            let index: Vec<String> = match host.get_list("index") {
+                ref list if list.is_empty() => vec!["index.html".to_string()],
+                list => list,
+            };
+            let index: Vec<&str> = index.iter().map(|i| i.as_str()).collect();
+
+            let mut files = StaticFiles::new(root).index(&index).cache(Arc::clone(&cache));
+            for section in config.sections("listing").filter(|s| vhost::belongs_to(s, &name)) {
+                files = files.listing(or_exit(Listing::from_section(section), "setting up listings"));
+            }
+
+            let files = Router::new().not_found(files);
+            let site = rewrites(&config, routes(&config, files, Some(&name)), Some(&name));
+            let site = Errors::new(site, error_pages(&config, Some(&name)));
+            let mut site: Box<dyn Handler> = Box::new(site);
+
+            if let Some(log) = host.get("log") {
+                site = Box::new(or_exit(AccessLog::open(log, site), &format!("opening {}", log)));
+            }
+
+            let mut names = vec![name.as_str()];
+            let aliases = host.get_list("aliases");
+            names.extend(aliases.iter().map(|a| a.as_str()));
 
-    for stream in listener.incoming().take(2) { // Accept 2 connections and finish to try out graceful shutdown
-        let stream = stream.expect("Couldn't establish connection");
+            sites = if or_exit(host.get_bool("default"), "setting up virtual hosts").unwrap_or(false) {
+                sites.default_host(&names, site)
+            } else {
+                sites.host(&names, site)
+            };
+        }
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+        Box::new(sites)
+    };
+
+    if let Some(section) = config.section("session") {
+        handler = Box::new(or_exit(Sessions::from_section(handler, section), "setting up sessions"));
     }
 
-    println!("Shutting down.");    
+    let handler = or_exit(AccessControl::from_config(handler, &config), "setting up access control");
+    let handler = or_exit(Cors::from_config(handler, &config), "setting up CORS"); // preflights carry no credentials
+    let handler = or_exit(RateLimiter::from_config(handler, &config), "setting up rate limits");
+
+    let server = config.section("server");
+    let address = server.and_then(|s| s.get("address")).unwrap_or("127.0.0.1:8080");
+    let threads = or_exit(server.map_or(Ok(None), |s| s.get_parsed("threads")), "reading threads").unwrap_or(4);
+
+    let listener = TcpListener::bind(address).expect("Couldn't open port");
+    let pool = ThreadPool::new(threads);
+    let connections = or_exit(ConnectionLimiter::from_config(&config), "setting up connection limits");
+    let shutdown = Shutdown::new();
+
+    let metrics = Metrics::new().connections(connections.stats())
+                                .pool(pool.stats())
+                                .cache(cache.stats())
+                                .rate_limits(handler.stats())
+                                .shutdown(shutdown.clone()); // not ready while draining
+    let handler = or_exit(Monitor::from_section(handler, metrics, config.section("metrics")), "setting up metrics"); // checked first
+
+    let mut errors = error_pages(&config, None);
+    if config.section("host").is_none() && config.section("errors").is_none() {
+        errors = errors.page(404, "404.html");
+    }
+
+    let timeout = or_exit(server.map_or(Ok(None), |s| s.get_parsed("timeout")), "reading timeout").unwrap_or(30);
+    let keep_alive = or_exit(server.map_or(Ok(None), |s| s.get_parsed("keep_alive")), "reading keep_alive").unwrap_or(5);
+
+    let mut server = Server::new(listener, pool, handler).connection_limiter(connections)
+                                                         .error_pages(errors)
+                                                         .request_timeout(Duration::from_secs(timeout))
+                                                         .keep_alive(Duration::from_secs(keep_alive))
+                                                         .shutdown(shutdown.clone());
+
+    if let Some(certificates) = or_exit(Certificates::from_config(&config), "loading certificates") {
+        let tls = config.section("tls").unwrap();
+        let address = tls.get("address").unwrap_or("127.0.0.1:8443");
+        let listener = TcpListener::bind(address).expect("Couldn't open HTTPS port");
+
+        server = server.https(listener, or_exit(certificates.server_config(), "setting up TLS"))
+                       .redirect_to_https(or_exit(tls.get_bool("redirect"), "setting up TLS").unwrap_or(false));
+    }
+
+    // Ctrl-C or SIGTERM stops taking connections, and the server is dropped once those it has are served
+    or_exit(ctrlc::set_handler(move || shutdown.request()), "handling signals");
+    server.run();
+    println!("Shutting down.");
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 512];
+// Adds the proxy and CGI routes of the given site (or of every site when None) to its router
+fn routes(config: &Config, mut router: Router, host: Option<&str>) -> Router {
+    let applies = |section: &&Section| host.is_none_or(|host| vhost::belongs_to(section, host));
 
-    stream.read(&mut buffer).expect("Failed reading request");
+    for section in config.sections("proxy").filter(applies) {
+        let proxy = or_exit(Proxy::from_section(section), "setting up proxy");
+        let prefix = proxy.prefix().to_string();
+        router = router.prefix(&prefix, proxy);
+    }
 
-    println!("[Request]\n{}", String::from_utf8_lossy(&buffer));
+    for section in config.sections("cgi").filter(applies) {
+        let cgi = or_exit(Cgi::from_section(section), "setting up CGI");
+        let prefix = cgi.prefix().to_string();
+        router = router.prefix(&prefix, cgi);
+    }
 
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
+    router
+}
 
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK\r\n\r\n", "hello.html")
-    } else if buffer.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK\r\n\r\n", "hello.html")        
-    } else {
-        ("HTTP/1.1 404 NOT FOUND\r\n\r\n", "404.html")
-    };
+// Applies the site's [rewrite] rules, or those for every site, before routing
+fn rewrites(config: &Config, router: Router, host: Option<&str>) -> Box<dyn Handler> {
+    let (shared, own): (Vec<&Section>, Vec<&Section>) = config.sections("rewrite").partition(|s| s.get("host").is_none());
+    let section = own.into_iter().find(|s| host.is_some_and(|h| vhost::belongs_to(s, h)))
+                     .or_else(|| shared.into_iter().next());
 
-    let mut file = File::open(filename).unwrap();
-    let mut contents = String::new();
+    match section {
+        Some(section) => Box::new(or_exit(Rewrites::from_section(router, section), "loading rewrite rules")),
+        None => Box::new(router),
+    }
+}
 
-    file.read_to_string(&mut contents).unwrap();
+// Pages for every site followed by those of the given site, which take precedence
+fn error_pages(config: &Config, host: Option<&str>) -> ErrorPages {
+    let (shared, own): (Vec<&Section>, Vec<&Section>) = config.sections("errors").partition(|s| s.get("host").is_none());
+    let own = own.into_iter().filter(|s| host.is_some_and(|h| vhost::belongs_to(s, h)));
 
-    let response = format!("{}{}", status_line, contents);
+    shared.into_iter().chain(own).fold(ErrorPages::new(), |pages, section| or_exit(pages.from_section(section), "setting up error pages"))
+}
+
+fn or_exit<T, E: Display>(result: Result<T, E>, what: &str) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("Problem {}: {}", what, err);
+        process::exit(1);
+    })
+}
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
-}
\ No newline at end of file
+fn page(cache: &FileCache, status: u16, filename: &str) -> Response {
+    let contents = match cache.get(Path::new(filename)) {
+        Ok(Some(file)) => Ok(file.contents.to_vec()),
+        Ok(None) => fs::read(filename),
+        Err(e) => Err(e),
+    };
+
+    match contents {
+        Ok(contents) => Response::html(status, contents),
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", filename, e);
+            Response::error(500, "Couldn't read page")
+        },
+    }
+}
@@ -0,0 +1,281 @@
+// In-memory cache of static files, bounded by the total size of what it holds
+//
+//     [cache]
+//     size = 64M        # for every site together, 0 to turn caching off
+//     max_file = 1M     # larger files are always read from disk
+//     gzip = yes        # also keep a compressed copy of text files
+//
+// Entries are checked against the file's modification time and size on each use, and the
+// least recently used ones make room for new ones.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use config::{ConfigError, Section};
+use gzip;
+use sha256;
+
+// What a cached file holds; the contents are shared with the responses sending them
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub contents: Arc<Vec<u8>>,
+    pub gzip: Option<Arc<Vec<u8>>>,
+    pub etag: String,
+    pub modified: SystemTime,
+}
+
+impl CachedFile {
+    fn size(&self) -> usize {
+        self.contents.len() + self.gzip.as_ref().map_or(0, |g| g.len())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub evictions: AtomicU64,
+    pub bytes: AtomicU64, // currently held
+}
+
+struct Entry {
+    file: CachedFile,
+    len: u64, // of the file when it was read
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<PathBuf, Entry>,
+    by_use: BTreeMap<u64, PathBuf>, // least recently used first
+    used: usize,
+    clock: u64,
+}
+
+impl State {
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.by_use.remove(&entry.last_used);
+            self.used -= entry.file.size();
+        }
+    }
+
+    fn touch(&mut self, path: &Path) {
+        self.clock += 1;
+        let clock = self.clock;
+
+        if let Some(entry) = self.entries.get_mut(path) {
+            self.by_use.remove(&entry.last_used);
+            entry.last_used = clock;
+            self.by_use.insert(clock, path.to_path_buf());
+        }
+    }
+}
+
+pub struct FileCache {
+    capacity: usize,
+    max_file: usize,
+    gzip: bool,
+    state: Mutex<State>,
+    stats: Arc<CacheStats>,
+}
+
+impl FileCache {
+    pub fn new(capacity: usize) -> FileCache {
+        FileCache {
+            capacity,
+            max_file: capacity.min(1024 * 1024),
+            gzip: false,
+            state: Mutex::new(State::default()),
+            stats: Arc::new(CacheStats::default()),
+        }
+    }
+
+    pub fn max_file(mut self, max_file: usize) -> FileCache {
+        self.max_file = max_file.min(self.capacity);
+        self
+    }
+
+    pub fn gzip(mut self, gzip: bool) -> FileCache {
+        self.gzip = gzip;
+        self
+    }
+
+    pub fn from_section(section: &Section) -> Result<FileCache, ConfigError> {
+        let size = |key: &str, default: usize| match section.get(key) {
+            Some(text) => parse_size(text).ok_or_else(|| ConfigError::Invalid {
+                line: section.line,
+                message: format!("invalid {} '{}', expected bytes with an optional K, M or G", key, text),
+            }),
+            None => Ok(default),
+        };
+
+        Ok(FileCache::new(size("size", 64 * 1024 * 1024)?).max_file(size("max_file", 1024 * 1024)?)
+                                                          .gzip(section.get_bool("gzip")?.unwrap_or(false)))
+    }
+
+    // Shared counters, which keep being updated once the cache is in use
+    pub fn stats(&self) -> Arc<CacheStats> {
+        Arc::clone(&self.stats)
+    }
+
+    // The file's contents, from memory if they haven't changed since they were cached; None if
+    // the file is too large to cache, so it's better streamed from disk
+    pub fn get(&self, path: &Path) -> io::Result<Option<CachedFile>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?;
+
+        if metadata.len() > self.max_file as u64 {
+            self.state.lock().unwrap().remove(path);
+            return Ok(None);
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            let fresh = state.entries.get(path).map(|e| e.file.modified == modified && e.len == metadata.len());
+
+            match fresh {
+                Some(true) => {
+                    self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                    state.touch(path);
+                    return Ok(Some(state.entries[path].file.clone()));
+                },
+                Some(false) => state.remove(path),
+                None => {},
+            }
+        }
+
+        // Read without holding the lock, so other files can be served meanwhile
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        let contents = fs::read(path)?;
+        let file = CachedFile {
+            gzip: if self.gzip && compressible(path) { compressed(&contents) } else { None },
+            etag: format!("\"{}\"", &sha256::hex(&sha256::digest(&contents))[..16]),
+            contents: Arc::new(contents),
+            modified,
+        };
+
+        self.insert(path, file.clone(), metadata.len());
+        Ok(Some(file))
+    }
+
+    fn insert(&self, path: &Path, file: CachedFile, len: u64) {
+        if file.size() > self.capacity {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.remove(path); // another thread may have cached it meanwhile
+
+        while state.used + file.size() > self.capacity {
+            let oldest = match state.by_use.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            state.remove(&oldest);
+            self.stats.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        state.used += file.size();
+        state.entries.insert(path.to_path_buf(), Entry { file, len, last_used: 0 });
+        state.touch(path);
+        self.stats.bytes.store(state.used as u64, Ordering::Relaxed);
+    }
+}
+
+// Only worth keeping when it saves something
+fn compressed(contents: &[u8]) -> Option<Arc<Vec<u8>>> {
+    let gzip = gzip::compress(contents);
+
+    if gzip.len() < contents.len() {
+        Some(Arc::new(gzip))
+    } else {
+        None
+    }
+}
+
+fn compressible(path: &Path) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+
+    matches!(extension.as_deref(), Some("html") | Some("htm") | Some("css") | Some("js") | Some("mjs") | Some("json")
+                                   | Some("txt") | Some("md") | Some("xml") | Some("svg") | Some("wasm"))
+}
+
+// Bytes with an optional K, M or G suffix (powers of 1024): "64M"
+pub fn parse_size(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let (number, unit) = match text.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&text[..i], c.to_ascii_uppercase()),
+        _ => (text, 'B'),
+    };
+
+    let multiplier = match unit {
+        'B' => 1,
+        'K' => 1024,
+        'M' => 1024 * 1024,
+        'G' => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    number.trim().parse::<usize>().ok().and_then(|n| n.checked_mul(multiplier))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+    use temp::TempDir;
+
+    #[test]
+    fn caches_until_file_changes() {
+        let dir = TempDir::new("cache");
+        let path = dir.join("page.html");
+        fs::write(&path, "<p>one</p>".repeat(10)).unwrap();
+
+        let cache = FileCache::new(1024).gzip(true);
+        let first = cache.get(&path).unwrap().unwrap();
+        let second = cache.get(&path).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&first.contents, &second.contents));
+        assert!(first.gzip.unwrap().len() < 100);
+
+        fs::write(&path, "<p>two</p>").unwrap();
+        File::options().write(true).open(&path).unwrap()
+                       .set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        let third = cache.get(&path).unwrap().unwrap();
+        assert_eq!(b"<p>two</p>".to_vec(), *third.contents);
+        assert_ne!(first.etag, third.etag);
+
+        let stats = cache.stats();
+        assert_eq!((1, 2), (stats.hits.load(Ordering::Relaxed), stats.misses.load(Ordering::Relaxed)));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let dir = TempDir::new("cache");
+        for name in &["a", "b", "c"] {
+            fs::write(dir.join(name), [0u8; 40]).unwrap();
+        }
+        fs::write(dir.join("big"), [0u8; 60]).unwrap();
+
+        let cache = FileCache::new(100).max_file(50);
+        cache.get(&dir.join("a")).unwrap();
+        cache.get(&dir.join("b")).unwrap();
+        cache.get(&dir.join("a")).unwrap(); // b is now the least recently used
+        cache.get(&dir.join("c")).unwrap();
+        assert!(cache.get(&dir.join("big")).unwrap().is_none());
+
+        let stats = cache.stats();
+        assert_eq!((1, 80), (stats.evictions.load(Ordering::Relaxed), stats.bytes.load(Ordering::Relaxed)));
+        cache.get(&dir.join("a")).unwrap();
+        assert_eq!(2, stats.hits.load(Ordering::Relaxed));
+
+        assert_eq!(Some(64 * 1024 * 1024), parse_size("64M"));
+        assert_eq!(None, parse_size("lots"));
+    }
+}
@@ -0,0 +1,397 @@
+// CGI/1.1 (RFC 3875): runs executables from a directory to answer requests under a prefix
+//
+//     [cgi /cgi-bin]
+//     directory = cgi-bin
+//     timeout = 10            # seconds a script may run
+//     max_output = 1048576    # bytes a script may write
+//
+// /cgi-bin/report.sh/extra?x=1 runs cgi-bin/report.sh with PATH_INFO=/extra and QUERY_STRING=x=1.
+// The request body goes to the script's standard input and its standard output is the response:
+// a header block (Status, Location, Content-Type...), an empty line, then the body.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::{ConfigError, Section};
+use handler::Handler;
+use http::{Headers, ParseError, Request, Response};
+
+pub struct Cgi {
+    prefix: String,
+    directory: PathBuf,
+    timeout: Duration,
+    max_output: usize,
+}
+
+enum Failure {
+    Timeout,
+    TooMuchOutput,
+    Io(io::Error),
+    BadOutput(String),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Failure {
+        Failure::Io(e)
+    }
+}
+
+impl Cgi {
+    pub fn new<P: Into<PathBuf>>(prefix: &str, directory: P) -> Cgi {
+        Cgi {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            directory: directory.into(),
+            timeout: Duration::from_secs(10),
+            max_output: 1024 * 1024,
+        }
+    }
+
+    pub fn from_section(section: &Section) -> Result<Cgi, ConfigError> {
+        let invalid = |message: &str| ConfigError::Invalid { line: section.line, message: message.to_string() };
+
+        let prefix = match section.argument {
+            Some(ref prefix) if prefix.starts_with('/') => prefix,
+            _ => return Err(invalid("expected [cgi /path]")),
+        };
+        let directory = section.get("directory").ok_or_else(|| invalid("missing directory"))?;
+
+        let mut cgi = Cgi::new(prefix, directory);
+        if let Some(timeout) = section.get_parsed("timeout")? {
+            cgi = cgi.timeout(Duration::from_secs(timeout));
+        }
+        if let Some(max_output) = section.get_parsed("max_output")? {
+            cgi = cgi.max_output(max_output);
+        }
+
+        Ok(cgi)
+    }
+
+    pub fn prefix(&self) -> &str {
+        if self.prefix.is_empty() { "/" } else { &self.prefix }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Cgi {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_output(mut self, max_output: usize) -> Cgi {
+        self.max_output = max_output;
+        self
+    }
+
+    // Script file name and PATH_INFO, or None if the path doesn't name a runnable script
+    fn locate(&self, path: &str) -> Option<(String, String)> {
+        let rest = path.get(self.prefix.len()..)?.trim_start_matches('/');
+        let (name, path_info) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+
+        // No climbing out of the directory nor running hidden files
+        if name.is_empty() || name.starts_with('.') || name.contains('\\') {
+            return None;
+        }
+
+        let script = self.directory.join(name);
+        if !is_executable(&script) {
+            return None;
+        }
+
+        Some((name.to_string(), path_info.to_string()))
+    }
+
+    fn command(&self, request: &Request, name: &str, path_info: &str) -> Command {
+        let mut command = Command::new(self.directory.join(name).canonicalize().unwrap_or_else(|_| self.directory.join(name)));
+        command.current_dir(&self.directory)
+               .env_clear()
+               .stdin(Stdio::piped())
+               .stdout(Stdio::piped())
+               .stderr(Stdio::inherit()); // script errors end up in the server log
+
+        if let Some(path) = env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+
+        let host = request.header("Host").unwrap_or("localhost");
+        let (server_name, server_port) = match host.rfind(':') {
+            Some(i) if !host.ends_with(']') => (&host[..i], &host[i + 1..]),
+            _ => (host, if request.secure { "443" } else { "80" }),
+        };
+
+        command.env("GATEWAY_INTERFACE", "CGI/1.1")
+               .env("SERVER_SOFTWARE", concat!("web_server/", env!("CARGO_PKG_VERSION")))
+               .env("SERVER_PROTOCOL", &request.version)
+               .env("SERVER_NAME", server_name)
+               .env("SERVER_PORT", server_port)
+               .env("REQUEST_METHOD", &request.method)
+               .env("REQUEST_URI", &request.target)
+               .env("SCRIPT_NAME", format!("{}/{}", self.prefix, name))
+               .env("PATH_INFO", path_info)
+               .env("QUERY_STRING", request.query.as_ref().map_or("", |q| q.as_str()));
+
+        if !path_info.is_empty() {
+            let translated = self.directory.join(path_info.trim_start_matches('/'));
+            command.env("PATH_TRANSLATED", translated);
+        }
+        if request.secure {
+            command.env("HTTPS", "on");
+        }
+        if let Some(body) = request.body.bytes().filter(|body| !body.is_empty()) {
+            command.env("CONTENT_LENGTH", body.len().to_string());
+        }
+        if let Some(content_type) = request.header("Content-Type") {
+            command.env("CONTENT_TYPE", content_type);
+        }
+        if let Some(addr) = request.remote_addr {
+            command.env("REMOTE_ADDR", addr.ip().to_string())
+                   .env("REMOTE_PORT", addr.port().to_string());
+        }
+        if let Some(ref user) = request.remote_user {
+            command.env("AUTH_TYPE", "Basic").env("REMOTE_USER", user);
+        }
+
+        // The rest of the headers as HTTP_*, except credentials which scripts have no business seeing,
+        // and Proxy, whose HTTP_PROXY many programs would take as the proxy to use ("httpoxy")
+        for (name, value) in request.headers.iter() {
+            if ["Authorization", "Content-Length", "Content-Type", "Proxy"].iter().any(|n| name.eq_ignore_ascii_case(n)) {
+                continue;
+            }
+            let variable = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+            command.env(variable, value);
+        }
+
+        command
+    }
+
+    fn run(&self, request: &Request, name: &str, path_info: &str) -> Result<Response, Failure> {
+        let deadline = Instant::now() + self.timeout;
+        let mut child = self.command(request, name, path_info).spawn()?;
+
+        // Feed and drain the script from their own threads so neither pipe can fill up and block it
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        let body = request.body.bytes().unwrap_or_default().to_vec();
+        thread::spawn(move || {
+            let _ = stdin.write_all(&body); // scripts don't have to read it all
+        });
+
+        let stdout = child.stdout.take().expect("piped stdout");
+        let limit = self.max_output;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let result = stdout.take(limit as u64 + 1).read_to_end(&mut output).map(|_| output);
+            let _ = sender.send(result);
+        });
+
+        let output = match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(output) => output,
+            Err(_) => {
+                kill(&mut child);
+                return Err(Failure::Timeout);
+            },
+        };
+
+        let output = match output {
+            Ok(ref output) if output.len() > limit => {
+                kill(&mut child);
+                return Err(Failure::TooMuchOutput);
+            },
+            Ok(output) => output,
+            Err(e) => {
+                kill(&mut child);
+                return Err(Failure::Io(e));
+            },
+        };
+
+        // Output is complete; give the script until the deadline to actually exit
+        loop {
+            if let Some(status) = child.try_wait()? {
+                if !status.success() && output.is_empty() {
+                    return Err(Failure::BadOutput(format!("script exited with {}", status)));
+                }
+                break;
+            }
+            if Instant::now() >= deadline {
+                kill(&mut child);
+                return Err(Failure::Timeout);
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        parse_output(&output)
+    }
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait(); // reap it
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata().map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// Header block up to the first empty line, then the body
+fn parse_output(output: &[u8]) -> Result<Response, Failure> {
+    let mut headers = Headers::new();
+    let mut rest = output;
+
+    loop {
+        let end = rest.iter().position(|&b| b == b'\n')
+                      .ok_or_else(|| Failure::BadOutput("header block not terminated".to_string()))?;
+        let line = String::from_utf8_lossy(&rest[..end]).trim_end_matches('\r').to_string();
+        rest = &rest[end + 1..];
+
+        if line.is_empty() {
+            break;
+        }
+
+        match line.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => headers.add(name.trim(), value.trim()),
+            _ => return Err(Failure::BadOutput(format!("malformed header line '{}'", line))),
+        }
+    }
+
+    let status = match headers.get("Status") {
+        Some(status) => status.split_whitespace().next()
+                              .and_then(|code| code.parse::<u16>().ok())
+                              .filter(|code| (100..600).contains(code))
+                              .ok_or_else(|| Failure::BadOutput(format!("invalid Status '{}'", status)))?,
+        None if headers.contains("Location") => 302,
+        None => 200,
+    };
+    headers.remove("Status");
+
+    if status != 302 && !headers.contains("Content-Type") && !rest.is_empty() {
+        return Err(Failure::BadOutput("missing Content-Type".to_string()));
+    }
+
+    let mut response = Response::new(status).with_body(rest.to_vec());
+    response.headers = headers;
+
+    Ok(response)
+}
+
+impl Handler for Cgi {
+    fn handle(&self, request: &mut Request) -> Response {
+        let (name, path_info) = match self.locate(&request.path) {
+            Some(found) => found,
+            None => return Response::error(404, "No such script"),
+        };
+
+        // Scripts are told the body's length up front, so it's read whole first
+        match request.buffer_body() {
+            Ok(_) => {},
+            Err(ParseError::TooLarge) => return Response::error(413, "Request too large"),
+            Err(e) => return Response::error(400, format!("Couldn't read the request body: {}", e)),
+        }
+
+        match self.run(request, &name, &path_info) {
+            Ok(response) => response,
+            Err(Failure::Timeout) => {
+                eprintln!("CGI script {} timed out", name);
+                Response::error(504, "Script timed out")
+            },
+            Err(Failure::TooMuchOutput) => {
+                eprintln!("CGI script {} wrote more than {} bytes", name, self.max_output);
+                Response::error(502, "Script output too large")
+            },
+            Err(Failure::Io(e)) => {
+                eprintln!("Couldn't run CGI script {}: {}", name, e);
+                Response::error(500, "Couldn't run script")
+            },
+            Err(Failure::BadOutput(msg)) => {
+                eprintln!("CGI script {}: {}", name, msg);
+                Response::error(502, "Bad script output")
+            },
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use http::Body;
+    use temp::TempDir;
+
+    fn script(directory: &Path, name: &str, body: &str) {
+        let path = directory.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn scripts() -> TempDir {
+        let directory = TempDir::new("cgi");
+
+        script(&directory, "echo.sh", "printf 'Content-Type: text/plain\\r\\nX-Script: echo\\r\\n\\r\\n'\n\
+                                       echo \"$REQUEST_METHOD $SCRIPT_NAME $PATH_INFO $QUERY_STRING $CONTENT_LENGTH\"\n\
+                                       echo \"$HTTP_X_TEST $REMOTE_ADDR ${HTTP_AUTHORIZATION:-hidden} ${HTTP_PROXY:-unset}\"\n\
+                                       cat");
+        script(&directory, "missing.sh", "echo 'Status: 404 Not Here'\necho 'Content-Type: text/plain'\necho\necho gone");
+        script(&directory, "redirect.sh", "echo 'Location: /elsewhere'\necho");
+        script(&directory, "slow.sh", "sleep 5");
+        script(&directory, "chatty.sh", "echo 'Content-Type: text/plain'\necho\nyes | head -c 10000");
+        script(&directory, "broken.sh", "echo 'no headers here'");
+        fs::write(directory.join("plain.txt"), "not executable").unwrap();
+
+        directory
+    }
+
+    fn run(cgi: &Cgi, method: &str, target: &str) -> Response {
+        let mut request = Request::new(method, target);
+        request.headers.add("X-Test", "yes");
+        request.headers.add("Authorization", "Basic c2VjcmV0");
+        request.headers.add("Proxy", "http://attacker.example:8080");
+        request.remote_addr = Some("10.1.2.3:4000".parse().unwrap());
+        request.body = Body::from("the body");
+
+        cgi.handle(&mut request)
+    }
+
+    #[test]
+    fn runs_scripts() {
+        let directory = scripts();
+        let cgi = Cgi::new("/cgi-bin", &directory).max_output(5000).timeout(Duration::from_millis(500));
+
+        let response = run(&cgi, "POST", "/cgi-bin/echo.sh/extra/path?x=1");
+        assert_eq!(200, response.status);
+        assert_eq!(Some("echo"), response.header("X-Script"));
+        assert_eq!(Some(&b"POST /cgi-bin/echo.sh /extra/path x=1 8\nyes 10.1.2.3 hidden unset\nthe body"[..]),
+                   response.body.bytes());
+
+        let missing = run(&cgi, "GET", "/cgi-bin/missing.sh");
+        assert_eq!((404, Some(&b"gone\n"[..])), (missing.status, missing.body.bytes()));
+
+        let redirect = run(&cgi, "GET", "/cgi-bin/redirect.sh");
+        assert_eq!((302, Some("/elsewhere")), (redirect.status, redirect.header("Location")));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let directory = scripts();
+        let cgi = Cgi::new("/cgi-bin", &directory).max_output(5000).timeout(Duration::from_millis(500));
+
+        assert_eq!(504, run(&cgi, "GET", "/cgi-bin/slow.sh").status);
+        assert_eq!(502, run(&cgi, "GET", "/cgi-bin/chatty.sh").status);
+        assert_eq!(502, run(&cgi, "GET", "/cgi-bin/broken.sh").status);
+        assert_eq!(404, run(&cgi, "GET", "/cgi-bin/plain.txt").status);
+        assert_eq!(200, run(&cgi, "GET", "/cgi-bin/../cgi-bin/echo.sh").status); // resolved when parsed
+        assert_eq!(404, run(&cgi, "GET", "/cgi-bin/").status);
+    }
+}
@@ -0,0 +1,452 @@
+// HTTP/1.1 client, reusing connections to the servers it talks to
+//
+//     let client = Client::new();
+//     let response = client.get("http://127.0.0.1:8080/")?;
+//     println!("{} {}", response.status, response.text());
+//
+// Redirects are followed up to a limit, and responses come back with their whole body.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io::{self, BufReader, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use http::{self, Body, Framing, Headers, ParseError, Request};
+
+const MAX_IDLE_PER_HOST: usize = 4;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Timeout,
+    InvalidUrl(String),
+    BadResponse(String),
+    TooManyRedirects,
+    TooLarge, // the body went over the client's limit
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Io(ref e) => write!(f, "I/O error: {}", e),
+            ClientError::Timeout => write!(f, "timed out"),
+            ClientError::InvalidUrl(ref url) => write!(f, "invalid URL '{}'", url),
+            ClientError::BadResponse(ref msg) => write!(f, "bad response: {}", msg),
+            ClientError::TooManyRedirects => write!(f, "too many redirects"),
+            ClientError::TooLarge => write!(f, "response too large"),
+        }
+    }
+}
+
+impl error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+            _ => ClientError::Io(e),
+        }
+    }
+}
+
+impl From<ParseError> for ClientError {
+    fn from(e: ParseError) -> ClientError {
+        match e {
+            ParseError::Io(e) => ClientError::from(e),
+            ParseError::Closed => ClientError::BadResponse("connection closed".to_string()),
+            ParseError::BadRequest(msg) => ClientError::BadResponse(msg),
+            ParseError::TooLarge => ClientError::TooLarge,
+        }
+    }
+}
+
+// An http:// URL split into what's needed to send a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub target: String, // path and query
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url, ClientError> {
+        let invalid = || ClientError::InvalidUrl(url.to_string());
+
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        let target = target.split('#').next().unwrap_or("/").to_string();
+
+        let (host, port) = match authority.rfind(':') {
+            Some(i) if !authority[i..].contains(']') => (&authority[..i], authority[i + 1..].parse().map_err(|_| invalid())?),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Url { host: host.to_string(), port, target })
+    }
+
+    // Where a Location header points, relative to this URL
+    pub fn join(&self, location: &str) -> Result<Url, ClientError> {
+        if location.contains("://") {
+            return Url::parse(location);
+        }
+
+        let target = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            let path = self.target.split('?').next().unwrap_or("/");
+            format!("{}{}", &path[..path.rfind('/').map_or(0, |i| i + 1)], location)
+        };
+
+        Ok(Url { target, ..self.clone() })
+    }
+
+    fn authority(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.target)
+    }
+}
+
+// A response with its whole body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    pub url: Url, // where it came from, after any redirects
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+pub struct Client {
+    timeout: Duration,
+    max_redirects: usize,
+    max_body: usize,
+    idle: Mutex<HashMap<String, Vec<BufReader<TcpStream>>>>, // by host and port
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            timeout: Duration::from_secs(30),
+            max_redirects: 5,
+            max_body: 16 * 1024 * 1024,
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // For connecting and for each read or write
+    pub fn timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    // Zero to hand redirects back instead of following them
+    pub fn max_redirects(mut self, max_redirects: usize) -> Client {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    pub fn max_body(mut self, max_body: usize) -> Client {
+        self.max_body = max_body;
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response, ClientError> {
+        self.request(Request::new("GET", url))
+    }
+
+    pub fn post<B: Into<Vec<u8>>>(&self, url: &str, content_type: &str, body: B) -> Result<Response, ClientError> {
+        let mut request = Request::new("POST", url);
+        request.headers.set("Content-Type", content_type);
+        request.body = Body::Bytes(body.into());
+
+        self.request(request)
+    }
+
+    // Sends a request whose target is an absolute URL, following redirects
+    pub fn request(&self, mut request: Request) -> Result<Response, ClientError> {
+        let mut url = Url::parse(&request.target)?;
+        let mut redirects = 0;
+
+        loop {
+            let response = self.send(&url, &mut request)?;
+
+            let location = match response.header("Location") {
+                Some(location) if is_redirect(response.status) && self.max_redirects > 0 => location,
+                _ => return Ok(response),
+            };
+
+            redirects += 1;
+            if redirects > self.max_redirects {
+                return Err(ClientError::TooManyRedirects);
+            }
+
+            // Only 307 and 308 ask to repeat the request as it was, which a streamed body can't be
+            if response.status != 307 && response.status != 308 && request.method != "HEAD" {
+                request.method = "GET".to_string();
+                request.body = Body::empty();
+                request.headers.remove("Content-Type");
+            } else if request.body.bytes().is_none() {
+                return Ok(response);
+            }
+
+            // Credentials are only for the host they were given for
+            let next = url.join(location)?;
+            if !next.host.eq_ignore_ascii_case(&url.host) || next.port != url.port {
+                for name in &["Authorization", "Cookie", "Proxy-Authorization"] {
+                    request.headers.remove(name);
+                }
+            }
+            url = next;
+        }
+    }
+
+    fn send(&self, url: &Url, request: &mut Request) -> Result<Response, ClientError> {
+        let (path, query) = http::split_target(&url.target);
+        request.target = url.target.clone();
+        request.path = path;
+        request.query = query;
+        request.headers.set("Host", &url.authority());
+        request.headers.set("Connection", "keep-alive");
+
+        // A pooled connection may have been closed by the server meanwhile, so try a new one then,
+        // unless the request may have got there and would do something twice if repeated
+        if let Some(mut connection) = self.idle_connection(url) {
+            let repeatable = request.body.bytes().is_some();
+
+            match request.write_to(connection.get_mut()) {
+                Ok(()) => match self.receive(connection, url, &request.method) {
+                    Err(ClientError::Io(_)) | Err(ClientError::BadResponse(_)) if repeatable && is_idempotent(&request.method) => {},
+                    result => return result,
+                },
+                Err(_) if repeatable => {},
+                Err(e) => return Err(ClientError::from(e)),
+            }
+        }
+
+        let mut connection = self.connect(url)?;
+        request.write_to(connection.get_mut())?;
+        self.receive(connection, url, &request.method)
+    }
+
+    fn receive(&self, mut connection: BufReader<TcpStream>, url: &Url, method: &str) -> Result<Response, ClientError> {
+        let (head, framing) = http::Response::read_head(&mut connection, method)?;
+        let mut body = Vec::new();
+        let limit = self.max_body as u64 + 1;
+
+        match framing {
+            Framing::Empty => {},
+            Framing::Chunked => {
+                http::ChunkedReader::new(&mut connection).take(limit).read_to_end(&mut body).map_err(from_body_error)?;
+            },
+            Framing::Length(length) => {
+                if length > self.max_body as u64 {
+                    return Err(ClientError::TooLarge);
+                }
+                (&mut connection).take(length).read_to_end(&mut body)?;
+                if (body.len() as u64) < length {
+                    return Err(ClientError::BadResponse("body shorter than its Content-Length".to_string()));
+                }
+            },
+            Framing::UntilClose => {
+                (&mut connection).take(limit).read_to_end(&mut body)?;
+            },
+        }
+        if body.len() > self.max_body {
+            return Err(ClientError::TooLarge);
+        }
+
+        let reusable = framing != Framing::UntilClose
+                       && !head.header("Connection").is_some_and(|c| c.eq_ignore_ascii_case("close"))
+                       && connection.buffer().is_empty();
+        if reusable {
+            self.release(url, connection);
+        }
+
+        Ok(Response { status: head.status, headers: head.headers, body, url: url.clone() })
+    }
+
+    fn connect(&self, url: &Url) -> Result<BufReader<TcpStream>, ClientError> {
+        let addresses = (url.host.trim_start_matches('[').trim_end_matches(']'), url.port).to_socket_addrs()?;
+        let mut last_error = ClientError::InvalidUrl(url.to_string());
+
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(BufReader::new(stream));
+                },
+                Err(e) => last_error = ClientError::from(e),
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn idle_connection(&self, url: &Url) -> Option<BufReader<TcpStream>> {
+        self.idle.lock().unwrap().get_mut(&url.authority()).and_then(|c| c.pop())
+    }
+
+    fn release(&self, url: &Url, connection: BufReader<TcpStream>) {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.entry(url.authority()).or_default();
+
+        if connections.len() < MAX_IDLE_PER_HOST {
+            connections.push(connection);
+        }
+    }
+}
+
+fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+// Methods that mean the same when sent twice
+fn is_idempotent(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE")
+}
+
+fn from_body_error(e: io::Error) -> ClientError {
+    if e.kind() == io::ErrorKind::InvalidData {
+        ClientError::BadResponse(e.to_string())
+    } else {
+        ClientError::from(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use http;
+    use server;
+
+    // Serves 'connections' connections in the background, one request each
+    fn serve<F>(connections: usize, handler: F) -> String
+        where F: Fn(&mut Request) -> http::Response + Send + Sync + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                server::handle_connection(stream.unwrap(), &handler).unwrap();
+            }
+        });
+
+        address
+    }
+
+    fn credentials(request: &mut Request) -> http::Response {
+        http::Response::text(200, format!("{:?} {:?}", request.header("Authorization"), request.header("Cookie")))
+    }
+
+    #[test]
+    fn parses_and_joins_urls() {
+        let url = Url::parse("http://example.com:8080/docs/intro.html?v=2#top").unwrap();
+        assert_eq!(("example.com", 8080, "/docs/intro.html?v=2"), (url.host.as_str(), url.port, url.target.as_str()));
+        assert_eq!("http://example.com:8080/docs/next.html", url.join("next.html").unwrap().to_string());
+        assert_eq!("http://example.com:8080/", url.join("/").unwrap().to_string());
+        assert_eq!("http://other.org/x", url.join("http://other.org/x").unwrap().to_string());
+
+        assert_eq!("/?q=1", Url::parse("http://[::1]?q=1").unwrap().target);
+        assert!(Url::parse("https://example.com/").is_err());
+        assert!(Url::parse("http://:80/").is_err());
+    }
+
+    #[test]
+    fn keeps_credentials_to_their_host() {
+        let elsewhere = format!("http://{}/", serve(1, credentials));
+        let here = serve(4, move |request: &mut Request| match request.path.as_str() {
+            "/away" => http::Response::new(302).with_header("Location", &elsewhere),
+            "/home" => http::Response::new(302).with_header("Location", "/landing"),
+            _ => credentials(request),
+        });
+
+        let get = |path: &str| {
+            let mut request = Request::new("GET", &format!("http://{}{}", here, path));
+            request.headers.add("Authorization", "Basic c2VjcmV0");
+            request.headers.add("Cookie", "id=1");
+            Client::new().request(request).unwrap().text()
+        };
+        assert_eq!("Some(\"Basic c2VjcmV0\") Some(\"id=1\")", get("/home"));
+        assert_eq!("None None", get("/away"));
+    }
+
+    // Answers the first request on each connection and drops the connection on the second, as a
+    // server closing idle connections may, keeping track of the methods it got
+    fn flaky_server() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&received);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                for answered in 0..2 {
+                    let request = match Request::read_from(&mut reader) {
+                        Ok(request) => request,
+                        Err(_) => break,
+                    };
+                    log.lock().unwrap().push(request.method);
+                    if answered == 0 {
+                        reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+                    }
+                }
+            }
+        });
+
+        (address, received)
+    }
+
+    #[test]
+    fn repeats_only_idempotent_requests() {
+        let (address, received) = flaky_server();
+        let url = format!("http://{}/", address);
+        let client = Client::new();
+
+        client.get(&url).unwrap();
+        assert!(client.post(&url, "text/plain", "order").is_err()); // the server may have acted on it
+        client.get(&url).unwrap();
+        client.get(&url).unwrap(); // repeated on a new connection
+        assert_eq!(vec!["GET", "POST", "GET", "GET", "GET"], *received.lock().unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+// Configuration file: INI-like sections of 'key = value' entries
+//
+//     # comments start with '#'
+//     [server]
+//     address = 127.0.0.1:8080
+//
+//     [protect /admin]
+//     realm = Admin tools
+//
+// A section header may carry an argument after its name, like the path above.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub argument: Option<String>,
+    pub line: usize,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter()
+                    .rev() // later entries override earlier ones
+                    .find(|(k, _)| k == key)
+                    .map(|(_, v)| v.as_str())
+    }
+
+    // Whitespace separated values
+    pub fn get_list(&self, key: &str) -> Vec<String> {
+        self.get(key).map_or(Vec::new(), |v| v.split_whitespace().map(String::from).collect())
+    }
+
+    pub fn get_parsed<T: ::std::str::FromStr>(&self, key: &str) -> Result<Option<T>, ConfigError> {
+        match self.get(key) {
+            Some(value) => value.parse().map(Some).map_err(|_| ConfigError::Invalid {
+                line: self.line,
+                message: format!("invalid value '{}' for '{}' in [{}]", value, key, self.name),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        match self.get(key) {
+            Some("yes") | Some("on") | Some("true") => Ok(Some(true)),
+            Some("no") | Some("off") | Some("false") => Ok(Some(false)),
+            Some(value) => Err(ConfigError::Invalid {
+                line: self.line,
+                message: format!("expected yes or no for '{}' in [{}], got '{}'", key, self.name, value),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref e) => write!(f, "{}", e),
+            ConfigError::Invalid { line, ref message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, ConfigError> {
+        let mut sections: Vec<Section> = Vec::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(ConfigError::Invalid { line: i + 1, message: format!("unterminated section '{}'", line) });
+                }
+
+                let header = line[1..line.len() - 1].trim();
+                let mut parts = header.splitn(2, char::is_whitespace);
+                let name = parts.next().unwrap_or("").to_string();
+                let argument = parts.next().map(|a| a.trim().to_string());
+
+                sections.push(Section { name, argument, line: i + 1, entries: Vec::new() });
+                continue;
+            }
+
+            let section = match sections.last_mut() {
+                Some(section) => section,
+                None => return Err(ConfigError::Invalid { line: i + 1, message: "entry outside of any section".to_string() }),
+            };
+
+            match line.find('=') {
+                Some(eq) => section.entries.push((line[..eq].trim().to_string(), line[eq + 1..].trim().to_string())),
+                None => return Err(ConfigError::Invalid { line: i + 1, message: format!("expected 'key = value', got '{}'", line) }),
+            }
+        }
+
+        Ok(Config { sections })
+    }
+
+    // First section with the given name
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    pub fn sections<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Section> + 'a {
+        self.sections.iter().filter(move |s| s.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_with_arguments() {
+        let config = Config::parse("\
+# comment
+[server]
+address = 127.0.0.1:8080
+
+[protect /admin]
+realm = Admin tools
+users = alice bob
+").unwrap();
+
+        assert_eq!(Some("127.0.0.1:8080"), config.section("server").and_then(|s| s.get("address")));
+
+        let protect = config.section("protect").unwrap();
+        assert_eq!(Some("/admin".to_string()), protect.argument);
+        assert_eq!(Some("Admin tools"), protect.get("realm"));
+        assert_eq!(vec!["alice", "bob"], protect.get_list("users"));
+    }
+
+    #[test]
+    fn reports_line_of_errors() {
+        match Config::parse("[server]\naddress\n") {
+            Err(ConfigError::Invalid { line: 2, .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+// Cookie request header parsing and Set-Cookie response header building (RFC 6265)
+
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use http;
+
+// name=value pairs from a Cookie header, in order; surrounding quotes are removed from values
+pub fn parse(header: &str) -> Vec<(String, String)> {
+    header.split(';')
+          .filter_map(|pair| {
+              let (name, value) = pair.split_once('=')?;
+              let name = name.trim();
+              let value = value.trim();
+              let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                  &value[1..value.len() - 1]
+              } else {
+                  value
+              };
+
+              if name.is_empty() { None } else { Some((name.to_string(), value.to_string())) }
+          })
+          .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    path: Option<String>,
+    domain: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> SetCookie {
+        SetCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            expires: None,
+            max_age: None,
+            path: None,
+            domain: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    // A cookie telling the browser to forget the one with that name
+    pub fn removal(name: &str) -> SetCookie {
+        SetCookie::new(name, "").max_age(Duration::from_secs(0))
+                                .expires(SystemTime::UNIX_EPOCH)
+    }
+
+    pub fn expires(mut self, time: SystemTime) -> SetCookie {
+        self.expires = Some(time);
+        self
+    }
+
+    pub fn max_age(mut self, age: Duration) -> SetCookie {
+        self.max_age = Some(age);
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> SetCookie {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> SetCookie {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> SetCookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> SetCookie {
+        self.http_only = http_only;
+        self
+    }
+
+    // SameSite=None is only honoured by browsers along with Secure
+    pub fn same_site(mut self, same_site: SameSite) -> SetCookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+// Header value, e.g. "sid=abc; Path=/; HttpOnly"
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http::format_date(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn parses_cookie_header() {
+        assert_eq!(vec![("sid".to_string(), "abc".to_string()), ("theme".to_string(), "dark mode".to_string())],
+                   parse("sid=abc; theme=\"dark mode\"; broken"));
+    }
+
+    #[test]
+    fn builds_set_cookie() {
+        let cookie = SetCookie::new("sid", "abc").expires(UNIX_EPOCH + Duration::from_secs(784_111_777))
+                                                 .max_age(Duration::from_secs(3600))
+                                                 .path("/")
+                                                 .domain("example.com")
+                                                 .secure(true)
+                                                 .http_only(true)
+                                                 .same_site(SameSite::Lax);
+
+        assert_eq!("sid=abc; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Max-Age=3600; Path=/; Domain=example.com; \
+                    Secure; HttpOnly; SameSite=Lax",
+                   cookie.to_string());
+    }
+}
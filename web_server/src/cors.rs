@@ -0,0 +1,320 @@
+// Cross-origin resource sharing, so pages served from other origins may call us from the browser
+//
+//     [cors /api]
+//     origins = https://app.example.com https://*.example.com   # '*' for any origin, but not with credentials
+//     methods = GET POST DELETE        # GET, HEAD and POST if missing
+//     headers = Content-Type X-Token   # request headers scripts may set, '*' for any
+//     expose = X-Total-Count           # response headers scripts may read
+//     credentials = yes                # cookies and HTTP authentication
+//     max_age = 600                    # seconds browsers may cache a preflight answer
+//
+// Requests from origins that aren't allowed get no CORS headers, so browsers keep their answers
+// from scripts, and their preflight requests are refused.
+
+use config::{Config, ConfigError, Section};
+use handler::Handler;
+use http::{self, Request, Response};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Origin {
+    Any,
+    Exact(String),
+    Wildcard(String, String), // what comes before and after the '*'
+}
+
+impl Origin {
+    fn new(origin: &str) -> Origin {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+
+        match origin.split_once('*') {
+            None => Origin::Exact(origin),
+            Some(("", "")) => Origin::Any,
+            Some((before, after)) => Origin::Wildcard(before.to_string(), after.to_string()),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match *self {
+            Origin::Any => true,
+            Origin::Exact(ref exact) => origin.eq_ignore_ascii_case(exact),
+            Origin::Wildcard(ref before, ref after) => {
+                let origin = origin.to_ascii_lowercase();
+                origin.len() > before.len() + after.len() && origin.starts_with(before.as_str()) && origin.ends_with(after.as_str())
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    prefix: String,
+    origins: Vec<Origin>,
+    methods: Vec<String>,
+    headers: Vec<String>, // lowercase, "*" for any
+    expose: Vec<String>,
+    credentials: bool,
+    max_age: Option<u32>,
+}
+
+impl CorsPolicy {
+    pub fn new(prefix: &str) -> CorsPolicy {
+        CorsPolicy {
+            prefix: prefix.to_string(),
+            origins: Vec::new(),
+            methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            headers: Vec::new(),
+            expose: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    pub fn origins(mut self, origins: &[&str]) -> CorsPolicy {
+        self.origins = origins.iter().map(|o| Origin::new(o)).collect();
+        self
+    }
+
+    pub fn methods(mut self, methods: &[&str]) -> CorsPolicy {
+        self.methods = methods.iter().map(|m| m.to_ascii_uppercase()).collect();
+        self
+    }
+
+    pub fn headers(mut self, headers: &[&str]) -> CorsPolicy {
+        self.headers = headers.iter().map(|h| h.to_ascii_lowercase()).collect();
+        self
+    }
+
+    pub fn expose(mut self, headers: &[&str]) -> CorsPolicy {
+        self.expose = headers.iter().map(|h| h.to_string()).collect();
+        self
+    }
+
+    pub fn credentials(mut self, credentials: bool) -> CorsPolicy {
+        self.credentials = credentials;
+        self
+    }
+
+    pub fn max_age(mut self, seconds: u32) -> CorsPolicy {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn from_section(section: &Section) -> Result<CorsPolicy, ConfigError> {
+        let prefix = match section.argument {
+            Some(ref prefix) if prefix.starts_with('/') => prefix,
+            _ => return Err(ConfigError::Invalid { line: section.line, message: "expected [cors /path]".to_string() }),
+        };
+
+        fn as_strs(list: &[String]) -> Vec<&str> {
+            list.iter().map(|s| s.as_str()).collect()
+        }
+
+        let mut policy = CorsPolicy::new(prefix).origins(&as_strs(&section.get_list("origins")))
+                                                .headers(&as_strs(&section.get_list("headers")))
+                                                .expose(&as_strs(&section.get_list("expose")))
+                                                .credentials(section.get_bool("credentials")?.unwrap_or(false));
+        if section.get("methods").is_some() {
+            policy = policy.methods(&as_strs(&section.get_list("methods")));
+        }
+        if let Some(max_age) = section.get_parsed("max_age")? {
+            policy = policy.max_age(max_age);
+        }
+
+        if policy.credentials && policy.origins.contains(&Origin::Any) {
+            let message = "credentials can't be allowed from any origin, list the origins instead";
+            return Err(ConfigError::Invalid { line: section.line, message: message.to_string() });
+        }
+
+        Ok(policy)
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o.matches(origin))
+    }
+
+    // Headers any request from an allowed origin gets
+    fn add_origin_headers(&self, origin: &str, response: &mut Response) {
+        if self.origins.contains(&Origin::Any) {
+            response.headers.set("Access-Control-Allow-Origin", "*");
+        } else {
+            response.headers.set("Access-Control-Allow-Origin", origin);
+        }
+
+        if self.credentials {
+            response.headers.set("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    fn preflight(&self, origin: &str, request: &Request) -> Response {
+        let method = request.header("Access-Control-Request-Method").unwrap_or("");
+        let requested: Vec<String> = request.header("Access-Control-Request-Headers")
+                                            .map(|h| h.split(',').map(|h| h.trim().to_ascii_lowercase()).filter(|h| !h.is_empty()).collect())
+                                            .unwrap_or_default();
+
+        if !self.methods.iter().any(|m| m == method) {
+            return Response::error(403, format!("Method {} not allowed from {}", method, origin));
+        }
+        let any_header = self.headers.iter().any(|h| h == "*");
+        if let Some(header) = requested.iter().find(|h| !any_header && !self.headers.contains(h)) {
+            return Response::error(403, format!("Header {} not allowed from {}", header, origin));
+        }
+
+        let mut response = Response::new(204);
+        self.add_origin_headers(origin, &mut response);
+        response.headers.set("Access-Control-Allow-Methods", &self.methods.join(", "));
+        if !requested.is_empty() {
+            response.headers.set("Access-Control-Allow-Headers", &requested.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            response.headers.set("Access-Control-Max-Age", &max_age.to_string());
+        }
+
+        response
+    }
+}
+
+// Middleware answering preflight requests and adding CORS headers to responses
+pub struct Cors<H> {
+    policies: Vec<CorsPolicy>,
+    inner: H,
+}
+
+impl<H: Handler> Cors<H> {
+    pub fn new(inner: H) -> Cors<H> {
+        Cors { policies: Vec::new(), inner }
+    }
+
+    pub fn from_config(inner: H, config: &Config) -> Result<Cors<H>, ConfigError> {
+        let mut cors = Cors::new(inner);
+        for section in config.sections("cors") {
+            cors = cors.policy(CorsPolicy::from_section(section)?);
+        }
+
+        Ok(cors)
+    }
+
+    // Any page on the web could use the visitor's cookies on us if any origin were allowed credentials
+    pub fn policy(mut self, policy: CorsPolicy) -> Cors<H> {
+        assert!(!(policy.credentials && policy.origins.contains(&Origin::Any)), "credentials allowed from any origin");
+
+        self.policies.push(policy);
+        self.policies.sort_by_key(|p| ::std::cmp::Reverse(p.prefix.len())); // longest prefix first
+        self
+    }
+}
+
+impl<H: Handler> Handler for Cors<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        let policy = match self.policies.iter().find(|p| http::matches_prefix(&request.path, &p.prefix)) {
+            Some(policy) => policy,
+            None => return self.inner.handle(request),
+        };
+        let origin = match request.header("Origin") {
+            Some(origin) => origin.to_string(),
+            None => {
+                // Not a cross-origin request, but its answer mustn't be cached for those that are
+                let mut response = self.inner.handle(request);
+                response.headers.add("Vary", "Origin");
+                return response;
+            },
+        };
+        let allowed = policy.allows_origin(&origin);
+
+        let mut response = if request.method == "OPTIONS" && request.headers.contains("Access-Control-Request-Method") {
+            let mut response = if allowed {
+                policy.preflight(&origin, request)
+            } else {
+                Response::error(403, format!("Origin {} not allowed", origin))
+            };
+            response.headers.add("Vary", "Access-Control-Request-Method, Access-Control-Request-Headers");
+            response
+        } else {
+            let mut response = self.inner.handle(request);
+            if allowed {
+                policy.add_origin_headers(&origin, &mut response);
+                if !policy.expose.is_empty() {
+                    response.headers.set("Access-Control-Expose-Headers", &policy.expose.join(", "));
+                }
+            }
+            response
+        };
+
+        // Caches must keep answers for different origins apart
+        response.headers.add("Vary", "Origin");
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api() -> Cors<impl Fn(&mut Request) -> Response + Send + Sync> {
+        let policy = CorsPolicy::new("/api").origins(&["https://app.example.com", "https://*.example.org"])
+                                            .methods(&["GET", "DELETE"])
+                                            .headers(&["Content-Type"])
+                                            .expose(&["X-Total-Count"])
+                                            .credentials(true)
+                                            .max_age(600);
+
+        Cors::new(|_: &mut Request| Response::text(200, "data").with_header("X-Total-Count", "3"))
+             .policy(policy)
+             .policy(CorsPolicy::new("/api/public").origins(&["*"]))
+    }
+
+    fn from(origin: &str, method: &str, path: &str) -> Request {
+        let mut request = Request::new(method, path);
+        request.headers.add("Origin", origin);
+        request
+    }
+
+    #[test]
+    fn answers_preflight_requests() {
+        let cors = api();
+
+        let mut request = from("https://app.example.com", "OPTIONS", "/api/items");
+        request.headers.add("Access-Control-Request-Method", "DELETE");
+        request.headers.add("Access-Control-Request-Headers", "content-type");
+        let response = cors.handle(&mut request);
+        assert_eq!(204, response.status);
+        assert_eq!(Some("https://app.example.com"), response.header("Access-Control-Allow-Origin"));
+        assert_eq!(Some("GET, DELETE"), response.header("Access-Control-Allow-Methods"));
+        assert_eq!(Some("content-type"), response.header("Access-Control-Allow-Headers"));
+        assert_eq!((Some("600"), Some("true")), (response.header("Access-Control-Max-Age"), response.header("Access-Control-Allow-Credentials")));
+
+        request.headers.set("Access-Control-Request-Headers", "X-Secret");
+        assert_eq!(403, cors.handle(&mut request).status);
+
+        let mut request = from("https://evil.example.com", "OPTIONS", "/api/items");
+        request.headers.add("Access-Control-Request-Method", "GET");
+        assert_eq!(403, cors.handle(&mut request).status);
+    }
+
+    #[test]
+    fn adds_headers_to_allowed_origins() {
+        let cors = api();
+
+        let response = cors.handle(&mut from("https://shop.example.org", "GET", "/api/items"));
+        assert_eq!(Some("https://shop.example.org"), response.header("Access-Control-Allow-Origin"));
+        assert_eq!((Some("X-Total-Count"), Some("Origin")), (response.header("Access-Control-Expose-Headers"), response.header("Vary")));
+
+        let response = cors.handle(&mut from("https://example.org", "GET", "/api/items"));
+        assert_eq!((None, Some("Origin")), (response.header("Access-Control-Allow-Origin"), response.header("Vary")));
+
+        let response = cors.handle(&mut from("https://anyone.net", "GET", "/api/public/items"));
+        assert_eq!(Some("*"), response.header("Access-Control-Allow-Origin"));
+
+        let response = cors.handle(&mut Request::new("GET", "/api/items"));
+        assert_eq!((None, Some("Origin")), (response.header("Access-Control-Allow-Origin"), response.header("Vary")));
+        assert_eq!(None, cors.handle(&mut Request::new("GET", "/elsewhere")).header("Vary"));
+    }
+
+    #[test]
+    fn refuses_credentials_from_any_origin() {
+        let config = Config::parse("[cors /api]\norigins = *\ncredentials = yes\n").unwrap();
+        assert!(Cors::from_config(|_: &mut Request| Response::new(200), &config).is_err());
+
+        let config = Config::parse("[cors /api]\norigins = *\n").unwrap();
+        assert!(Cors::from_config(|_: &mut Request| Response::new(200), &config).is_ok());
+    }
+}
@@ -0,0 +1,186 @@
+// Error pages
+//
+// Errors raised by the server itself, as opposed to those relayed from upstream servers or CGI
+// scripts, get a page made from a template when one is configured for their status and a
+// built-in one otherwise:
+//
+//     [errors]
+//     host = docs.example.com   # the site they're for, every site if missing
+//     404 = pages/404.html
+//     500 = pages/500.html
+//
+// Templates may use {{status}}, {{reason}} and {{message}}. Clients preferring JSON get
+// {"status":404,"error":"Not Found","message":"..."} instead.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+use config::{ConfigError, Section};
+use escape;
+use handler::Handler;
+use http::{self, Request, Response};
+
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    templates: HashMap<u16, PathBuf>,
+}
+
+impl ErrorPages {
+    pub fn new() -> ErrorPages {
+        ErrorPages::default()
+    }
+
+    pub fn page<P: Into<PathBuf>>(mut self, status: u16, template: P) -> ErrorPages {
+        self.templates.insert(status, template.into());
+        self
+    }
+
+    // Adds the pages of an [errors] section, replacing those for the same statuses
+    pub fn from_section(mut self, section: &Section) -> Result<ErrorPages, ConfigError> {
+        for (key, template) in section.entries().iter().filter(|(key, _)| key != "host") {
+            let status = match key.parse::<u16>() {
+                Ok(status) if (400..600).contains(&status) => status,
+                _ => return Err(ConfigError::Invalid { line: section.line,
+                                                       message: format!("'{}' isn't an error status", key) }),
+            };
+            self = self.page(status, template.as_str());
+        }
+
+        Ok(self)
+    }
+
+    // Dresses up errors raised by the server, leaving other responses alone; without a request
+    // to tell what the client prefers the page is HTML
+    pub fn render(&self, request: Option<&Request>, mut response: Response) -> Response {
+        let message = match response.error.take() {
+            Some(message) => message,
+            None => return response,
+        };
+
+        let status = response.status;
+        let json = request.is_some_and(|r| r.preferred_type(&["text/html", "application/json"]) == Some("application/json"));
+
+        let (content_type, body) = if json {
+            ("application/json", format!("{{\"status\":{},\"error\":{},\"message\":{}}}",
+                                         status, escape::json(http::reason_phrase(status)), escape::json(&message)))
+        } else {
+            ("text/html; charset=utf-8", self.html(status, &message))
+        };
+
+        response.headers.set("Content-Type", content_type);
+        response.headers.add("Vary", "Accept");
+        response.with_body(body)
+    }
+
+    fn html(&self, status: u16, message: &str) -> String {
+        let template = match self.templates.get(&status) {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(template) => template,
+                Err(e) => {
+                    eprintln!("Couldn't read error page {}: {}", path.display(), e);
+                    BUILTIN.to_string()
+                },
+            },
+            None => BUILTIN.to_string(),
+        };
+
+        template.replace("{{status}}", &status.to_string())
+                .replace("{{reason}}", &escape::html(http::reason_phrase(status)))
+                .replace("{{message}}", &escape::html(message))
+    }
+}
+
+const BUILTIN: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+  <head>
+    <meta charset=\"utf-8\">
+    <title>{{status}} {{reason}}</title>
+  </head>
+  <body>
+    <h1>{{reason}}</h1>
+    <p>{{message}}</p>
+  </body>
+</html>
+";
+
+// Middleware rendering the errors of the inner handler, panics included, with the given pages
+pub struct Errors<H> {
+    pages: ErrorPages,
+    inner: H,
+}
+
+impl<H: Handler> Errors<H> {
+    pub fn new(inner: H, pages: ErrorPages) -> Errors<H> {
+        Errors { pages, inner }
+    }
+}
+
+impl<H: Handler> Handler for Errors<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        let response = call(&self.inner, request);
+
+        self.pages.render(Some(request), response)
+    }
+}
+
+// Calls the handler, turning a panic into a 500 response so the worker carries on
+pub fn call<H: Handler + ?Sized>(handler: &H, request: &mut Request) -> Response {
+    panic::catch_unwind(AssertUnwindSafe(|| handler.handle(request))).unwrap_or_else(|cause| {
+        eprintln!("Handler panicked on {} {}: {}", request.method, request.target, panic_message(&*cause));
+        Response::error(500, "Internal server error")
+    })
+}
+
+fn panic_message(cause: &(dyn Any + Send)) -> &str {
+    cause.downcast_ref::<&str>().cloned()
+         .or_else(|| cause.downcast_ref::<String>().map(|s| s.as_str()))
+         .unwrap_or("unknown cause")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp::TempDir;
+
+    fn body(response: Response) -> String {
+        String::from_utf8(response.body.into_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn renders_templates_and_builtin_pages() {
+        let dir = TempDir::new("errors");
+        let template = dir.join("404.html");
+        fs::write(&template, "<h1>{{status}}: {{message}}</h1>").unwrap();
+
+        let pages = ErrorPages::new().page(404, &template);
+        let handler = Errors::new(|request: &mut Request| match request.path.as_str() {
+            "/upstream" => Response::text(404, "relayed"),
+            "/denied" => Response::error(403, "No <script> for you").with_header("X-Kept", "yes"),
+            _ => Response::error(404, "Nothing at /missing"),
+        }, pages);
+
+        assert_eq!("<h1>404: Nothing at /missing</h1>", body(handler.handle(&mut Request::new("GET", "/missing"))));
+        assert_eq!("relayed", body(handler.handle(&mut Request::new("GET", "/upstream"))));
+
+        let denied = handler.handle(&mut Request::new("GET", "/denied"));
+        assert_eq!((403, Some("text/html; charset=utf-8"), Some("yes")),
+                   (denied.status, denied.header("Content-Type"), denied.header("X-Kept")));
+        let denied = body(denied);
+        assert!(denied.contains("<title>403 Forbidden</title>") && denied.contains("No &lt;script&gt; for you"));
+    }
+
+    #[test]
+    fn negotiates_json_and_catches_panics() {
+        let handler = Errors::new(|_: &mut Request| -> Response { panic!("oops") }, ErrorPages::new());
+
+        let mut request = Request::new("GET", "/");
+        request.headers.add("Accept", "application/json");
+        let response = handler.handle(&mut request);
+
+        assert_eq!((500, Some("application/json")), (response.status, response.header("Content-Type")));
+        assert_eq!("{\"status\":500,\"error\":\"Internal Server Error\",\"message\":\"Internal server error\"}", body(response));
+    }
+}
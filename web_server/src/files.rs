@@ -0,0 +1,498 @@
+// Serves files from a document root
+//
+// Directories without an index file get a generated listing where enabled:
+//
+//     [listing /downloads]
+//     enabled = yes        # 'no' switches listings off again for a subdirectory
+//     hidden = no          # whether to show names starting with a dot
+//     host = example.com   # the site it applies to, every site if missing
+//
+// Listings are HTML, or JSON for clients asking for application/json, sorted by name, size or
+// modification time with '?sort=size&order=desc'.
+
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cache::{CachedFile, FileCache};
+use config::{ConfigError, Section};
+use escape;
+use handler::Handler;
+use http::{self, Body, Request, Response};
+
+pub struct Listing {
+    prefix: String,
+    enabled: bool,
+    hidden: bool,
+}
+
+impl Listing {
+    pub fn new(prefix: &str) -> Listing {
+        Listing { prefix: prefix.to_string(), enabled: true, hidden: false }
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Listing {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn hidden(mut self, hidden: bool) -> Listing {
+        self.hidden = hidden;
+        self
+    }
+
+    pub fn from_section(section: &Section) -> Result<Listing, ConfigError> {
+        let prefix = match section.argument {
+            Some(ref prefix) if prefix.starts_with('/') => prefix,
+            _ => return Err(ConfigError::Invalid { line: section.line, message: "expected [listing /path]".to_string() }),
+        };
+
+        Ok(Listing::new(prefix).enabled(section.get_bool("enabled")?.unwrap_or(true))
+                               .hidden(section.get_bool("hidden")?.unwrap_or(false)))
+    }
+}
+
+pub struct StaticFiles {
+    root: PathBuf,
+    index: Vec<String>, // tried in order when a directory is requested
+    listings: Vec<Listing>, // longest prefix first
+    cache: Option<Arc<FileCache>>,
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: vec!["index.html".to_string()],
+            listings: Vec::new(),
+            cache: None,
+        }
+    }
+
+    // Several sites may share one cache
+    pub fn cache(mut self, cache: Arc<FileCache>) -> StaticFiles {
+        self.cache = Some(cache);
+        self
+    }
+
+    // The most specific listing for a path decides whether its directories are listed
+    pub fn listing(mut self, listing: Listing) -> StaticFiles {
+        self.listings.push(listing);
+        self.listings.sort_by_key(|l| ::std::cmp::Reverse(l.prefix.len()));
+        self
+    }
+
+    pub fn index(mut self, names: &[&str]) -> StaticFiles {
+        self.index = names.iter().map(|n| n.to_string()).collect();
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // File system path for a request path, refusing anything that could climb out of the root
+    // and hidden files such as .htpasswd
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone();
+
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            if segment.starts_with('.') || segment.contains('\\') || segment.contains('\0') {
+                return None;
+            }
+            resolved.push(segment);
+        }
+
+        Some(resolved)
+    }
+
+    fn serve_file(&self, request: &Request, path: &Path) -> io::Result<Response> {
+        if let Some(ref cache) = self.cache {
+            if let Some(file) = cache.get(path)? {
+                return Ok(serve_cached(request, path, file));
+            }
+        }
+
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+
+        Ok(Response::new(200).with_header("Content-Type", content_type(path))
+                             .with_body(Body::Stream(Box::new(file), Some(length))))
+    }
+
+    fn list(&self, request: &Request, dir: &Path) -> Response {
+        let listing = match self.listings.iter().find(|l| http::matches_prefix(&request.path, &l.prefix)) {
+            Some(listing) if listing.enabled => listing,
+            _ => return Response::error(404, "Not found"),
+        };
+
+        let mut entries = match read_entries(dir, listing.hidden) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Couldn't list {}: {}", dir.display(), e);
+                return Response::error(500, "Couldn't read directory");
+            },
+        };
+
+        let sort = request.query_param("sort").and_then(|s| SortKey::parse(&s)).unwrap_or(SortKey::Name);
+        let descending = request.query_param("order").is_some_and(|o| o == "desc");
+        sort_entries(&mut entries, sort, descending);
+
+        let response = match request.preferred_type(&["text/html", "application/json"]) {
+            Some("application/json") => Response::new(200).with_header("Content-Type", "application/json")
+                                                          .with_body(listing_json(&request.path, &entries)),
+            _ => Response::html(200, listing_html(&request.path, &entries, sort, descending)),
+        };
+
+        response.with_header("Vary", "Accept")
+    }
+}
+
+fn serve_cached(request: &Request, path: &Path, file: CachedFile) -> Response {
+    let not_modified = request.header("If-None-Match")
+                              .is_some_and(|tags| tags.split(',').any(|t| t.trim() == file.etag || t.trim() == "*"));
+
+    let mut response = Response::new(if not_modified { 304 } else { 200 })
+        .with_header("Content-Type", content_type(path))
+        .with_header("ETag", &file.etag)
+        .with_header("Last-Modified", &http::format_date(file.modified));
+
+    let contents = match file.gzip {
+        Some(gzip) => {
+            response.headers.add("Vary", "Accept-Encoding");
+            if accepts_gzip(request) {
+                response.headers.add("Content-Encoding", "gzip");
+                gzip
+            } else {
+                file.contents
+            }
+        },
+        None => file.contents,
+    };
+
+    if not_modified {
+        return response;
+    }
+
+    let length = contents.len() as u64;
+    response.with_body(Body::Stream(Box::new(Cursor::new(Shared(contents))), Some(length)))
+}
+
+fn accepts_gzip(request: &Request) -> bool {
+    request.headers.get_all("Accept-Encoding")
+                   .flat_map(|value| value.split(','))
+                   .any(|coding| {
+                       let mut parts = coding.split(';').map(str::trim);
+                       parts.next().is_some_and(|name| name.eq_ignore_ascii_case("gzip"))
+                           && !parts.any(|p| p.strip_prefix("q=").is_some_and(|q| q.parse() == Ok(0.0)))
+                   })
+}
+
+// Cached contents, read by as many responses as need them without copying
+struct Shared(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for Shared {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+struct Entry {
+    name: String,
+    dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+fn read_entries(dir: &Path, hidden: bool) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && !hidden {
+            continue;
+        }
+
+        // Follows symbolic links, leaving out broken ones
+        if let Ok(metadata) = fs::metadata(entry.path()) {
+            entries.push(Entry {
+                name,
+                dir: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                modified: metadata.modified().ok(),
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    fn parse(text: &str) -> Option<SortKey> {
+        match text {
+            "name" => Some(SortKey::Name),
+            "size" => Some(SortKey::Size),
+            "modified" => Some(SortKey::Modified),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+        }
+    }
+}
+
+// Directories always come first, ties are broken by name
+fn sort_entries(entries: &mut [Entry], sort: SortKey, descending: bool) {
+    entries.sort_by(|a, b| {
+        let order = match sort {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+        }.then_with(|| a.name.cmp(&b.name));
+
+        b.dir.cmp(&a.dir).then(if descending { order.reverse() } else { order })
+    });
+}
+
+fn listing_html(path: &str, entries: &[Entry], sort: SortKey, descending: bool) -> String {
+    let title = escape::html(&format!("Index of {}", path));
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+                            <body>\n<h1>{0}</h1>\n<table>\n<tr>", title);
+
+    // Clicking the column the listing is sorted by reverses the order
+    for &(key, label) in &[(SortKey::Name, "Name"), (SortKey::Size, "Size"), (SortKey::Modified, "Modified")] {
+        let order = if key == sort && !descending { "desc" } else { "asc" };
+        html.push_str(&format!("<th><a href=\"?sort={}&amp;order={}\">{}</a></th>", key.name(), order, label));
+    }
+    html.push_str("</tr>\n");
+
+    if path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in entries {
+        let slash = if entry.dir { "/" } else { "" };
+        html.push_str(&format!("<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                               escape::html(&http::percent_encode(&entry.name)), slash,
+                               escape::html(&entry.name), slash,
+                               if entry.dir { "-".to_string() } else { entry.size.to_string() },
+                               entry.modified.map_or(String::new(), format_timestamp)));
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn listing_json(path: &str, entries: &[Entry]) -> String {
+    let entries: Vec<String> = entries.iter().map(|entry| {
+        format!("{{\"name\":{},\"type\":\"{}\",\"size\":{},\"modified\":{}}}",
+                escape::json(&entry.name),
+                if entry.dir { "directory" } else { "file" },
+                if entry.dir { "null".to_string() } else { entry.size.to_string() },
+                entry.modified.map_or("null".to_string(), |m| escape::json(&format_timestamp(m))))
+    }).collect();
+
+    format!("{{\"path\":{},\"entries\":[{}]}}", escape::json(path), entries.join(","))
+}
+
+// RFC 3339 in UTC: 2024-02-29T13:05:00Z
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = http::civil_from_days((secs / 86_400) as i64);
+
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs % 86_400 / 3600, secs % 3600 / 60, secs % 60)
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &mut Request) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Response::error(405, "Method not allowed").with_header("Allow", "GET, HEAD");
+        }
+
+        let path = match self.resolve(&request.path) {
+            Some(path) => path,
+            None => return Response::error(404, "Not found"),
+        };
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Response::error(404, "Not found"),
+        };
+
+        let file = if metadata.is_dir() {
+            if !request.path.ends_with('/') {
+                // Relative links in the index page only work from a URL ending in '/'. A path
+                // starting with "//" would send the client to another host.
+                let path = format!("/{}", request.path.trim_start_matches('/'));
+                let location = format!("{}/{}", http::percent_encode(&path),
+                                       request.query.as_ref().map_or(String::new(), |q| format!("?{}", q)));
+                return Response::new(301).with_header("Location", &location);
+            }
+
+            match self.index.iter().map(|name| path.join(name)).find(|p| p.is_file()) {
+                Some(index) => index,
+                None => return self.list(request, &path),
+            }
+        } else {
+            path
+        };
+
+        match self.serve_file(request, &file) {
+            Ok(response) => response,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Response::error(404, "Not found"),
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => Response::error(403, "Forbidden"),
+            Err(e) => {
+                eprintln!("Couldn't serve {}: {}", file.display(), e);
+                Response::error(500, "Couldn't read file")
+            },
+        }
+    }
+}
+
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") | Some("md") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp::TempDir;
+
+    fn site() -> TempDir {
+        let root = TempDir::new("files");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(root.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+        fs::write(root.join("docs/notes.txt"), "notes").unwrap();
+        fs::write(root.join(".htpasswd"), "secret").unwrap();
+        root
+    }
+
+    fn get(files: &StaticFiles, target: &str) -> Response {
+        files.handle(&mut Request::new("GET", target))
+    }
+
+    #[test]
+    fn serves_files_and_indexes() {
+        let root = site();
+        let files = StaticFiles::new(&root);
+
+        let home = get(&files, "/");
+        assert_eq!((200, Some("text/html; charset=utf-8")), (home.status, home.header("Content-Type")));
+        assert_eq!(b"<h1>Home</h1>".to_vec(), home.body.into_bytes().unwrap());
+
+        let notes = get(&files, "/docs/notes.txt");
+        assert_eq!(Some(5), notes.body.len());
+
+        let redirect = get(&files, "/docs?x=1");
+        assert_eq!((301, Some("/docs/?x=1")), (redirect.status, redirect.header("Location")));
+        assert_eq!(Some("/docs/"), get(&files, "//docs").header("Location"));
+        let mut request = Request::new("GET", "/");
+        request.path = "//docs".to_string(); // as a middleware might leave it
+        assert_eq!(Some("/docs/"), files.handle(&mut request).header("Location"));
+        assert_eq!(200, get(&files, "/docs/").status);
+    }
+
+    #[test]
+    fn refuses_hidden_and_outside_paths() {
+        let root = site();
+        let files = StaticFiles::new(root.join("docs"));
+
+        assert_eq!(404, get(&files, "/../index.html").status);
+        assert_eq!(404, get(&StaticFiles::new(&root), "/.htpasswd").status);
+        assert_eq!(404, get(&StaticFiles::new(&root), "/empty/").status);
+        assert_eq!(404, get(&files, "/missing.html").status);
+        assert_eq!(405, files.handle(&mut Request::new("POST", "/")).status);
+    }
+
+    #[test]
+    fn serves_cached_files_with_etags() {
+        let root = site();
+        fs::write(root.join("docs/long.txt"), "all work and no play ".repeat(50)).unwrap();
+        let files = StaticFiles::new(&root).cache(Arc::new(FileCache::new(4096).gzip(true)));
+
+        let plain = get(&files, "/docs/long.txt");
+        let etag = plain.header("ETag").unwrap().to_string();
+        assert_eq!((Some("Accept-Encoding"), None), (plain.header("Vary"), plain.header("Content-Encoding")));
+        assert_eq!(Some(1050), plain.body.len());
+
+        let mut request = Request::new("GET", "/docs/long.txt");
+        request.headers.add("Accept-Encoding", "deflate, gzip;q=0.8");
+        let gzipped = files.handle(&mut request);
+        assert_eq!(Some("gzip"), gzipped.header("Content-Encoding"));
+        assert!(gzipped.body.len().unwrap() < 100);
+
+        request.headers.add("If-None-Match", &etag);
+        let cached = files.handle(&mut request);
+        assert_eq!((304, Some(0)), (cached.status, cached.body.len()));
+    }
+
+    #[test]
+    fn lists_directories_without_index() {
+        let root = site();
+        fs::create_dir_all(root.join("empty/sub")).unwrap();
+        fs::write(root.join("empty/big & small.txt"), "0123456789").unwrap();
+        fs::write(root.join("empty/a.txt"), "a").unwrap();
+        fs::write(root.join("empty/.hidden"), "").unwrap();
+
+        let files = StaticFiles::new(&root).listing(Listing::new("/"))
+                                           .listing(Listing::new("/empty/sub").enabled(false));
+
+        let html = get(&files, "/empty/");
+        assert_eq!((200, Some("text/html; charset=utf-8")), (html.status, html.header("Content-Type")));
+        let html = String::from_utf8(html.body.into_bytes().unwrap()).unwrap();
+        assert!(html.contains("<a href=\"big%20%26%20small.txt\">big &amp; small.txt</a></td><td>10</td>"));
+        assert!(html.contains("<a href=\"../\">"));
+        assert!(!html.contains(".hidden"));
+
+        let mut request = Request::new("GET", "/empty/?sort=size&order=desc");
+        request.headers.add("Accept", "application/json");
+        let json = files.handle(&mut request);
+        assert_eq!(Some("application/json"), json.header("Content-Type"));
+        let json = String::from_utf8(json.body.into_bytes().unwrap()).unwrap();
+        assert!(json.starts_with("{\"path\":\"/empty/\",\"entries\":[{\"name\":\"sub\",\"type\":\"directory\",\"size\":null,"));
+        assert!(json.find("big & small.txt").unwrap() < json.find("a.txt").unwrap());
+
+        assert_eq!(404, get(&files, "/empty/sub/").status);
+        assert_eq!(404, get(&StaticFiles::new(&root), "/empty/").status);
+    }
+}
@@ -0,0 +1,239 @@
+// gzip (RFC 1952) compression: LZ77 matches coded with the fixed Huffman codes of DEFLATE
+// (RFC 1951), which gets most of the gain for text without building custom code tables
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64; // earlier positions tried for each match, trading ratio for speed
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASES: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASES: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                   257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                   8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                  7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter { bytes: vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255], bits: 0, count: 0 };
+
+    out.write(1, 1); // last block
+    out.write(1, 2); // fixed Huffman codes
+    deflate(data, &mut out);
+    write_literal(&mut out, 256); // end of block
+
+    let mut bytes = out.finish();
+    bytes.extend_from_slice(&crc32(data).to_le_bytes());
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes
+}
+
+fn deflate(data: &[u8], out: &mut BitWriter) {
+    let mut head = vec![usize::MAX; 1 << HASH_BITS]; // latest position for each hash
+    let mut prev = vec![usize::MAX; WINDOW];         // previous position with the same hash
+    let hash = |i: usize| {
+        let key = u32::from(data[i]) << 16 | u32::from(data[i + 1]) << 8 | u32::from(data[i + 2]);
+        (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+    let insert = |i: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(i)];
+            let max_len = MAX_MATCH.min(data.len() - i);
+
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || i - candidate > WINDOW - 1 {
+                    break;
+                }
+
+                let len = data[candidate..].iter().zip(&data[i..i + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+
+                let next = prev[candidate % WINDOW];
+                if next == usize::MAX || next >= candidate {
+                    break; // the slot was reused by a later position
+                }
+                candidate = next;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(out, best_len, best_dist);
+            for j in i..i + best_len {
+                insert(j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            write_literal(out, u16::from(data[i]));
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+}
+
+// Fixed literal/length codes: 0-143 take 8 bits, 144-255 9 bits, 256-279 7 bits, 280-287 8 bits
+fn write_literal(out: &mut BitWriter, symbol: u16) {
+    let (code, len) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+    out.write_code(code, len);
+}
+
+fn write_match(out: &mut BitWriter, len: usize, dist: usize) {
+    let code = LENGTH_BASES.iter().rposition(|&base| base as usize <= len).unwrap();
+    write_literal(out, 257 + code as u16);
+    out.write((len - LENGTH_BASES[code] as usize) as u32, LENGTH_EXTRA[code]);
+
+    let code = DISTANCE_BASES.iter().rposition(|&base| base as usize <= dist).unwrap();
+    out.write_code(code as u16, 5);
+    out.write((dist - DISTANCE_BASES[code] as usize) as u32, DISTANCE_EXTRA[code]);
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    // Values go least significant bit first
+    fn write(&mut self, value: u32, len: u8) {
+        for i in 0..len {
+            self.bits |= ((value >> i) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.bits as u8);
+                self.bits = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn write_code(&mut self, code: u16, len: u8) {
+        let reversed = (0..len).fold(0, |r, i| r | (u32::from(code) >> i & 1) << (len - 1 - i));
+        self.write(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize, // in bits
+    }
+
+    impl<'a> BitReader<'a> {
+        fn bit(&mut self) -> u32 {
+            let bit = u32::from(self.bytes[self.pos / 8] >> (self.pos % 8) & 1);
+            self.pos += 1;
+            bit
+        }
+
+        fn bits(&mut self, len: u8) -> usize {
+            (0..len).fold(0, |v, i| v | (self.bit() as usize) << i)
+        }
+
+        fn code(&mut self, len: u8) -> u32 {
+            (0..len).fold(0, |c, _| c << 1 | self.bit())
+        }
+    }
+
+    // Just enough of an inflater to read back what compress() writes
+    fn decompress(gzip: &[u8]) -> Vec<u8> {
+        assert_eq!(&[0x1f, 0x8b, 8][..], &gzip[..3]);
+        let mut reader = BitReader { bytes: &gzip[10..], pos: 0 };
+        assert_eq!((1, 1), (reader.bits(1), reader.bits(2)));
+
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let mut code = reader.code(7);
+            let symbol = if code <= 23 {
+                256 + code
+            } else {
+                code = code << 1 | reader.bit();
+                match code {
+                    0x30..=0xbf => code - 0x30,
+                    0xc0..=0xc7 => 280 + code - 0xc0,
+                    _ => 144 + (code << 1 | reader.bit()) - 0x190,
+                }
+            } as usize;
+
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let len = LENGTH_BASES[symbol - 257] as usize + reader.bits(LENGTH_EXTRA[symbol - 257]);
+                    let code = reader.code(5) as usize;
+                    let dist = DISTANCE_BASES[code] as usize + reader.bits(DISTANCE_EXTRA[code]);
+                    for _ in 0..len {
+                        out.push(out[out.len() - dist]);
+                    }
+                },
+            }
+        }
+
+        let trailer = &gzip[gzip.len() - 8..];
+        assert_eq!(crc32(&out).to_le_bytes(), trailer[..4]);
+        assert_eq!((out.len() as u32).to_le_bytes(), trailer[4..]);
+        out
+    }
+
+    #[test]
+    fn compresses_and_round_trips() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+
+        let text = "<p>Sorry, I don't know what you're asking for.</p>\n".repeat(200);
+        let compressed = compress(text.as_bytes());
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(text.as_bytes(), &decompress(&compressed)[..]);
+
+        let bytes: Vec<u8> = (0..70_000u64).map(|i| (i * i % 251) as u8).collect();
+        assert_eq!(bytes, decompress(&compress(&bytes)));
+        assert_eq!(b"".to_vec(), decompress(&compress(b"")));
+    }
+}
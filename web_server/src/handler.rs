@@ -0,0 +1,22 @@
+// Anything able to turn a request into a response. Middleware are handlers wrapping other handlers.
+
+use http::{Request, Response};
+
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &mut Request) -> Response;
+}
+
+// Plain closures and functions can be used as handlers
+impl<F> Handler for F
+    where F: Fn(&mut Request) -> Response + Send + Sync
+{
+    fn handle(&self, request: &mut Request) -> Response {
+        self(request)
+    }
+}
+
+impl Handler for Box<dyn Handler> {
+    fn handle(&self, request: &mut Request) -> Response {
+        (**self).handle(request)
+    }
+}
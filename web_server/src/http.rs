@@ -0,0 +1,1043 @@
+// HTTP/1.1 messages: just enough parsing and serialization for the server's needs
+
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::mem;
+use std::net::SocketAddr;
+use std::slice;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use cookie;
+use session::Session;
+
+const MAX_HEAD_LEN: usize = 8 * 1024; // request line plus headers
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+// Header names are case-insensitive, but we keep them as received and in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers { entries: Vec::new() }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter()
+                    .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // Appends a value, keeping any previous ones (e.g. several Set-Cookie headers)
+    pub fn add(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    // Replaces every previous value
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.add(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, (String, String)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    Closed, // the peer closed the connection before sending anything
+    BadRequest(String),
+    TooLarge,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Io(ref e) => write!(f, "I/O error: {}", e),
+            ParseError::Closed => write!(f, "connection closed"),
+            ParseError::BadRequest(ref msg) => write!(f, "bad request: {}", msg),
+            ParseError::TooLarge => write!(f, "request too large"),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub target: String, // as received, e.g. "/search?q=rust"
+    pub path: String,   // percent-decoded path component of the target
+    pub query: Option<String>,
+    pub version: String,
+    pub headers: Headers,
+    pub body: Body,     // streamed off the connection by the server, see buffer_body
+    pub remote_addr: Option<SocketAddr>,
+    pub remote_user: Option<String>, // set once the user has authenticated
+    pub session: Option<Session>,    // set by the session middleware
+    pub route: Option<String>,       // path or prefix of the route that matched, set by the router
+    pub secure: bool,                // received over TLS
+}
+
+impl Request {
+    pub fn new(method: &str, target: &str) -> Request {
+        let (path, query) = split_target(target);
+
+        Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            path,
+            query,
+            version: "HTTP/1.1".to_string(),
+            headers: Headers::new(),
+            body: Body::empty(),
+            remote_addr: None,
+            remote_user: None,
+            session: None,
+            route: None,
+            secure: false,
+        }
+    }
+
+    // Reads a whole request, body included, from the stream
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        let (mut request, framing) = Request::read_head(reader)?;
+        request.body = Body::Bytes(read_body(reader, framing)?);
+
+        Ok(request)
+    }
+
+    // Reads the request line and headers, leaving the body for the caller to read as framed
+    pub(crate) fn read_head<R: BufRead>(reader: &mut R) -> Result<(Request, Framing), ParseError> {
+        let mut head_len = 0;
+
+        let line = match read_line(reader, &mut head_len)? {
+            Some(line) => line,
+            None => return Err(ParseError::Closed),
+        };
+
+        let mut parts = line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(t), Some(v), None) if !m.is_empty() && !t.is_empty() => (m, t, v),
+            _ => return Err(ParseError::BadRequest(format!("malformed request line '{}'", line))),
+        };
+
+        if !version.starts_with("HTTP/1.") {
+            return Err(ParseError::BadRequest(format!("unsupported version '{}'", version)));
+        }
+
+        let mut request = Request::new(method, target);
+        if request.path == "/.." || request.path.starts_with("/../") {
+            return Err(ParseError::BadRequest(format!("path '{}' climbs above the root", target)));
+        }
+        request.version = version.to_string();
+        request.headers = read_headers(reader, &mut head_len)?;
+        let framing = request_framing(&request.headers)?;
+
+        Ok((request, framing))
+    }
+
+    // Serializes the request, e.g. to forward it to another server. A streamed body is sent as
+    // it's read, so it can only be sent once.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, self.target);
+
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Transfer-Encoding") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+
+        match self.body {
+            Body::Bytes(ref bytes) => {
+                if !bytes.is_empty() || self.method == "POST" || self.method == "PUT" {
+                    head.push_str(&format!("Content-Length: {}\r\n", bytes.len()));
+                }
+                head.push_str("\r\n");
+                writer.write_all(head.as_bytes())?;
+                writer.write_all(bytes)?;
+            },
+            Body::Stream(ref mut reader, Some(length)) => {
+                head.push_str(&format!("Content-Length: {}\r\n\r\n", length));
+                writer.write_all(head.as_bytes())?;
+                io::copy(reader, writer)?;
+            },
+            Body::Stream(ref mut reader, None) => {
+                head.push_str("Transfer-Encoding: chunked\r\n\r\n");
+                writer.write_all(head.as_bytes())?;
+                write_chunked(reader, writer)?;
+            },
+        }
+        writer.flush()
+    }
+
+    // Reads a streamed body into memory, for handlers that need it whole, refusing it past
+    // MAX_BODY_LEN. Handlers that can pass the body on as it comes should read it as a stream.
+    pub fn buffer_body(&mut self) -> Result<&[u8], ParseError> {
+        if let Body::Stream(_, length) = self.body {
+            if length.is_some_and(|length| length > MAX_BODY_LEN as u64) {
+                return Err(ParseError::TooLarge);
+            }
+
+            let reader = match mem::replace(&mut self.body, Body::empty()) {
+                Body::Stream(reader, _) => reader,
+                Body::Bytes(_) => unreachable!(),
+            };
+
+            let mut body = Vec::new();
+            reader.take(MAX_BODY_LEN as u64 + 1)
+                  .read_to_end(&mut body)
+                  .map_err(from_body_error)?;
+            if body.len() > MAX_BODY_LEN {
+                return Err(ParseError::TooLarge);
+            }
+            self.body = Body::Bytes(body);
+        }
+
+        Ok(self.body.bytes().unwrap_or_default())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.headers.get_all("Cookie")
+                    .flat_map(cookie::parse)
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v)
+    }
+
+    // Value of a query string parameter, percent-decoded
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query.as_ref().and_then(|query| {
+            parse_query(query).into_iter()
+                              .find(|(k, _)| k == name)
+                              .map(|(_, v)| v)
+        })
+    }
+
+    // Which of the offered media types the client likes best according to its Accept header,
+    // the first one if it didn't say, None if it accepts none of them
+    pub fn preferred_type<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let accept = match self.headers.get("Accept") {
+            Some(accept) => accept,
+            None => return offered.first().cloned(),
+        };
+
+        let ranges: Vec<(&str, f32)> = accept.split(',').map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media = parts.next().unwrap_or("");
+            let quality = parts.filter_map(|p| p.strip_prefix("q="))
+                               .find_map(|q| q.parse().ok())
+                               .unwrap_or(1.0);
+            (media, quality)
+        }).collect();
+
+        // The most specific matching range decides, e.g. 'text/html' over 'text/*' over '*/*'
+        let quality = |offer: &str| {
+            let kind = offer.split('/').next().unwrap_or("");
+            ranges.iter()
+                  .filter_map(|&(media, q)| {
+                      if media.eq_ignore_ascii_case(offer) {
+                          Some((2, q))
+                      } else if media.strip_suffix("/*").is_some_and(|k| k.eq_ignore_ascii_case(kind)) {
+                          Some((1, q))
+                      } else if media == "*/*" {
+                          Some((0, q))
+                      } else {
+                          None
+                      }
+                  })
+                  .max_by_key(|&(specificity, _)| specificity)
+                  .map_or(0.0, |(_, q)| q)
+        };
+
+        let mut best = None;
+        for &offer in offered {
+            let q = quality(offer);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((offer, q));
+            }
+        }
+        best.map(|(offer, _)| offer)
+    }
+
+    pub fn wants_keep_alive(&self) -> bool {
+        match self.headers.get("Connection") {
+            Some(value) if value.eq_ignore_ascii_case("close") => false,
+            Some(value) if value.eq_ignore_ascii_case("keep-alive") => true,
+            _ => self.version == "HTTP/1.1", // persistent by default since 1.1
+        }
+    }
+}
+
+// Response bodies are either in memory or read as they're sent (e.g. from another server)
+pub enum Body {
+    Bytes(Vec<u8>),
+    Stream(Box<dyn Read + Send>, Option<u64>), // length, if known beforehand
+}
+
+impl Body {
+    pub fn empty() -> Body {
+        Body::Bytes(Vec::new())
+    }
+
+    // None for streams, which can only be read once
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Body::Bytes(ref bytes) => Some(bytes),
+            Body::Stream(..) => None,
+        }
+    }
+
+    pub fn len(&self) -> Option<u64> {
+        match *self {
+            Body::Bytes(ref bytes) => Some(bytes.len() as u64),
+            Body::Stream(_, length) => length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    // Reads a stream to the end
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::Stream(mut reader, _) => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Body::Bytes(ref bytes) => write!(f, "Bytes({:?})", String::from_utf8_lossy(bytes)),
+            Body::Stream(_, length) => write!(f, "Stream({:?})", length),
+        }
+    }
+}
+
+// Streams never compare equal since comparing them would consume them
+impl PartialEq for Body {
+    fn eq(&self, other: &Body) -> bool {
+        match (self.bytes(), other.bytes()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl<'a> From<&'a str> for Body {
+    fn from(text: &'a str) -> Body {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Body,
+    pub error: Option<String>, // what went wrong, for errors raised by this server rather than relayed
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::empty(),
+            error: None,
+        }
+    }
+
+    // A plain text error, which error pages may later replace with something nicer
+    pub fn error<M: Into<String>>(status: u16, message: M) -> Response {
+        let message = message.into();
+        let mut response = Response::text(status, message.as_str());
+        response.error = Some(message);
+        response
+    }
+
+    pub fn html<B: Into<Body>>(status: u16, body: B) -> Response {
+        Response::new(status).with_header("Content-Type", "text/html; charset=utf-8")
+                             .with_body(body)
+    }
+
+    pub fn text<B: Into<Body>>(status: u16, body: B) -> Response {
+        Response::new(status).with_header("Content-Type", "text/plain; charset=utf-8")
+                             .with_body(body)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.add(name, value);
+        self
+    }
+
+    pub fn with_body<B: Into<Body>>(mut self, body: B) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    // Reads the head of a response to the given request method; the body is streamed from the reader
+    pub fn read_from<R: BufRead + Send + 'static>(mut reader: R, method: &str) -> Result<Response, ParseError> {
+        let (mut response, framing) = Response::read_head(&mut reader, method)?;
+
+        response.body = match framing {
+            Framing::Empty => Body::empty(),
+            Framing::Chunked => Body::Stream(Box::new(ChunkedReader::new(reader)), None),
+            Framing::Length(length) => Body::Stream(Box::new(reader.take(length)), Some(length)),
+            Framing::UntilClose => Body::Stream(Box::new(reader), None),
+        };
+
+        Ok(response)
+    }
+
+    // Reads the status line and headers, leaving the body for the caller to read as framed
+    pub(crate) fn read_head<R: BufRead>(reader: &mut R, method: &str) -> Result<(Response, Framing), ParseError> {
+        let mut head_len = 0;
+
+        let (status, headers) = loop {
+            let line = match read_line(reader, &mut head_len)? {
+                Some(line) => line,
+                None => return Err(ParseError::Closed),
+            };
+
+            let mut parts = line.splitn(3, ' ');
+            let status = match (parts.next(), parts.next()) {
+                (Some(version), Some(code)) if version.starts_with("HTTP/1.") => code.parse::<u16>().ok(),
+                _ => None,
+            };
+            let status = status.ok_or_else(|| ParseError::BadRequest(format!("malformed status line '{}'", line)))?;
+            let headers = read_headers(reader, &mut head_len)?;
+
+            if status >= 200 || status == 101 {
+                break (status, headers);
+            }
+            // 100 Continue and other interim responses precede the real one
+        };
+
+        let mut response = Response { status, headers, body: Body::empty(), error: None };
+
+        if method == "HEAD" || !has_body(status) {
+            return Ok((response, Framing::Empty));
+        }
+
+        let chunked = response.headers.get("Transfer-Encoding")
+                                      .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        let length = response.headers.get("Content-Length").and_then(|l| l.parse::<u64>().ok());
+
+        let framing = if chunked {
+            response.headers.remove("Transfer-Encoding"); // decoded while reading
+            Framing::Chunked
+        } else if let Some(length) = length {
+            Framing::Length(length)
+        } else {
+            Framing::UntilClose
+        };
+
+        Ok((response, framing))
+    }
+
+    // Status line, headers (framing added if missing) and the body unless told to omit it
+    pub fn write_to<W: Write>(self, writer: &mut W, include_body: bool) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status));
+
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        let send_body = has_body(self.status);
+        let chunked = send_body && !self.headers.contains("Content-Length") && self.body.len().is_none();
+
+        if chunked {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        } else if send_body && !self.headers.contains("Content-Length") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len().unwrap_or(0)));
+        }
+        head.push_str("\r\n");
+
+        writer.write_all(head.as_bytes())?;
+
+        if include_body && send_body {
+            match self.body {
+                Body::Bytes(bytes) => writer.write_all(&bytes)?,
+                Body::Stream(mut reader, _) if chunked => write_chunked(&mut reader, writer)?,
+                Body::Stream(mut reader, _) => {
+                    io::copy(&mut reader, writer)?;
+                },
+            }
+        }
+        writer.flush()
+    }
+}
+
+fn write_chunked<R: Read + ?Sized, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buffer = [0; 8192];
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        writer.write_all(format!("{:x}\r\n", read).as_bytes())?;
+        writer.write_all(&buffer[..read])?;
+        writer.write_all(b"\r\n")?;
+    }
+
+    writer.write_all(b"0\r\n\r\n")
+}
+
+// How the body of a response is delimited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Framing {
+    Empty,
+    Chunked,
+    Length(u64),
+    UntilClose,
+}
+
+fn has_body(status: u16) -> bool {
+    !(status < 200 || status == 204 || status == 304)
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"]; // 1970-01-01 was a Thursday
+pub(crate) const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// IMF-fixdate as used by Date, Expires or Last-Modified: "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn format_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86_400) as i64;
+    let (year, month, day) = civil_from_days(days);
+
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            DAYS[(days % 7) as usize], day, MONTHS[month as usize - 1], year,
+            secs % 86_400 / 3600, secs % 3600 / 60, secs % 60)
+}
+
+pub fn parse_date(text: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = text.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None;
+    }
+
+    let day: u32 = parts[1].parse().ok()?;
+    let month = MONTHS.iter().position(|&m| m == parts[2])? as u32 + 1;
+    let year: i64 = parts[3].parse().ok()?;
+    let hms: Vec<u64> = parts[4].split(':').map(|n| n.parse().ok()).collect::<Option<_>>()?;
+    if hms.len() != 3 || day == 0 || day > 31 || hms[0] > 23 || hms[1] > 59 || hms[2] > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::from_secs(days as u64 * 86_400 + hms[0] * 3600 + hms[1] * 60 + hms[2]))
+}
+
+// Conversions between days since 1970-01-01 and the proleptic Gregorian calendar
+// (http://howardhinnant.github.io/date_algorithms.html)
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+// Splits a request target into its decoded, normalized path and raw query string
+pub fn split_target(target: &str) -> (String, Option<String>) {
+    let without_fragment = target.split('#').next().unwrap_or("");
+
+    match without_fragment.find('?') {
+        Some(i) => (normalize_path(&percent_decode(&without_fragment[..i])), Some(without_fragment[i + 1..].to_string())),
+        None => (normalize_path(&percent_decode(without_fragment)), None),
+    }
+}
+
+// Collapses repeated slashes and resolves '.' and '..' segments, so that "//admin/./x" and
+// "/%2Fadmin/x" are "/admin/x" for every middleware that matches prefixes. A '..' that would
+// climb above the root is kept at the start, for read_from to refuse.
+pub fn normalize_path(path: &str) -> String {
+    if !path.starts_with('/') {
+        return path.to_string(); // e.g. "*" for OPTIONS
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." if segments.last().is_some_and(|&last| last != "..") => {
+                segments.pop();
+            },
+            _ => segments.push(segment),
+        }
+    }
+
+    let last = path.rsplit('/').next().unwrap_or("");
+    let mut normalized = format!("/{}", segments.join("/"));
+    if !segments.is_empty() && (last.is_empty() || last == "." || last == "..") {
+        normalized.push('/');
+    }
+    normalized
+}
+
+// Whether the path lies under the prefix: "/admin" covers "/admin" and "/admin/users" but not "/administrator"
+pub fn matches_prefix(path: &str, prefix: &str) -> bool {
+    if prefix.ends_with('/') {
+        return path.starts_with(prefix) || path == &prefix[..prefix.len() - 1];
+    }
+
+    path.starts_with(prefix) && (path.len() == prefix.len() || path[prefix.len()..].starts_with('/'))
+}
+
+// Invalid escapes are kept verbatim, invalid UTF-8 replaced
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(h), Some(l)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(h * 16 + l);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+
+    for &b in s.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    encoded
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+// key=value pairs separated by '&', with '+' standing for a space
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query.split('&')
+         .filter(|pair| !pair.is_empty())
+         .map(|pair| {
+             let mut kv = pair.splitn(2, '=');
+             let key = kv.next().unwrap_or("").replace('+', " ");
+             let value = kv.next().unwrap_or("").replace('+', " ");
+             (percent_decode(&key), percent_decode(&value))
+         })
+         .collect()
+}
+
+fn read_line<R: BufRead>(reader: &mut R, head_len: &mut usize) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let read = reader.take((MAX_HEAD_LEN - *head_len + 1) as u64)
+                     .read_until(b'\n', &mut line)?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+
+    *head_len += read;
+    if *head_len > MAX_HEAD_LEN {
+        return Err(ParseError::TooLarge);
+    }
+    if !line.ends_with(b"\n") {
+        return Err(ParseError::BadRequest("unexpected end of request".to_string()));
+    }
+
+    while line.ends_with(b"\n") || line.ends_with(b"\r") {
+        line.pop();
+    }
+
+    String::from_utf8(line).map(Some)
+                           .map_err(|_| ParseError::BadRequest("request head is not valid UTF-8".to_string()))
+}
+
+// Header lines up to and including the empty line that ends them
+pub fn read_headers<R: BufRead>(reader: &mut R, head_len: &mut usize) -> Result<Headers, ParseError> {
+    let mut headers = Headers::new();
+
+    loop {
+        let line = match read_line(reader, head_len)? {
+            Some(line) => line,
+            None => return Err(ParseError::BadRequest("unexpected end of headers".to_string())),
+        };
+
+        if line.is_empty() {
+            return Ok(headers);
+        }
+
+        match line.find(':') {
+            Some(i) if i > 0 => headers.add(line[..i].trim(), line[i + 1..].trim()),
+            _ => return Err(ParseError::BadRequest(format!("malformed header '{}'", line))),
+        }
+    }
+}
+
+// Requests only have a body when they say so
+fn request_framing(headers: &Headers) -> Result<Framing, ParseError> {
+    let chunked = headers.get("Transfer-Encoding")
+                         .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+
+    if chunked {
+        return Ok(Framing::Chunked);
+    }
+
+    match headers.get("Content-Length") {
+        Some(value) => value.parse::<u64>()
+                            .map(Framing::Length)
+                            .map_err(|_| ParseError::BadRequest(format!("invalid Content-Length '{}'", value))),
+        None => Ok(Framing::Empty),
+    }
+}
+
+fn read_body<R: BufRead>(reader: &mut R, framing: Framing) -> Result<Vec<u8>, ParseError> {
+    let length = match framing {
+        Framing::Chunked => return read_chunked(reader, MAX_BODY_LEN),
+        Framing::Length(length) => length,
+        Framing::Empty | Framing::UntilClose => 0,
+    };
+
+    if length > MAX_BODY_LEN as u64 {
+        return Err(ParseError::TooLarge);
+    }
+
+    let mut body = vec![0; length as usize];
+    reader.read_exact(&mut body)?;
+
+    Ok(body)
+}
+
+// Decodes a whole chunked body, discarding chunk extensions and trailers
+pub fn read_chunked<R: BufRead>(reader: &mut R, limit: usize) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    ChunkedReader::new(reader).take(limit as u64 + 1)
+                              .read_to_end(&mut body)
+                              .map_err(from_body_error)?;
+
+    if body.len() > limit {
+        return Err(ParseError::TooLarge);
+    }
+
+    Ok(body)
+}
+
+// Errors from reading a body that was decoded on the way
+fn from_body_error(e: io::Error) -> ParseError {
+    match e.kind() {
+        io::ErrorKind::InvalidData => ParseError::BadRequest(e.to_string()),
+        _ => ParseError::Io(e),
+    }
+}
+
+// Decodes a chunked body while it's being read
+pub struct ChunkedReader<R> {
+    inner: R,
+    chunks: Chunks,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader { inner, chunks: Chunks::default() }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.chunks.read(&mut self.inner, buf)
+    }
+}
+
+// How far decoding a chunked body has got, apart from the reader for those who can only lend it
+// one read at a time
+#[derive(Debug, Default)]
+pub(crate) struct Chunks {
+    remaining: usize, // in the current chunk
+    started: bool,
+    done: bool,
+}
+
+impl Chunks {
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn next_chunk<R: BufRead>(&mut self, inner: &mut R) -> Result<(), ParseError> {
+        let mut head_len = 0;
+
+        if self.started {
+            let mut crlf = [0; 2];
+            inner.read_exact(&mut crlf)?; // end of the previous chunk
+        }
+        self.started = true;
+
+        let line = read_line(inner, &mut head_len)?
+                         .ok_or_else(|| ParseError::BadRequest("unexpected end of chunked body".to_string()))?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        self.remaining = usize::from_str_radix(size, 16)
+                               .map_err(|_| ParseError::BadRequest(format!("invalid chunk size '{}'", size)))?;
+
+        if self.remaining == 0 {
+            read_headers(inner, &mut head_len)?; // trailers
+            self.done = true;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read<R: BufRead>(&mut self, inner: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !self.done {
+            self.next_chunk(inner).map_err(|e| match e {
+                ParseError::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+            })?;
+        }
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let max = buf.len().min(self.remaining);
+        let read = inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "chunk cut short"));
+        }
+        self.remaining -= read;
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_with_body() {
+        let raw = b"POST /login?next=%2Fadmin HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+        let request = Request::read_from(&mut &raw[..]).unwrap();
+
+        assert_eq!("POST", request.method);
+        assert_eq!("/login", request.path);
+        assert_eq!(Some("/admin".to_string()), request.query_param("next"));
+        assert_eq!(Some("localhost"), request.header("host"));
+        assert_eq!(Some(&b"hello"[..]), request.body.bytes());
+    }
+
+    #[test]
+    fn parses_chunked_body() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\n\r\n";
+        let request = Request::read_from(&mut &raw[..]).unwrap();
+
+        assert_eq!(Some(&b"Wikipedia"[..]), request.body.bytes());
+    }
+
+    #[test]
+    fn rejects_malformed_request_line() {
+        match Request::read_from(&mut &b"GET\r\n\r\n"[..]) {
+            Err(ParseError::BadRequest(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!("/a b/ñ", percent_decode("/a%20b/%C3%B1"));
+        assert_eq!("100%", percent_decode("100%"));
+        assert_eq!("/a%20b", percent_encode("/a b"));
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!("/admin/secret", split_target("//admin/secret").0);
+        assert_eq!("/admin/secret", split_target("/%2Fadmin/secret").0);
+        assert_eq!("/docs/", split_target("/docs/./a/..//").0);
+        assert_eq!("/", split_target("/a/..").0);
+        assert_eq!("*", split_target("*").0);
+
+        match Request::read_from(&mut &b"GET /a/../../etc/passwd HTTP/1.1\r\n\r\n"[..]) {
+            Err(ParseError::BadRequest(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negotiates_media_types() {
+        let offered = ["text/html", "application/json"];
+        let with_accept = |accept: &str| {
+            let mut request = Request::new("GET", "/");
+            request.headers.add("Accept", accept);
+            request.preferred_type(&offered)
+        };
+
+        assert_eq!(Some("text/html"), Request::new("GET", "/").preferred_type(&offered));
+        assert_eq!(Some("application/json"), with_accept("application/json"));
+        assert_eq!(Some("text/html"), with_accept("text/html,application/xhtml+xml,*/*;q=0.8"));
+        assert_eq!(Some("application/json"), with_accept("text/*;q=0.5, application/json"));
+        assert_eq!(None, with_accept("image/png"));
+    }
+
+    #[test]
+    fn formats_and_parses_dates() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", format_date(date));
+        assert_eq!(Some(date), parse_date("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert_eq!("Thu, 29 Feb 2024 00:00:00 GMT", format_date(UNIX_EPOCH + Duration::from_secs(1_709_164_800)));
+        assert_eq!(None, parse_date("yesterday"));
+    }
+
+    #[test]
+    fn streams_chunked_responses() {
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n";
+        let response = Response::read_from(io::Cursor::new(raw.to_vec()), "GET").unwrap();
+
+        assert_eq!(200, response.status);
+        assert_eq!(b"Wikipedia".to_vec(), response.body.into_bytes().unwrap());
+
+        let mut out = Vec::new();
+        Response::new(200).with_body(Body::Stream(Box::new(&b"Wikipedia"[..]), None))
+                          .write_to(&mut out, true)
+                          .unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with("Transfer-Encoding: chunked\r\n\r\n9\r\nWikipedia\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn writes_content_length() {
+        let mut out = Vec::new();
+        Response::text(200, "hi").write_to(&mut out, true).unwrap();
+
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 2\r\n\r\nhi",
+                   String::from_utf8(out).unwrap());
+    }
+}
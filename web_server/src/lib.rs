@@ -1,9 +1,67 @@
+extern crate rustls;
+extern crate rustls_pemfile;
+
 use std::thread;
 use std::sync::{ mpsc, Arc, Mutex };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+pub mod auth;
+pub mod cache;
+pub mod cgi;
+pub mod client;
+pub mod config;
+pub mod errors;
+pub mod cookie;
+pub mod cors;
+pub mod files;
+pub mod handler;
+pub mod http;
+pub mod limit;
+pub mod log;
+pub mod metrics;
+pub mod proxy;
+pub mod rewrite;
+pub mod router;
+pub mod server;
+pub mod session;
+pub mod template;
+pub mod tls;
+pub mod vhost;
+
+mod base64;
+mod escape;
+mod gzip;
+mod random;
+mod sha256;
+#[cfg(test)]
+mod temp;
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>
+    sender: mpsc::Sender<Message>,
+    stats: Arc<PoolStats>
+}
+
+// How busy the pool is, updated as jobs come and go
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    size: usize,
+    busy: AtomicUsize,   // workers running a job
+    queued: AtomicUsize, // jobs waiting for a worker
+}
+
+impl PoolStats {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn busy(&self) -> usize {
+        self.busy.load(Ordering::Relaxed)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
 }
 
 impl ThreadPool {
@@ -14,24 +72,31 @@ impl ThreadPool {
 
         // share ownership across multiple threads (Arc) and allow them to mutate the value, one at at time (Mutex)
         let receiver = Arc::new(Mutex::new(receiver)); 
+        let stats = Arc::new(PoolStats { size, ..PoolStats::default() });
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&stats)));
         }
 
         ThreadPool {
             workers,
-            sender
+            sender,
+            stats
         }
     }
 
+    pub fn stats(&self) -> Arc<PoolStats> {
+        Arc::clone(&self.stats)
+    }
+
     pub fn execute<F>(&self, f: F) 
         where F: FnOnce() + Send + 'static
     {
         let job = Box::new(f);
 
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
         self.sender.send(Message::NewJob(job)).expect("Receiving side has shut down");
     }    
 }
@@ -50,7 +115,7 @@ impl Drop for ThreadPool {
             println!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
-                worker.thread.join().unwrap();
+                thread.join().unwrap();
             }
         }
     }
@@ -62,7 +127,7 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>, stats: Arc<PoolStats>) -> Worker {
         
         let thread = thread::spawn(move || {
             loop {
@@ -72,7 +137,10 @@ impl Worker {
                 match msg {
                     Message::NewJob(job) => {
                         println!("Worker {} got a job; executing.", id);
+                        stats.queued.fetch_sub(1, Ordering::Relaxed);
+                        stats.busy.fetch_add(1, Ordering::Relaxed);
                         job.call_box();
+                        stats.busy.fetch_sub(1, Ordering::Relaxed);
                     },
                     Message::Terminate => {
                         println!("Worker {} was told to terminate.", id);
@@ -105,4 +173,4 @@ impl<F: FnOnce()> FnBox for F {
     }
 }
 
-type Job = Box<FnBox + Send + 'static>; // type alias for a trait object that holds the type of closure that 'execute' receives
+type Job = Box<dyn FnBox + Send + 'static>; // type alias for a trait object that holds the type of closure that 'execute' receives
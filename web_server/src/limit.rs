@@ -0,0 +1,374 @@
+// Per-client request rate limiting and concurrent connection caps
+//
+//     [limits]
+//     connections = 256          # open connections in total
+//     connections_per_ip = 16
+//
+//     [rate_limit /api]
+//     rate = 10                  # requests per second, per client address
+//     burst = 20                 # requests allowed in a row before the rate applies
+//
+// Each [rate_limit] section is a route group: the most specific prefix matching a request applies.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use auth;
+use config::{Config, ConfigError, Section};
+use handler::Handler;
+use http::{self, Request, Response};
+
+const IDLE_SWEEP: Duration = Duration::from_secs(60);
+
+// Holds up to 'burst' tokens, refilled at 'rate' tokens per second; each request takes one
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+pub struct RateLimit {
+    prefix: String,
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(prefix: &str, rate: f64, burst: u32) -> RateLimit {
+        assert!(rate > 0.0 && rate.is_finite() && burst > 0);
+
+        RateLimit {
+            prefix: prefix.to_string(),
+            rate,
+            burst: f64::from(burst),
+            buckets: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn from_section(section: &Section) -> Result<RateLimit, ConfigError> {
+        let invalid = |message: &str| ConfigError::Invalid { line: section.line, message: message.to_string() };
+
+        let prefix = match section.argument {
+            Some(ref prefix) if prefix.starts_with('/') => prefix,
+            _ => return Err(invalid("expected [rate_limit /path]")),
+        };
+        let rate: f64 = section.get_parsed("rate")?.ok_or_else(|| invalid("missing rate"))?;
+        let burst: u32 = section.get_parsed("burst")?.unwrap_or_else(|| rate.ceil().max(1.0) as u32);
+
+        // Written so that NaN fails it too
+        if !(rate > 0.0 && rate.is_finite()) || burst == 0 {
+            return Err(invalid("rate and burst must be positive numbers"));
+        }
+
+        Ok(RateLimit::new(prefix, rate, burst))
+    }
+
+    // Ok if the client may go ahead, otherwise how long until it may try again
+    fn take(&self, addr: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let burst = self.burst;
+        let bucket = buckets.entry(auth::normalize(addr)).or_insert(TokenBucket { tokens: burst, last: now });
+
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last = now;
+
+        let result = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        };
+
+        // Buckets that would be full again carry no information, so forget them
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if now.duration_since(*last_sweep) > IDLE_SWEEP {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, b| b.tokens + now.duration_since(b.last).as_secs_f64() * rate < burst);
+            *last_sweep = now;
+        }
+
+        result
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimitStats {
+    allowed: AtomicUsize,
+    limited: AtomicUsize,
+}
+
+impl RateLimitStats {
+    pub fn allowed(&self) -> usize {
+        self.allowed.load(Ordering::Relaxed)
+    }
+
+    pub fn limited(&self) -> usize {
+        self.limited.load(Ordering::Relaxed)
+    }
+}
+
+// Middleware answering 429 Too Many Requests to clients going over their group's rate
+pub struct RateLimiter<H> {
+    limits: Vec<RateLimit>,
+    stats: Arc<RateLimitStats>,
+    inner: H,
+}
+
+impl<H: Handler> RateLimiter<H> {
+    pub fn new(inner: H) -> RateLimiter<H> {
+        RateLimiter {
+            limits: Vec::new(),
+            stats: Arc::new(RateLimitStats::default()),
+            inner,
+        }
+    }
+
+    pub fn from_config(inner: H, config: &Config) -> Result<RateLimiter<H>, ConfigError> {
+        let mut limiter = RateLimiter::new(inner);
+        for section in config.sections("rate_limit") {
+            limiter = limiter.limit(RateLimit::from_section(section)?);
+        }
+
+        Ok(limiter)
+    }
+
+    pub fn limit(mut self, limit: RateLimit) -> RateLimiter<H> {
+        self.limits.push(limit);
+        self.limits.sort_by_key(|l| ::std::cmp::Reverse(l.prefix.len())); // longest prefix first
+        self
+    }
+
+    // Shared counters, which keep being updated once the limiter is in use
+    pub fn stats(&self) -> Arc<RateLimitStats> {
+        Arc::clone(&self.stats)
+    }
+}
+
+impl<H: Handler> Handler for RateLimiter<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        let limit = self.limits.iter().find(|l| http::matches_prefix(&request.path, &l.prefix));
+
+        if let (Some(limit), Some(addr)) = (limit, request.remote_addr) {
+            if let Err(wait) = limit.take(addr.ip(), Instant::now()) {
+                self.stats.limited.fetch_add(1, Ordering::Relaxed);
+
+                return Response::error(429, "Too many requests, slow down")
+                                .with_header("Retry-After", &retry_after(wait));
+            }
+        }
+
+        self.stats.allowed.fetch_add(1, Ordering::Relaxed);
+        self.inner.handle(request)
+    }
+}
+
+// Whole seconds, rounded up so clients don't come back too early
+fn retry_after(wait: Duration) -> String {
+    let secs = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+    secs.max(1).to_string()
+}
+
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    open: AtomicUsize,
+    accepted: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+impl ConnectionStats {
+    pub fn open(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    pub fn accepted(&self) -> usize {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+// Why a connection was turned away
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    TooManyFromClient,
+    TooMany,
+}
+
+// Counts open connections, in total and per client address
+pub struct ConnectionLimiter {
+    max_total: Option<usize>,
+    max_per_ip: Option<usize>,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+    stats: Arc<ConnectionStats>,
+}
+
+impl Default for ConnectionLimiter {
+    fn default() -> ConnectionLimiter {
+        ConnectionLimiter::new(None, None)
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_total: Option<usize>, max_per_ip: Option<usize>) -> ConnectionLimiter {
+        ConnectionLimiter {
+            max_total,
+            max_per_ip,
+            per_ip: Mutex::new(HashMap::new()),
+            stats: Arc::new(ConnectionStats::default()),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<ConnectionLimiter, ConfigError> {
+        match config.section("limits") {
+            Some(section) => Ok(ConnectionLimiter::new(section.get_parsed("connections")?,
+                                                       section.get_parsed("connections_per_ip")?)),
+            None => Ok(ConnectionLimiter::default()),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<ConnectionStats> {
+        Arc::clone(&self.stats)
+    }
+
+    // The returned guard holds the connection's place until dropped
+    pub fn acquire(self: &Arc<Self>, addr: IpAddr) -> Result<ConnectionGuard, Rejection> {
+        let addr = auth::normalize(addr); // one client either way on a dual-stack listener
+        let mut per_ip = self.per_ip.lock().unwrap(); // also serializes the total count
+        let open_from_client = per_ip.get(&addr).cloned().unwrap_or(0);
+
+        let rejection = if self.max_total.is_some_and(|max| self.stats.open() >= max) {
+            Some(Rejection::TooMany)
+        } else if self.max_per_ip.is_some_and(|max| open_from_client >= max) {
+            Some(Rejection::TooManyFromClient)
+        } else {
+            None
+        };
+
+        if let Some(rejection) = rejection {
+            self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(rejection);
+        }
+
+        per_ip.insert(addr, open_from_client + 1);
+        self.stats.open.fetch_add(1, Ordering::Relaxed);
+        self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+
+        Ok(ConnectionGuard { limiter: Arc::clone(self), addr })
+    }
+
+    fn release(&self, addr: IpAddr) {
+        let mut per_ip = self.per_ip.lock().unwrap();
+
+        if let Some(count) = per_ip.get_mut(&addr) {
+            *count -= 1;
+        }
+        if per_ip.get(&addr) == Some(&0) {
+            per_ip.remove(&addr);
+        }
+        self.stats.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    addr: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.addr);
+    }
+}
+
+// What to tell a client whose connection is turned away
+pub fn rejection_response(rejection: Rejection) -> Response {
+    match rejection {
+        Rejection::TooManyFromClient => Response::error(429, "Too many connections from your address")
+                                                 .with_header("Retry-After", "1"),
+        Rejection::TooMany => Response::error(503, "Server busy, try again later")
+                                       .with_header("Retry-After", "1"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn ok(_: &mut Request) -> Response {
+        Response::new(200)
+    }
+
+    fn get(handler: &dyn Handler, path: &str, from: &str) -> Response {
+        let mut request = Request::new("GET", path);
+        request.remote_addr = Some(SocketAddr::new(from.parse().unwrap(), 5000));
+        handler.handle(&mut request)
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limit = RateLimit::new("/", 2.0, 2);
+        let addr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(Ok(()), limit.take(addr, start));
+        assert_eq!(Ok(()), limit.take(addr, start));
+        assert_eq!(Err(Duration::from_millis(500)), limit.take(addr, start));
+        assert_eq!(Ok(()), limit.take(addr, start + Duration::from_millis(500)));
+        assert_eq!(Ok(()), limit.take("10.0.0.2".parse().unwrap(), start)); // separate bucket
+        assert!(limit.take("::ffff:10.0.0.1".parse().unwrap(), start).is_err()); // the same client over IPv6
+    }
+
+    #[test]
+    fn limits_per_route_group() {
+        let limiter = RateLimiter::new(ok).limit(RateLimit::new("/api", 0.5, 1))
+                                          .limit(RateLimit::new("/api/bulk", 1.0, 3));
+        let stats = limiter.stats();
+
+        assert_eq!(200, get(&limiter, "/api/users", "10.0.0.1").status);
+
+        let limited = get(&limiter, "/api/users", "10.0.0.1");
+        assert_eq!(429, limited.status);
+        assert_eq!(Some("2"), limited.header("Retry-After"));
+
+        assert_eq!(200, get(&limiter, "/api/bulk", "10.0.0.1").status);
+        assert_eq!(200, get(&limiter, "/other", "10.0.0.1").status);
+        assert_eq!((3, 1), (stats.allowed(), stats.limited()));
+    }
+
+    #[test]
+    fn caps_connections() {
+        let limiter = Arc::new(ConnectionLimiter::new(Some(3), Some(2)));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = limiter.acquire(a).unwrap();
+        let _second = limiter.acquire(a).unwrap();
+        assert_eq!(Some(Rejection::TooManyFromClient), limiter.acquire(a).err());
+        assert_eq!(Some(Rejection::TooManyFromClient), limiter.acquire("::ffff:10.0.0.1".parse().unwrap()).err());
+
+        let _third = limiter.acquire(b).unwrap();
+        assert_eq!(Some(Rejection::TooMany), limiter.acquire(b).err());
+
+        drop(first);
+        assert!(limiter.acquire(a).is_ok());
+        assert_eq!(3, limiter.stats().rejected());
+    }
+
+    #[test]
+    fn rejects_bad_rates() {
+        for rate in &["0", "-1", "nan", "inf"] {
+            let config = Config::parse(&format!("[rate_limit /api]\nrate = {}\n", rate)).unwrap();
+            assert!(RateLimiter::from_config(ok, &config).is_err(), "rate = {}", rate);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// Access logs in the Common Log Format understood by most log analyzers:
+//
+//     127.0.0.1 - alice [06/Nov/1994:08:49:37 +0000] "GET /index.html HTTP/1.1" 200 2326
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use handler::Handler;
+use http::{self, Request, Response};
+
+pub struct AccessLog<H> {
+    file: Mutex<File>,
+    inner: H,
+}
+
+impl<H: Handler> AccessLog<H> {
+    // Appends to the file, creating it if needed
+    pub fn open<P: AsRef<Path>>(path: P, inner: H) -> io::Result<AccessLog<H>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(AccessLog { file: Mutex::new(file), inner })
+    }
+}
+
+impl<H: Handler> Handler for AccessLog<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        // Handlers may rewrite the request, so take note of what was asked first
+        let line = format!("{} {} {}", request.method, request.target, request.version);
+        let response = self.inner.handle(request);
+
+        let entry = format!("{} - {} [{}] \"{}\" {} {}\n",
+                            request.remote_addr.map_or("-".to_string(), |a| a.ip().to_string()),
+                            request.remote_user.as_ref().map_or("-", |u| u.as_str()),
+                            format_time(SystemTime::now()),
+                            line.replace('"', "\\\""),
+                            response.status,
+                            response.body.len().map_or("-".to_string(), |l| l.to_string()));
+
+        if let Err(e) = self.file.lock().unwrap().write_all(entry.as_bytes()) {
+            eprintln!("Couldn't write to access log: {}", e);
+        }
+
+        response
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = http::civil_from_days((secs / 86_400) as i64);
+
+    format!("{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            day, http::MONTHS[month as usize - 1], year, secs % 86_400 / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+    use temp::TempDir;
+
+    #[test]
+    fn writes_common_log_format() {
+        let dir = TempDir::new("log");
+        let path = dir.join("access.log");
+        let log = AccessLog::open(&path, |_: &mut Request| Response::text(200, "hello")).unwrap();
+
+        let mut request = Request::new("GET", "/index.html?q=\"x\"");
+        request.remote_addr = Some("10.0.0.1:4000".parse().unwrap());
+        request.remote_user = Some("alice".to_string());
+        log.handle(&mut request);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("10.0.0.1 - alice ["));
+        assert!(contents.ends_with("] \"GET /index.html?q=\\\"x\\\" HTTP/1.1\" 200 5\n"));
+
+        assert_eq!("06/Nov/1994:08:49:37 +0000", format_time(UNIX_EPOCH + Duration::from_secs(784_111_777)));
+    }
+}
@@ -0,0 +1,325 @@
+// Health checks for load balancers and metrics in the Prometheus text format
+//
+//     [metrics]
+//     path = /metrics        # '' to leave out any of the three
+//     healthz = /healthz     # answers as long as requests are being served
+//     readyz = /readyz       # 503 while shutting down or when too many connections wait for a worker
+//     max_queue = 16         # waiting connections that count as too many, the pool's size if missing
+//
+// These endpoints are answered before any other handler, so they aren't subject to rate limits
+// nor access control.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use PoolStats;
+use cache::CacheStats;
+use config::{ConfigError, Section};
+use errors;
+use handler::Handler;
+use http::{Request, Response};
+use limit::{ConnectionStats, RateLimitStats};
+use server::Shutdown;
+
+// Upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Requests outside any route, e.g. turned away before reaching a router, or served by its fallback
+const NO_ROUTE: &str = "other";
+
+#[derive(Debug, Default)]
+struct RouteStats {
+    by_status: BTreeMap<u16, u64>,
+    buckets: [u64; 11], // not cumulative, unlike what gets exported
+    seconds: f64,
+    count: u64,
+}
+
+impl RouteStats {
+    fn record(&mut self, status: u16, seconds: f64) {
+        *self.by_status.entry(status).or_insert(0) += 1;
+        if let Some(i) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[i] += 1;
+        }
+        self.seconds += seconds;
+        self.count += 1;
+    }
+}
+
+// Everything /metrics reports on, shared with the parts of the server it comes from
+#[derive(Default)]
+pub struct Metrics {
+    routes: Mutex<HashMap<String, RouteStats>>,
+    connections: Option<Arc<ConnectionStats>>,
+    pool: Option<Arc<PoolStats>>,
+    cache: Option<Arc<CacheStats>>,
+    rate_limits: Option<Arc<RateLimitStats>>,
+    shutdown: Option<Shutdown>,
+    max_queue: Option<usize>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn connections(mut self, stats: Arc<ConnectionStats>) -> Metrics {
+        self.connections = Some(stats);
+        self
+    }
+
+    pub fn pool(mut self, stats: Arc<PoolStats>) -> Metrics {
+        self.pool = Some(stats);
+        self
+    }
+
+    pub fn cache(mut self, stats: Arc<CacheStats>) -> Metrics {
+        self.cache = Some(stats);
+        self
+    }
+
+    pub fn rate_limits(mut self, stats: Arc<RateLimitStats>) -> Metrics {
+        self.rate_limits = Some(stats);
+        self
+    }
+
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Metrics {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    // Connections waiting for a worker before the server reports it isn't ready
+    pub fn max_queue(mut self, max_queue: usize) -> Metrics {
+        self.max_queue = Some(max_queue);
+        self
+    }
+
+    pub fn record(&self, route: Option<&str>, status: u16, seconds: f64) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.entry(route.unwrap_or(NO_ROUTE).to_string()).or_default().record(status, seconds);
+    }
+
+    // Why the server shouldn't be sent new requests, if it shouldn't
+    pub fn not_ready(&self) -> Option<&'static str> {
+        if self.shutdown.as_ref().is_some_and(|s| s.is_requested()) {
+            return Some("shutting down");
+        }
+
+        let saturated = self.pool.as_ref().is_some_and(|pool| {
+            let queued = pool.queued();
+            queued > 0 && queued >= self.max_queue.unwrap_or(pool.size())
+        });
+        if saturated {
+            return Some("too many connections waiting");
+        }
+
+        None
+    }
+
+    // The Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        {
+            let routes = self.routes.lock().unwrap();
+            let mut routes: Vec<(&String, &RouteStats)> = routes.iter().collect();
+            routes.sort_by(|a, b| a.0.cmp(b.0));
+
+            header(&mut out, "http_requests_total", "counter", "Requests served, by route and status");
+            for &(route, stats) in &routes {
+                for (status, count) in &stats.by_status {
+                    let _ = writeln!(out, "http_requests_total{{route=\"{}\",status=\"{}\"}} {}", label(route), status, count);
+                }
+            }
+
+            header(&mut out, "http_request_duration_seconds", "histogram", "Time taken to answer requests, by route");
+            for &(route, stats) in &routes {
+                let mut cumulative = 0;
+                for (bound, count) in BUCKETS.iter().zip(stats.buckets.iter()) {
+                    cumulative += count;
+                    let _ = writeln!(out, "http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}", label(route), bound, cumulative);
+                }
+                let _ = writeln!(out, "http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}", label(route), stats.count);
+                let _ = writeln!(out, "http_request_duration_seconds_sum{{route=\"{}\"}} {}", label(route), stats.seconds);
+                let _ = writeln!(out, "http_request_duration_seconds_count{{route=\"{}\"}} {}", label(route), stats.count);
+            }
+        }
+
+        if let Some(ref connections) = self.connections {
+            gauge(&mut out, "http_connections_open", "Connections currently open", connections.open() as u64);
+            counter(&mut out, "http_connections_accepted_total", "Connections accepted", connections.accepted() as u64);
+            counter(&mut out, "http_connections_rejected_total", "Connections turned away by the limits", connections.rejected() as u64);
+        }
+
+        if let Some(ref pool) = self.pool {
+            gauge(&mut out, "thread_pool_workers", "Worker threads", pool.size() as u64);
+            gauge(&mut out, "thread_pool_busy_workers", "Workers serving a connection", pool.busy() as u64);
+            gauge(&mut out, "thread_pool_queued_jobs", "Connections waiting for a worker", pool.queued() as u64);
+        }
+
+        if let Some(ref cache) = self.cache {
+            counter(&mut out, "file_cache_hits_total", "Files served from memory", cache.hits.load(Ordering::Relaxed));
+            counter(&mut out, "file_cache_misses_total", "Files read from disk", cache.misses.load(Ordering::Relaxed));
+            counter(&mut out, "file_cache_evictions_total", "Files dropped to make room", cache.evictions.load(Ordering::Relaxed));
+            gauge(&mut out, "file_cache_bytes", "Bytes held in memory", cache.bytes.load(Ordering::Relaxed));
+        }
+
+        if let Some(ref limits) = self.rate_limits {
+            counter(&mut out, "rate_limit_allowed_total", "Requests within their rate limit", limits.allowed() as u64);
+            counter(&mut out, "rate_limit_limited_total", "Requests answered 429", limits.limited() as u64);
+        }
+
+        gauge(&mut out, "ready", "Whether the server accepts new requests", self.not_ready().map_or(1, |_| 0));
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+// Label values are quoted, so backslashes, quotes and newlines need escaping
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Middleware answering the health and metrics endpoints and timing every other request
+pub struct Monitor<H> {
+    metrics: Arc<Metrics>,
+    path: String,
+    healthz: String,
+    readyz: String,
+    inner: H,
+}
+
+impl<H: Handler> Monitor<H> {
+    pub fn new(inner: H, metrics: Arc<Metrics>) -> Monitor<H> {
+        Monitor {
+            metrics,
+            path: "/metrics".to_string(),
+            healthz: "/healthz".to_string(),
+            readyz: "/readyz".to_string(),
+            inner,
+        }
+    }
+
+    // Endpoint paths from a [metrics] section; the metrics get its max_queue
+    pub fn from_section(inner: H, metrics: Metrics, section: Option<&Section>) -> Result<Monitor<H>, ConfigError> {
+        let path = |key: &str, default: &str| match section.and_then(|s| s.get(key)) {
+            Some(value) if !value.is_empty() && !value.starts_with('/') => Err(ConfigError::Invalid {
+                line: section.map_or(0, |s| s.line),
+                message: format!("{} should start with '/'", key),
+            }),
+            Some(value) => Ok(value.to_string()),
+            None => Ok(default.to_string()),
+        };
+
+        let metrics = match section.map_or(Ok(None), |s| s.get_parsed("max_queue"))? {
+            Some(max_queue) => metrics.max_queue(max_queue),
+            None => metrics,
+        };
+
+        Ok(Monitor {
+            metrics: Arc::new(metrics),
+            path: path("path", "/metrics")?,
+            healthz: path("healthz", "/healthz")?,
+            readyz: path("readyz", "/readyz")?,
+            inner,
+        })
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    fn endpoint(&self, path: &str) -> Option<Response> {
+        if path.is_empty() {
+            None
+        } else if path == self.healthz {
+            Some(Response::text(200, "ok"))
+        } else if path == self.readyz {
+            Some(match self.metrics.not_ready() {
+                Some(reason) => Response::text(503, reason),
+                None => Response::text(200, "ready"),
+            })
+        } else if path == self.path {
+            Some(Response::new(200).with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                                   .with_body(self.metrics.render()))
+        } else {
+            None
+        }
+    }
+}
+
+impl<H: Handler> Handler for Monitor<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        if request.method == "GET" || request.method == "HEAD" {
+            if let Some(response) = self.endpoint(&request.path) {
+                return response.with_header("Cache-Control", "no-store");
+            }
+        }
+
+        let start = Instant::now();
+        let response = errors::call(&self.inner, request); // so panics are counted as the 500s they become
+        self.metrics.record(request.route.as_deref(), response.status, start.elapsed().as_secs_f64());
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use router::Router;
+
+    fn body(response: &Response) -> String {
+        String::from_utf8_lossy(response.body.bytes().unwrap()).into_owned()
+    }
+
+    #[test]
+    fn counts_requests_by_route() {
+        let router = Router::new().get("/users", |_: &mut Request| Response::text(200, "users"))
+                                  .get("/panic", |_: &mut Request| -> Response { panic!("broken") });
+        let monitor = Monitor::new(router, Arc::new(Metrics::new()));
+
+        for path in &["/users", "/users", "/panic", "/missing"] {
+            monitor.handle(&mut Request::new("GET", path));
+        }
+        let text = body(&monitor.handle(&mut Request::new("GET", "/metrics")));
+
+        assert!(text.contains("http_requests_total{route=\"/users\",status=\"200\"} 2\n"));
+        assert!(text.contains("http_requests_total{route=\"/panic\",status=\"500\"} 1\n"));
+        assert!(text.contains("http_requests_total{route=\"other\",status=\"404\"} 1\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{route=\"/users\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("# TYPE http_request_duration_seconds histogram\n"));
+        assert!(!text.contains("route=\"/metrics\""));
+    }
+
+    #[test]
+    fn reports_readiness() {
+        let shutdown = Shutdown::new();
+        let monitor = Monitor::new(|_: &mut Request| Response::text(200, "hi"), Arc::new(Metrics::new().shutdown(shutdown.clone())));
+
+        assert_eq!(200, monitor.handle(&mut Request::new("GET", "/readyz")).status);
+        shutdown.request();
+
+        let response = monitor.handle(&mut Request::new("GET", "/readyz"));
+        assert_eq!((503, "shutting down".to_string()), (response.status, body(&response)));
+        assert_eq!(200, monitor.handle(&mut Request::new("GET", "/healthz")).status);
+        assert!(body(&monitor.handle(&mut Request::new("GET", "/metrics"))).contains("\nready 0\n"));
+    }
+}
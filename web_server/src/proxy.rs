@@ -0,0 +1,408 @@
+// Reverse proxy forwarding requests to other servers
+//
+//     [proxy /api]
+//     upstreams = 127.0.0.1:9001 127.0.0.1:9002   # used in turns
+//     timeout = 30                                # seconds to wait for an upstream to answer
+//     max_fails = 1                               # failures in a row before an upstream is left out...
+//     fail_timeout = 10                           # ...for this many seconds
+//     strip_prefix = no                           # forward /api/users as /users
+//
+// Request bodies are streamed to the upstream and its responses back to the client as they arrive.
+
+use std::io::{self, BufReader};
+use std::mem;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use config::{ConfigError, Section};
+use handler::Handler;
+use http::{self, Body, Headers, ParseError, Request, Response};
+
+// Meaningful only for a single connection, so never forwarded
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection", "Keep-Alive", "Proxy-Authenticate", "Proxy-Authorization", "TE", "Trailer", "Transfer-Encoding", "Upgrade",
+];
+
+struct Upstream {
+    address: String,
+    failures: AtomicUsize, // in a row
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn is_available(&self, now: Instant) -> bool {
+        match *self.down_until.lock().unwrap() {
+            Some(until) => now >= until,
+            None => true,
+        }
+    }
+}
+
+enum Failure {
+    Connect(io::Error), // nothing was sent, so another upstream can be tried
+    Timeout,
+    Other(String),
+}
+
+pub struct Proxy {
+    prefix: String,
+    strip_prefix: bool,
+    upstreams: Vec<Upstream>,
+    next: AtomicUsize,
+    timeout: Duration,
+    max_fails: usize,
+    fail_timeout: Duration,
+}
+
+impl Proxy {
+    pub fn new(prefix: &str, upstreams: &[&str]) -> Proxy {
+        assert!(!upstreams.is_empty());
+
+        Proxy {
+            prefix: prefix.to_string(),
+            strip_prefix: false,
+            upstreams: upstreams.iter().map(|&address| Upstream {
+                address: address.to_string(),
+                failures: AtomicUsize::new(0),
+                down_until: Mutex::new(None),
+            }).collect(),
+            next: AtomicUsize::new(0),
+            timeout: Duration::from_secs(30),
+            max_fails: 1,
+            fail_timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn from_section(section: &Section) -> Result<Proxy, ConfigError> {
+        let invalid = |message: &str| ConfigError::Invalid { line: section.line, message: message.to_string() };
+
+        let prefix = match section.argument {
+            Some(ref prefix) if prefix.starts_with('/') => prefix,
+            _ => return Err(invalid("expected [proxy /path]")),
+        };
+        let upstreams = section.get_list("upstreams");
+        if upstreams.is_empty() {
+            return Err(invalid("missing upstreams"));
+        }
+
+        let upstreams: Vec<&str> = upstreams.iter().map(|u| u.as_str()).collect();
+        let mut proxy = Proxy::new(prefix, &upstreams);
+
+        if let Some(timeout) = section.get_parsed("timeout")? {
+            proxy = proxy.timeout(Duration::from_secs(timeout));
+        }
+        if let Some(max_fails) = section.get_parsed("max_fails")? {
+            proxy = proxy.max_fails(max_fails);
+        }
+        if let Some(fail_timeout) = section.get_parsed("fail_timeout")? {
+            proxy = proxy.fail_timeout(Duration::from_secs(fail_timeout));
+        }
+        if let Some(strip) = section.get_bool("strip_prefix")? {
+            proxy = proxy.strip_prefix(strip);
+        }
+
+        Ok(proxy)
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_fails(mut self, max_fails: usize) -> Proxy {
+        self.max_fails = max_fails.max(1);
+        self
+    }
+
+    pub fn fail_timeout(mut self, fail_timeout: Duration) -> Proxy {
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    pub fn strip_prefix(mut self, strip: bool) -> Proxy {
+        self.strip_prefix = strip;
+        self
+    }
+
+    // Round robin over the upstreams not left out after failing, or over all of them if none is left
+    fn candidates(&self) -> Vec<&Upstream> {
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = self.upstreams.len();
+
+        let in_turn: Vec<&Upstream> = (0..count).map(|i| &self.upstreams[(start + i) % count]).collect();
+        let available: Vec<&Upstream> = in_turn.iter().cloned().filter(|u| u.is_available(now)).collect();
+
+        if available.is_empty() { in_turn } else { available }
+    }
+
+    fn record_success(&self, upstream: &Upstream) {
+        upstream.failures.store(0, Ordering::Relaxed);
+        *upstream.down_until.lock().unwrap() = None;
+    }
+
+    fn record_failure(&self, upstream: &Upstream) {
+        let failures = upstream.failures.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if failures >= self.max_fails {
+            eprintln!("Upstream {} failed {} time(s), leaving it out for {:?}", upstream.address, failures, self.fail_timeout);
+            *upstream.down_until.lock().unwrap() = Some(Instant::now() + self.fail_timeout);
+        }
+    }
+
+    // The request to send to the upstream, which takes the body along
+    fn upstream_request(&self, request: &mut Request, upstream: &Upstream) -> Request {
+        let target = if self.strip_prefix && self.prefix != "/" {
+            // The prefix matched the decoded path, which the raw target may spell differently
+            let rest = request.path.get(self.prefix.trim_end_matches('/').len()..).unwrap_or("");
+            let rest = if rest.starts_with('/') { http::percent_encode(rest) } else { format!("/{}", http::percent_encode(rest)) };
+            match request.query {
+                Some(ref query) => format!("{}?{}", rest, query),
+                None => rest,
+            }
+        } else {
+            request.target.clone()
+        };
+
+        let mut forwarded = Request::new(&request.method, &target);
+        forwarded.headers = request.headers.clone();
+        forwarded.body = mem::replace(&mut request.body, Body::empty());
+
+        remove_hop_by_hop(&mut forwarded.headers);
+
+        if let Some(host) = request.header("Host") {
+            forwarded.headers.set("X-Forwarded-Host", host);
+        }
+        forwarded.headers.set("Host", &upstream.address);
+
+        if let Some(addr) = request.remote_addr {
+            let chain = match request.header("X-Forwarded-For") {
+                Some(previous) => format!("{}, {}", previous, addr.ip()),
+                None => addr.ip().to_string(),
+            };
+            forwarded.headers.set("X-Forwarded-For", &chain);
+        }
+        forwarded.headers.set("X-Forwarded-Proto", if request.secure { "https" } else { "http" });
+        forwarded.headers.set("Connection", "close");
+
+        forwarded
+    }
+
+    fn forward(&self, request: &mut Request, upstream: &Upstream) -> Result<Response, Failure> {
+        let addresses: Vec<SocketAddr> = upstream.address.to_socket_addrs().map_err(Failure::Connect)?.collect();
+        let mut stream = None;
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address");
+
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(s) => {
+                    stream = Some(s);
+                    break;
+                },
+                Err(e) => last_error = e,
+            }
+        }
+        let mut stream = stream.ok_or(Failure::Connect(last_error))?;
+
+        let io_failure = |e: io::Error| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Failure::Timeout,
+            _ => Failure::Other(e.to_string()),
+        };
+
+        stream.set_read_timeout(Some(self.timeout)).map_err(&io_failure)?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(&io_failure)?;
+        self.upstream_request(request, upstream).write_to(&mut stream).map_err(&io_failure)?;
+
+        let mut response = match Response::read_from(BufReader::new(stream), &request.method) {
+            Ok(response) => response,
+            Err(ParseError::Io(e)) => return Err(io_failure(e)),
+            Err(e) => return Err(Failure::Other(e.to_string())),
+        };
+
+        remove_hop_by_hop(&mut response.headers);
+
+        Ok(response)
+    }
+}
+
+// The headers that are only about one connection: the usual ones and whatever Connection names
+fn remove_hop_by_hop(headers: &mut Headers) {
+    let named: Vec<String> = headers.get_all("Connection")
+                                    .flat_map(|value| value.split(','))
+                                    .map(|name| name.trim().to_string())
+                                    .filter(|name| !name.is_empty())
+                                    .collect();
+
+    for name in HOP_BY_HOP.iter().map(|name| name.to_string()).chain(named) {
+        headers.remove(&name);
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, request: &mut Request) -> Response {
+        for upstream in self.candidates() {
+            match self.forward(request, upstream) {
+                Ok(response) => {
+                    self.record_success(upstream);
+                    return response;
+                },
+                Err(Failure::Connect(e)) => {
+                    eprintln!("Couldn't connect to upstream {}: {}", upstream.address, e);
+                    self.record_failure(upstream);
+                },
+                Err(Failure::Timeout) => {
+                    eprintln!("Upstream {} timed out", upstream.address);
+                    self.record_failure(upstream);
+                    return Response::error(504, "Upstream server timed out");
+                },
+                Err(Failure::Other(e)) => {
+                    eprintln!("Upstream {} failed: {}", upstream.address, e);
+                    self.record_failure(upstream);
+                    return Response::error(502, "Bad response from upstream server");
+                },
+            }
+        }
+
+        Response::error(502, "No upstream server available")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use server;
+
+    // Serves 'connections' connections in the background, answering with the given handler
+    fn upstream<H: Handler + 'static>(connections: usize, handler: H) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                server::handle_connection(stream.unwrap(), &handler).unwrap();
+            }
+        });
+
+        address
+    }
+
+    fn echo(name: &'static str) -> impl Fn(&mut Request) -> Response + Send + Sync {
+        move |request: &mut Request| {
+            let mut headers = format!("{} {} {}\n", name, request.method, request.target);
+            for (n, v) in request.headers.iter() {
+                headers.push_str(&format!("{}: {}\n", n, v));
+            }
+            headers.push_str(&String::from_utf8_lossy(request.buffer_body().unwrap()));
+
+            Response::text(200, headers)
+        }
+    }
+
+    fn closed_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string() // nobody listens once dropped
+    }
+
+    fn send(proxy: &Proxy, method: &str, target: &str) -> (u16, String) {
+        let mut request = Request::new(method, target);
+        request.headers.add("Host", "example.com");
+        request.headers.add("Connection", "keep-alive");
+        request.remote_addr = Some("10.1.2.3:4000".parse().unwrap());
+        request.body = Body::from("payload");
+
+        let response = proxy.handle(&mut request);
+        (response.status, String::from_utf8(response.body.into_bytes().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn rewrites_forwarding_headers() {
+        let address = upstream(1, echo("a"));
+        let proxy = Proxy::new("/api", &[&address]).strip_prefix(true);
+
+        let (status, body) = send(&proxy, "POST", "/api/users?page=2");
+
+        assert_eq!(200, status);
+        assert!(body.starts_with("a POST /users?page=2\n"), "{}", body);
+        assert!(body.contains(&format!("Host: {}\n", address)));
+        assert!(body.contains("X-Forwarded-Host: example.com\n"));
+        assert!(body.contains("X-Forwarded-For: 10.1.2.3\n"));
+        assert!(body.contains("X-Forwarded-Proto: http\n"));
+        assert!(body.contains("Connection: close\n"));
+        assert!(body.ends_with("payload"));
+
+        let address = upstream(1, echo("a"));
+        let proxy = Proxy::new("/api", &[&address]).strip_prefix(true);
+        assert!(send(&proxy, "GET", "/%61pi/caf%C3%A9%20menu").1.starts_with("a GET /caf%C3%A9%20menu\n"));
+    }
+
+    #[test]
+    fn drops_headers_named_by_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let upstream = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = Request::read_from(&mut BufReader::new(&stream)).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close, X-Internal\r\nX-Internal: 1\r\nX-Public: 1\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            request
+        });
+
+        let mut request = Request::new("GET", "/api/");
+        request.headers.add("Connection", "keep-alive, X-Secret");
+        request.headers.add("Connection", "Cookie");
+        request.headers.add("X-Secret", "1");
+        request.headers.add("Cookie", "a=1");
+        request.headers.add("X-Kept", "1");
+        let response = Proxy::new("/api", &[&address]).handle(&mut request);
+
+        let forwarded = upstream.join().unwrap();
+        assert_eq!((None, None, Some("1")), (forwarded.header("X-Secret"), forwarded.header("Cookie"), forwarded.header("X-Kept")));
+        assert_eq!((200, None, Some("1")), (response.status, response.header("X-Internal"), response.header("X-Public")));
+    }
+
+    #[test]
+    fn streams_large_uploads() {
+        let address = upstream(1, |request: &mut Request| {
+            let body = mem::replace(&mut request.body, Body::empty());
+            Response::text(200, body.into_bytes().unwrap().len().to_string())
+        });
+        let proxy = Proxy::new("/", &[&address]);
+
+        let mut request = Request::new("PUT", "/upload");
+        let upload = io::repeat(b'x').take(3 * 1024 * 1024); // more than a buffered body may be
+        request.body = Body::Stream(Box::new(upload), None);
+
+        let response = proxy.handle(&mut request);
+        assert_eq!((200, Some(&b"3145728"[..])), (response.status, response.body.into_bytes().ok().as_deref()));
+    }
+
+    #[test]
+    fn balances_and_skips_failed_upstreams() {
+        let a = upstream(2, echo("a"));
+        let b = upstream(2, echo("b"));
+        let dead = closed_port();
+        let proxy = Proxy::new("/", &[&a, &dead, &b]).fail_timeout(Duration::from_secs(60));
+
+        let served: Vec<String> = (0..4).map(|_| send(&proxy, "GET", "/")).map(|(_, body)| body[..1].to_string()).collect();
+
+        assert_eq!(vec!["a", "b", "b", "a"], served); // the dead one hands its turn to the next
+    }
+
+    #[test]
+    fn reports_unavailable_and_slow_upstreams() {
+        let proxy = Proxy::new("/", &[&closed_port()]);
+        assert_eq!(502, send(&proxy, "GET", "/").0);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap(); // accepts but never answers
+        let proxy = Proxy::new("/", &[&listener.local_addr().unwrap().to_string()]).timeout(Duration::from_millis(200));
+        assert_eq!(504, send(&proxy, "GET", "/").0);
+    }
+}
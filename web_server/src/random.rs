@@ -0,0 +1,42 @@
+// Unpredictable bytes for identifiers such as session IDs
+
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use sha256;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn bytes(len: usize) -> Vec<u8> {
+    let mut buffer = vec![0; len];
+
+    if File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut buffer)).is_ok() {
+        return buffer;
+    }
+
+    // No OS source (e.g. on Windows): hash whatever varies between calls and processes
+    let mut filled = 0;
+    while filled < len {
+        let mut hasher = RandomState::new().build_hasher(); // randomly keyed per process
+        hasher.write_usize(COUNTER.fetch_add(1, Ordering::SeqCst));
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        hasher.write_u128(now);
+
+        let seed = format!("{}{}{:?}", hasher.finish(), now, ::std::thread::current().id());
+        let block = sha256::digest(seed.as_bytes());
+        let n = (len - filled).min(block.len());
+        buffer[filled..filled + n].copy_from_slice(&block[..n]);
+        filled += n;
+    }
+
+    buffer
+}
+
+// Hex encoded, 'len' random bytes long
+pub fn token(len: usize) -> String {
+    sha256::hex(&bytes(len))
+}
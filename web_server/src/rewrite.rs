@@ -0,0 +1,427 @@
+// Redirects and internal rewrites of request paths
+//
+//     [rewrite]
+//     host = docs.example.com    # the site the rules are for, every site if missing
+//     rules = rewrite.rules      # reloaded whenever the file changes
+//     trailing_slash = add       # or 'remove', or 'keep' as they come (the default)
+//
+// The rules file holds one rule per line, tried in order until one matches:
+//
+//     redirect 301 /old-page /new-page
+//     redirect 302 /blog/*/* /posts/$1/$2?from=blog
+//     rewrite /manual/** /docs/$1
+//
+// '*' matches within a path segment and '**' across segments, and $1 to $9 in the target stand
+// for what they matched. Redirects send the client elsewhere, while rewrites change the path the
+// inner handler (usually a router) sees. Either way the query string is kept unless the target
+// has its own.
+//
+// Adding trailing slashes suits paths to directories, so names with an extension are left
+// alone; removing them doesn't mix with directory indexes, which redirect the other way.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use config::{ConfigError, Section};
+use handler::Handler;
+use http::{self, Request, Response};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Segment, // *
+    Any,     // **
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Pattern {
+        let mut tokens = Vec::new();
+        let mut rest = pattern;
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("**") {
+                tokens.push(Token::Any);
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('*') {
+                tokens.push(Token::Segment);
+                rest = after;
+            } else {
+                let end = rest.find('*').unwrap_or(rest.len());
+                tokens.push(Token::Literal(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+
+        Pattern { tokens }
+    }
+
+    // What each wildcard matched, if the whole path matches
+    fn captures(&self, path: &str) -> Option<Vec<String>> {
+        let mut captures = Vec::new();
+
+        if match_tokens(&self.tokens, path, &mut captures) {
+            Some(captures)
+        } else {
+            None
+        }
+    }
+}
+
+// Wildcards match as little as possible, backtracking when the rest doesn't match
+fn match_tokens(tokens: &[Token], path: &str, captures: &mut Vec<String>) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return path.is_empty(),
+    };
+
+    match *token {
+        Token::Literal(ref literal) => path.strip_prefix(literal.as_str())
+                                           .is_some_and(|after| match_tokens(rest, after, captures)),
+        Token::Segment | Token::Any => {
+            for end in (0..=path.len()).filter(|&i| path.is_char_boundary(i)) {
+                if *token == Token::Segment && path[..end].contains('/') {
+                    break;
+                }
+
+                captures.push(path[..end].to_string());
+                if match_tokens(rest, &path[end..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+
+            false
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Redirect(u16),
+    Rewrite,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    action: Action,
+    pattern: Pattern,
+    target: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn new() -> Rules {
+        Rules::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Rules, ConfigError> {
+        Rules::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Rules, ConfigError> {
+        let mut rules = Rules::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |message: &str| ConfigError::Invalid { line: i + 1, message: message.to_string() };
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            rules = match words[..] {
+                ["redirect", status, from, to] => match status.parse() {
+                    Ok(status @ 301) | Ok(status @ 302) | Ok(status @ 303) | Ok(status @ 307) | Ok(status @ 308) => {
+                        rules.redirect(status, from, to)
+                    },
+                    _ => return Err(invalid("redirect status must be 301, 302, 303, 307 or 308")),
+                },
+                ["rewrite", from, to] if to.starts_with('/') => rules.rewrite(from, to),
+                ["rewrite", _, _] => return Err(invalid("rewrite targets must be paths")),
+                _ => return Err(invalid("expected 'redirect STATUS FROM TO' or 'rewrite FROM TO'")),
+            };
+        }
+
+        Ok(rules)
+    }
+
+    pub fn redirect(self, status: u16, from: &str, to: &str) -> Rules {
+        self.add(Action::Redirect(status), from, to)
+    }
+
+    pub fn rewrite(self, from: &str, to: &str) -> Rules {
+        self.add(Action::Rewrite, from, to)
+    }
+
+    fn add(mut self, action: Action, from: &str, to: &str) -> Rules {
+        self.rules.push(Rule { action, pattern: Pattern::new(from), target: to.to_string() });
+        self
+    }
+
+    // What the first matching rule says to do with a path, and the target with its captures
+    pub fn apply(&self, path: &str) -> Option<(Action, String)> {
+        self.rules.iter().find_map(|rule| {
+            rule.pattern.captures(path).map(|captures| (rule.action, substitute(&rule.target, &captures)))
+        })
+    }
+}
+
+// Replaces $1 to $9 with the corresponding captures, empty if there's no such capture
+fn substitute(target: &str, captures: &[String]) -> String {
+    let mut result = String::with_capacity(target.len());
+    let mut chars = target.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().and_then(|d| d.to_digit(10))) {
+            ('$', Some(n)) if n > 0 => {
+                chars.next();
+                result.push_str(captures.get(n as usize - 1).map_or("", |c| c.as_str()));
+            },
+            (c, _) => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrailingSlash {
+    Keep,
+    Add,
+    Remove,
+}
+
+struct Loaded {
+    rules: Arc<Rules>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+// Middleware applying the rules before the inner handler sees the request
+pub struct Rewrites<H> {
+    file: Option<PathBuf>,
+    loaded: Mutex<Loaded>,
+    check_every: Duration,
+    trailing_slash: TrailingSlash,
+    inner: H,
+}
+
+impl<H: Handler> Rewrites<H> {
+    pub fn new(inner: H, rules: Rules) -> Rewrites<H> {
+        Rewrites {
+            file: None,
+            loaded: Mutex::new(Loaded { rules: Arc::new(rules), modified: None, checked: Instant::now() }),
+            check_every: Duration::from_secs(1),
+            trailing_slash: TrailingSlash::Keep,
+            inner,
+        }
+    }
+
+    // Rules from a file, which is checked for changes at most once a second
+    pub fn load<P: Into<PathBuf>>(inner: H, path: P) -> Result<Rewrites<H>, ConfigError> {
+        let path = path.into();
+        let modified = fs::metadata(&path)?.modified().ok();
+        let rewrites = Rewrites::new(inner, Rules::load(&path)?);
+
+        rewrites.loaded.lock().unwrap().modified = modified;
+        Ok(Rewrites { file: Some(path), ..rewrites })
+    }
+
+    pub fn from_section(inner: H, section: &Section) -> Result<Rewrites<H>, ConfigError> {
+        let rewrites = match section.get("rules") {
+            Some(path) => Rewrites::load(inner, path)?,
+            None => Rewrites::new(inner, Rules::new()),
+        };
+
+        let trailing_slash = match section.get("trailing_slash") {
+            None | Some("keep") => TrailingSlash::Keep,
+            Some("add") => TrailingSlash::Add,
+            Some("remove") => TrailingSlash::Remove,
+            Some(_) => return Err(ConfigError::Invalid { line: section.line,
+                                                         message: "trailing_slash must be add, remove or keep".to_string() }),
+        };
+
+        Ok(rewrites.trailing_slash(trailing_slash))
+    }
+
+    pub fn trailing_slash(mut self, trailing_slash: TrailingSlash) -> Rewrites<H> {
+        self.trailing_slash = trailing_slash;
+        self
+    }
+
+    pub fn check_every(mut self, interval: Duration) -> Rewrites<H> {
+        self.check_every = interval;
+        self
+    }
+
+    // The current rules, reloading the file first if it changed; broken files are reported and
+    // the previous rules kept
+    fn rules(&self) -> Arc<Rules> {
+        let mut loaded = self.loaded.lock().unwrap();
+
+        if let Some(ref file) = self.file {
+            if loaded.checked.elapsed() >= self.check_every {
+                loaded.checked = Instant::now();
+
+                let modified = fs::metadata(file).and_then(|m| m.modified()).ok();
+                if modified != loaded.modified {
+                    loaded.modified = modified;
+                    match Rules::load(file) {
+                        Ok(rules) => loaded.rules = Arc::new(rules),
+                        Err(e) => eprintln!("Couldn't reload {}: {}", file.display(), e),
+                    }
+                }
+            }
+        }
+
+        Arc::clone(&loaded.rules)
+    }
+
+    fn normalized(&self, path: &str) -> Option<String> {
+        let path = local(path);
+        let last = path.rsplit('/').next().unwrap_or("");
+
+        match self.trailing_slash {
+            TrailingSlash::Add if !path.ends_with('/') && !last.contains('.') => Some(format!("{}/", path)),
+            TrailingSlash::Remove if path.len() > 1 && path.ends_with('/') => Some(path.trim_end_matches('/').to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl<H: Handler> Handler for Rewrites<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        let query = request.query.as_ref().map_or(String::new(), |q| format!("?{}", q));
+
+        if let Some(path) = self.normalized(&request.path) {
+            let status = if request.method == "GET" || request.method == "HEAD" { 301 } else { 308 };
+            return Response::new(status).with_header("Location", &format!("{}{}", http::percent_encode(&path), query));
+        }
+
+        match self.rules().apply(&request.path) {
+            Some((Action::Redirect(status), target)) => {
+                let location = if target.contains("://") {
+                    target // elsewhere, as written
+                } else {
+                    with_query(&target, &query)
+                };
+
+                Response::new(status).with_header("Location", &location)
+            },
+            Some((Action::Rewrite, target)) => {
+                let target = with_query(&target, &query);
+
+                let (path, query) = http::split_target(&target);
+                request.target = target;
+                request.path = path;
+                request.query = query;
+
+                self.inner.handle(request)
+            },
+            None => self.inner.handle(request),
+        }
+    }
+}
+
+// Encodes the path of a target, adding the original query unless it has its own
+fn with_query(target: &str, query: &str) -> String {
+    match target.split_once('?') {
+        Some((path, target_query)) => format!("{}?{}", http::percent_encode(&local(path)), target_query),
+        None => format!("{}{}", http::percent_encode(&local(target)), query),
+    }
+}
+
+// A path on this server: one starting with "//" would send redirected clients to another host
+fn local(path: &str) -> String {
+    format!("/{}", path.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use temp::TempDir;
+
+    fn echo() -> impl Fn(&mut Request) -> Response + Send + Sync {
+        |request: &mut Request| Response::text(200, request.target.as_str())
+    }
+
+    fn target(response: Response) -> String {
+        match response.header("Location") {
+            Some(location) => format!("{} {}", response.status, location),
+            None => String::from_utf8(response.body.into_bytes().unwrap()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn matches_exact_and_wildcard_rules() {
+        let rules = Rules::parse("# moved pages\n\
+                                  redirect 301 /old-page /new-page\n\
+                                  redirect 302 /blog/*/* /posts/$1/$2?from=blog\n\
+                                  rewrite /manual/** /docs/$1\n").unwrap();
+        let rewrites = Rewrites::new(echo(), rules);
+        let get = |target: &str| self::target(rewrites.handle(&mut Request::new("GET", target)));
+
+        assert_eq!("301 /new-page?x=1", get("/old-page?x=1"));
+        assert_eq!("/old-page/", get("/old-page/"));
+        assert_eq!("302 /posts/2024/hello%20world?from=blog", get("/blog/2024/hello%20world"));
+        assert_eq!("/blog/2024/a/b", get("/blog/2024/a/b")); // '*' stays within a segment
+        assert_eq!("/docs/guide/intro.html?v=2", get("/manual/guide/intro.html?v=2"));
+
+        let careless = Rewrites::new(echo(), Rules::parse("redirect 301 /out/** //$1\n").unwrap());
+        assert_eq!("301 /evil.com", target(careless.handle(&mut Request::new("GET", "/out/evil.com"))));
+
+        assert!(Rules::parse("redirect 200 /a /b").is_err());
+        assert!(Rules::parse("rewrite /a http://example.com/").is_err());
+    }
+
+    #[test]
+    fn normalizes_trailing_slashes() {
+        let add = Rewrites::new(echo(), Rules::new()).trailing_slash(TrailingSlash::Add);
+        assert_eq!("301 /docs/?page=2", target(add.handle(&mut Request::new("GET", "/docs?page=2"))));
+        assert_eq!("308 /docs/", target(add.handle(&mut Request::new("POST", "/docs"))));
+        assert_eq!("/style.css", target(add.handle(&mut Request::new("GET", "/style.css"))));
+        assert_eq!("301 /evil/", target(add.handle(&mut Request::new("GET", "//evil"))));
+        assert_eq!(Some("/evil/".to_string()), add.normalized("//evil"));
+
+        let remove = Rewrites::new(echo(), Rules::new()).trailing_slash(TrailingSlash::Remove);
+        assert_eq!("301 /docs", target(remove.handle(&mut Request::new("GET", "/docs/"))));
+        assert_eq!("/", target(remove.handle(&mut Request::new("GET", "/"))));
+        assert_eq!("301 /evil.com", target(remove.handle(&mut Request::new("GET", "//evil.com//"))));
+        assert_eq!(Some("/evil.com".to_string()), remove.normalized("//evil.com//"));
+        assert_eq!("////", target(remove.handle(&mut Request::new("GET", "////")))); // passed on, it's the root
+        assert_eq!(None, remove.normalized("////"));
+    }
+
+    #[test]
+    fn reloads_changed_rules_file() {
+        let dir = TempDir::new("rewrite");
+        let path = dir.join("rules");
+        fs::write(&path, "redirect 301 /a /b\n").unwrap();
+
+        let rewrites = Rewrites::load(echo(), &path).unwrap().check_every(Duration::from_secs(0));
+        assert_eq!("301 /b", target(rewrites.handle(&mut Request::new("GET", "/a"))));
+
+        fs::write(&path, "redirect 301 /a /c\n").unwrap();
+        File::options().write(true).open(&path).unwrap()
+                       .set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert_eq!("301 /c", target(rewrites.handle(&mut Request::new("GET", "/a"))));
+
+        fs::write(&path, "nonsense\n").unwrap();
+        File::options().write(true).open(&path).unwrap()
+                       .set_modified(SystemTime::now() + Duration::from_secs(20)).unwrap();
+        assert_eq!("301 /c", target(rewrites.handle(&mut Request::new("GET", "/a"))));
+    }
+}
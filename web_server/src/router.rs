@@ -0,0 +1,161 @@
+// Dispatches requests to handlers by method and path: exact paths first, then the longest matching prefix
+
+use handler::Handler;
+use http::{self, Request, Response};
+
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+}
+
+struct Route {
+    method: Option<String>, // any method when None
+    pattern: Pattern,
+    handler: Box<dyn Handler>,
+}
+
+impl Route {
+    fn matches_path(&self, path: &str) -> bool {
+        match self.pattern {
+            Pattern::Exact(ref exact) => path == exact,
+            Pattern::Prefix(ref prefix) => http::matches_prefix(path, prefix),
+        }
+    }
+
+    fn matches_method(&self, method: &str) -> bool {
+        match self.method {
+            Some(ref m) => m == method || (m == "GET" && method == "HEAD"), // HEAD is GET without a body
+            None => true,
+        }
+    }
+
+    fn label(&self) -> &str {
+        match self.pattern {
+            Pattern::Exact(ref path) | Pattern::Prefix(ref path) => path,
+        }
+    }
+
+    fn specificity(&self) -> (bool, usize) {
+        match self.pattern {
+            Pattern::Exact(ref exact) => (true, exact.len()),
+            Pattern::Prefix(ref prefix) => (false, prefix.len()),
+        }
+    }
+}
+
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Box<dyn Handler>,
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_: &mut Request| Response::error(404, "Not found")),
+        }
+    }
+
+    pub fn get<H: Handler + 'static>(self, path: &str, handler: H) -> Router {
+        self.add(Some("GET"), Pattern::Exact(path.to_string()), handler)
+    }
+
+    pub fn post<H: Handler + 'static>(self, path: &str, handler: H) -> Router {
+        self.add(Some("POST"), Pattern::Exact(path.to_string()), handler)
+    }
+
+    // Exact path, any method
+    pub fn route<H: Handler + 'static>(self, path: &str, handler: H) -> Router {
+        self.add(None, Pattern::Exact(path.to_string()), handler)
+    }
+
+    // Everything under the prefix, any method
+    pub fn prefix<H: Handler + 'static>(self, prefix: &str, handler: H) -> Router {
+        self.add(None, Pattern::Prefix(prefix.to_string()), handler)
+    }
+
+    pub fn not_found<H: Handler + 'static>(mut self, handler: H) -> Router {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    fn add<H: Handler + 'static>(mut self, method: Option<&str>, pattern: Pattern, handler: H) -> Router {
+        self.routes.push(Route {
+            method: method.map(String::from),
+            pattern,
+            handler: Box::new(handler),
+        });
+        self.routes.sort_by_key(|r| ::std::cmp::Reverse(r.specificity())); // most specific first
+        self
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &mut Request) -> Response {
+        let mut allowed: Vec<&str> = Vec::new();
+
+        for route in self.routes.iter().filter(|r| r.matches_path(&request.path)) {
+            if route.matches_method(&request.method) {
+                request.route = Some(route.label().to_string());
+                return route.handler.handle(request);
+            }
+            if let Some(ref method) = route.method {
+                allowed.push(method);
+            }
+        }
+
+        if allowed.is_empty() {
+            return self.not_found.handle(request);
+        }
+
+        if allowed.contains(&"GET") {
+            allowed.push("HEAD");
+        }
+        allowed.sort();
+        allowed.dedup();
+
+        Response::error(405, "Method not allowed").with_header("Allow", &allowed.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(text: &'static str) -> impl Fn(&mut Request) -> Response + Send + Sync {
+        move |_: &mut Request| Response::text(200, text)
+    }
+
+    fn dispatch(router: &Router, method: &str, path: &str) -> Response {
+        router.handle(&mut Request::new(method, path))
+    }
+
+    #[test]
+    fn dispatches_by_specificity() {
+        let router = Router::new().prefix("/api", answer("api"))
+                                  .prefix("/api/v2", answer("v2"))
+                                  .get("/api/status", answer("status"))
+                                  .post("/api/status", answer("posted"));
+
+        assert_eq!(Some(&b"api"[..]), dispatch(&router, "GET", "/api/users").body.bytes());
+        assert_eq!(Some(&b"v2"[..]), dispatch(&router, "DELETE", "/api/v2/users").body.bytes());
+        assert_eq!(Some(&b"status"[..]), dispatch(&router, "HEAD", "/api/status").body.bytes());
+        assert_eq!(Some(&b"posted"[..]), dispatch(&router, "POST", "/api/status").body.bytes());
+        assert_eq!(404, dispatch(&router, "GET", "/apis").status);
+    }
+
+    #[test]
+    fn reports_allowed_methods() {
+        let router = Router::new().get("/", answer("home"));
+        let response = dispatch(&router, "POST", "/");
+
+        assert_eq!(405, response.status);
+        assert_eq!(Some("GET, HEAD"), response.header("Allow"));
+    }
+}
@@ -0,0 +1,355 @@
+// Accepting connections, reading requests off them and writing back the handler's responses
+
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use ThreadPool;
+use errors::{self, ErrorPages};
+use handler::Handler;
+use http::{Body, Chunks, Framing, ParseError, Request, Response};
+use limit::{self, ConnectionLimiter};
+use tls::HttpsRedirect;
+
+const MAX_REQUESTS_PER_CONNECTION: usize = 100;
+
+// Tells a running server to stop accepting connections; those already accepted are still served
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    addresses: Arc<Mutex<Vec<SocketAddr>>>, // of the listeners to wake up
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+
+        // Listeners only notice once something connects
+        for address in self.addresses.lock().unwrap().iter() {
+            let _ = TcpStream::connect_timeout(address, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+// A connection requests are read from and responses written to, in plain text or over TLS
+trait Connection: Read + Write {
+    fn tcp(&self) -> &TcpStream;
+
+    fn is_tls(&self) -> bool {
+        false
+    }
+
+    // Before closing, once the last response is written
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Connection for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl Connection for StreamOwned<ServerConnection, TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn is_tls(&self) -> bool {
+        true
+    }
+
+    // Tells the client nothing got cut off
+    fn finish(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    https: Option<(TcpListener, Arc<ServerConfig>)>,
+    redirect_to_https: bool, // answer plain requests with a redirect instead of the handler's response
+    pool: ThreadPool,
+    handler: Arc<dyn Handler>, // shared by all the workers
+    connections: Arc<ConnectionLimiter>,
+    errors: Arc<ErrorPages>, // for errors no site has dressed up, e.g. malformed requests
+    timeout: Duration,       // to receive a request once connected
+    keep_alive: Duration,    // to wait for another request on the same connection
+    shutdown: Shutdown,
+}
+
+impl Server {
+    pub fn new<H: Handler + 'static>(listener: TcpListener, pool: ThreadPool, handler: H) -> Server {
+        Server {
+            listener,
+            https: None,
+            redirect_to_https: false,
+            pool,
+            handler: Arc::new(handler),
+            connections: Arc::new(ConnectionLimiter::default()),
+            errors: Arc::new(ErrorPages::new()),
+            timeout: Duration::from_secs(30),
+            keep_alive: Duration::from_secs(5),
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    pub fn error_pages(mut self, pages: ErrorPages) -> Server {
+        self.errors = Arc::new(pages);
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> Server {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connection_limiter(mut self, limiter: ConnectionLimiter) -> Server {
+        self.connections = Arc::new(limiter);
+        self
+    }
+
+    // Idle connections hold on to a worker, so this is better kept short; zero turns keep-alive off
+    pub fn keep_alive(mut self, timeout: Duration) -> Server {
+        self.keep_alive = timeout;
+        self
+    }
+
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Server {
+        self.shutdown = shutdown;
+        self
+    }
+
+    // Also serves the handler over TLS on a second listener
+    pub fn https(mut self, listener: TcpListener, config: Arc<ServerConfig>) -> Server {
+        self.https = Some((listener, config));
+        self
+    }
+
+    // Only once there's an HTTPS listener to redirect to
+    pub fn redirect_to_https(mut self, redirect: bool) -> Server {
+        self.redirect_to_https = redirect;
+        self
+    }
+
+    pub fn https_addr(&self) -> Option<SocketAddr> {
+        self.https.as_ref().and_then(|(listener, _)| listener.local_addr().ok())
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Returns once shut down; dropping the server then waits for the workers to finish
+    pub fn run(&self) {
+        {
+            let mut addresses = self.shutdown.addresses.lock().unwrap();
+            addresses.extend(self.local_addr().ok());
+            addresses.extend(self.https_addr());
+        }
+
+        let plain: Arc<dyn Handler> = match self.https_addr() {
+            Some(https) if self.redirect_to_https => Arc::new(HttpsRedirect::new(https.port())),
+            _ => Arc::clone(&self.handler),
+        };
+
+        thread::scope(|scope| {
+            if let Some((ref listener, ref config)) = self.https {
+                scope.spawn(move || self.listen(listener, &self.handler, Some(config)));
+            }
+            self.listen(&self.listener, &plain, None);
+        });
+    }
+
+    fn listen(&self, listener: &TcpListener, handler: &Arc<dyn Handler>, tls: Option<&Arc<ServerConfig>>) {
+        for stream in listener.incoming() {
+            if self.shutdown.is_requested() {
+                break;
+            }
+
+            match stream {
+                Ok(stream) => self.accept(stream, handler, tls),
+                Err(e) => eprintln!("Couldn't establish connection: {}", e),
+            }
+        }
+    }
+
+    fn accept(&self, mut stream: TcpStream, handler: &Arc<dyn Handler>, tls: Option<&Arc<ServerConfig>>) {
+        let addr = match stream.peer_addr() {
+            Ok(addr) => addr,
+            Err(_) => return, // already gone
+        };
+
+        // Turn excess connections away here so they never queue up in the pool
+        let guard = match self.connections.acquire(addr.ip()) {
+            Ok(guard) => guard,
+            Err(rejection) => {
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                let response = self.errors.render(None, limit::rejection_response(rejection));
+                let _ = response.with_header("Connection", "close").write_to(&mut stream, true);
+                return;
+            }
+        };
+
+        if let Err(e) = stream.set_read_timeout(Some(self.timeout)) {
+            eprintln!("Couldn't set timeout: {}", e);
+            return;
+        }
+
+        let handler = Arc::clone(handler);
+        let errors = Arc::clone(&self.errors);
+        let keep_alive = self.keep_alive;
+        let tls = tls.cloned();
+
+        self.pool.execute(move || {
+            let _guard = guard; // released when the connection is done with
+
+            let result = match tls {
+                Some(config) => ServerConnection::new(config).map_err(io::Error::other)
+                                                             .and_then(|tls| serve(StreamOwned::new(tls, stream), &*handler, &errors, keep_alive)),
+                None => serve(stream, &*handler, &errors, keep_alive),
+            };
+            if let Err(e) = result {
+                eprintln!("Connection error: {}", e);
+            }
+        });
+    }
+}
+
+// The body of the request being served, read off the connection only as the handler asks for it,
+// so that e.g. a proxy can pass on uploads of any size. The connection is shared with the server,
+// which needs it back to write the response.
+struct RequestBody<C> {
+    connection: Arc<Mutex<BufReader<C>>>,
+    framing: Framing, // how much is left of it
+    chunks: Chunks,
+    expects_continue: bool, // the client waits for a go-ahead before sending it
+    finished: Arc<AtomicBool>, // read to the end, so the connection can take another request
+}
+
+impl<C: Connection> Read for RequestBody<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Only the response being written holds it meanwhile, and it can't wait for itself
+        let mut connection = self.connection.try_lock()
+                                 .map_err(|_| io::Error::other("request body read while the response is written"))?;
+
+        if self.expects_continue {
+            self.expects_continue = false;
+            connection.get_mut().write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            connection.get_mut().flush()?;
+        }
+
+        let read = match self.framing {
+            Framing::Length(0) => 0, // reading more would wait for the next request
+            Framing::Length(left) => {
+                let max = left.min(buf.len() as u64) as usize;
+                let read = connection.read(&mut buf[..max])?;
+                if read == 0 && max > 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "request body cut short"));
+                }
+                self.framing = Framing::Length(left - read as u64);
+                read
+            },
+            Framing::Chunked => self.chunks.read(&mut *connection, buf)?,
+            Framing::Empty | Framing::UntilClose => 0,
+        };
+
+        if self.framing == Framing::Length(0) || self.chunks.is_done() {
+            self.finished.store(true, Ordering::SeqCst);
+        }
+        Ok(read)
+    }
+}
+
+// Serves a single request, for connections the caller won't keep open
+pub fn handle_connection<H: Handler + ?Sized>(stream: TcpStream, handler: &H) -> io::Result<()> {
+    serve(stream, handler, &ErrorPages::new(), Duration::from_secs(0))
+}
+
+fn serve<C, H>(connection: C, handler: &H, errors: &ErrorPages, keep_alive: Duration) -> io::Result<()>
+    where C: Connection + Send + 'static, H: Handler + ?Sized
+{
+    let remote_addr = connection.tcp().peer_addr().ok();
+    let secure = connection.is_tls();
+    let connection = Arc::new(Mutex::new(BufReader::new(connection)));
+    let mut served = 0;
+
+    loop {
+        let head = Request::read_head(&mut *connection.lock().unwrap());
+        let error = match head {
+            Ok((mut request, framing)) => {
+                request.remote_addr = remote_addr;
+                request.secure = secure;
+                println!("[Request] {} {}", request.method, request.target);
+                served += 1;
+
+                let finished = Arc::new(AtomicBool::new(framing == Framing::Empty || framing == Framing::Length(0)));
+                if !finished.load(Ordering::SeqCst) {
+                    let length = match framing {
+                        Framing::Length(length) => Some(length),
+                        _ => None,
+                    };
+                    let expects_continue = request.header("Expect").is_some_and(|e| e.eq_ignore_ascii_case("100-continue"));
+                    let body = RequestBody {
+                        connection: Arc::clone(&connection),
+                        framing,
+                        chunks: Chunks::default(),
+                        expects_continue,
+                        finished: Arc::clone(&finished),
+                    };
+                    request.body = Body::Stream(Box::new(body), length);
+                }
+
+                let response = errors::call(handler, &mut request);
+                let mut response = errors.render(Some(&request), response);
+
+                // Whatever of the body the handler left unread is in the way of the next request
+                let again = keep_alive > Duration::from_secs(0)
+                            && served < MAX_REQUESTS_PER_CONNECTION
+                            && request.wants_keep_alive()
+                            && finished.load(Ordering::SeqCst)
+                            && !response.header("Connection").is_some_and(|c| c.eq_ignore_ascii_case("close"));
+                response.headers.set("Connection", if again { "keep-alive" } else { "close" });
+                response.write_to(connection.lock().unwrap().get_mut(), request.method != "HEAD")?;
+
+                if !again {
+                    break;
+                }
+                connection.lock().unwrap().get_ref().tcp().set_read_timeout(Some(keep_alive))?;
+                continue;
+            },
+            Err(ParseError::Closed) => break,
+            Err(ParseError::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                if served > 0 {
+                    break; // nothing more to ask for
+                }
+                Response::error(408, "Timed out waiting for the request")
+            },
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(ParseError::TooLarge) => Response::error(413, "Request too large"),
+            Err(ParseError::BadRequest(msg)) => Response::error(400, msg),
+        };
+
+        errors.render(None, error).with_header("Connection", "close")
+                                  .write_to(connection.lock().unwrap().get_mut(), true)?;
+        break;
+    }
+
+    let mut connection = connection.lock().unwrap();
+    connection.get_mut().finish()
+}
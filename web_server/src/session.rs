@@ -0,0 +1,414 @@
+// Server-side sessions identified by a random ID kept in a cookie
+//
+//     [session]
+//     cookie = sid
+//     ttl = 1800                     # seconds of inactivity before a session expires
+//     directory = /var/lib/sessions  # keeps sessions across restarts; in memory if missing
+//     secure = no
+//
+// Handlers find the session in request.session. Sessions are only stored (and the cookie only
+// sent) once something is put in them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use config::{ConfigError, Section};
+use cookie::{SameSite, SetCookie};
+use handler::Handler;
+use http::{self, Request, Response};
+use random;
+
+const ID_BYTES: usize = 16;
+const PURGE_EVERY: usize = 100; // requests between sweeps of expired sessions
+
+type Data = HashMap<String, String>;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    id: String,
+    data: Data,
+    is_new: bool,
+    destroyed: bool,
+    regenerate: bool,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            id: random::token(ID_BYTES),
+            is_new: true,
+            ..Session::default()
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(|v| v.as_str())
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.data.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.data.remove(key)
+    }
+
+    // Drops the session from the store and tells the browser to forget the cookie
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.destroyed = true;
+    }
+
+    // Moves the data to a fresh ID, which should be done whenever privileges change (e.g. on login)
+    // so an ID planted by someone else before can't be used to ride the session
+    pub fn regenerate_id(&mut self) {
+        self.regenerate = true;
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_BYTES * 2 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+pub trait SessionStore: Send + Sync {
+    // None if there's no such session or it has expired
+    fn load(&self, id: &str) -> Option<Data>;
+
+    fn save(&self, id: &str, data: &Data, expires: SystemTime) -> io::Result<()>;
+
+    fn remove(&self, id: &str) -> io::Result<()>;
+
+    fn remove_expired(&self) -> io::Result<()>;
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (Data, SystemTime)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<Data> {
+        let sessions = self.sessions.lock().unwrap();
+
+        match sessions.get(id) {
+            Some((data, expires)) if *expires > SystemTime::now() => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    fn save(&self, id: &str, data: &Data, expires: SystemTime) -> io::Result<()> {
+        self.sessions.lock().unwrap().insert(id.to_string(), (data.clone(), expires));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn remove_expired(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+        self.sessions.lock().unwrap().retain(|_, &mut (_, expires)| expires > now);
+        Ok(())
+    }
+}
+
+// One file per session: the expiry time in seconds since the epoch on the first line,
+// then a percent-encoded 'key=value' line per entry
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> io::Result<FileStore> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(FileStore { directory })
+    }
+
+    fn read(&self, id: &str) -> io::Result<(Data, SystemTime)> {
+        let contents = fs::read_to_string(self.directory.join(id))?;
+        let mut lines = contents.lines();
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt session file");
+
+        let expires = lines.next().and_then(|l| l.parse().ok()).ok_or_else(invalid)?;
+        let mut data = HashMap::new();
+        for line in lines {
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            data.insert(http::percent_decode(key), http::percent_decode(value));
+        }
+
+        Ok((data, UNIX_EPOCH + Duration::from_secs(expires)))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Option<Data> {
+        match self.read(id) {
+            Ok((data, expires)) if expires > SystemTime::now() => Some(data),
+            _ => None,
+        }
+    }
+
+    fn save(&self, id: &str, data: &Data, expires: SystemTime) -> io::Result<()> {
+        let expires = expires.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut contents = format!("{}\n", expires);
+        for (key, value) in data {
+            contents.push_str(&format!("{}={}\n", http::percent_encode(key), http::percent_encode(value)));
+        }
+
+        // Write aside and rename so a crash never leaves half a session behind
+        let temporary = self.directory.join(format!("{}.tmp", id));
+        fs::write(&temporary, contents)?;
+        fs::rename(temporary, self.directory.join(id))
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.directory.join(id)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn remove_expired(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+
+        for entry in fs::read_dir(&self.directory)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !is_valid_id(&name) {
+                continue;
+            }
+            match self.read(&name) {
+                Ok((_, expires)) if expires > now => (),
+                _ => self.remove(&name)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Middleware loading the session before calling the inner handler and storing it afterwards
+pub struct Sessions<H> {
+    store: Box<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    requests: AtomicUsize,
+    inner: H,
+}
+
+impl<H: Handler> Sessions<H> {
+    pub fn new(inner: H, store: Box<dyn SessionStore>) -> Sessions<H> {
+        Sessions {
+            store,
+            cookie_name: "sid".to_string(),
+            ttl: Duration::from_secs(30 * 60),
+            secure: false,
+            requests: AtomicUsize::new(0),
+            inner,
+        }
+    }
+
+    pub fn from_section(inner: H, section: &Section) -> Result<Sessions<H>, ConfigError> {
+        let store: Box<dyn SessionStore> = match section.get("directory") {
+            Some(directory) => Box::new(FileStore::new(directory)?),
+            None => Box::new(MemoryStore::new()),
+        };
+
+        let mut sessions = Sessions::new(inner, store);
+        if let Some(name) = section.get("cookie") {
+            sessions = sessions.cookie_name(name);
+        }
+        if let Some(ttl) = section.get_parsed("ttl")? {
+            sessions = sessions.ttl(Duration::from_secs(ttl));
+        }
+        if let Some(secure) = section.get_bool("secure")? {
+            sessions = sessions.secure(secure);
+        }
+
+        Ok(sessions)
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> Sessions<H> {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Sessions<H> {
+        self.ttl = ttl;
+        self
+    }
+
+    // Only send the cookie over HTTPS
+    pub fn secure(mut self, secure: bool) -> Sessions<H> {
+        self.secure = secure;
+        self
+    }
+
+    fn cookie(&self, id: &str) -> SetCookie {
+        SetCookie::new(&self.cookie_name, id).path("/")
+                                             .http_only(true)
+                                             .same_site(SameSite::Lax)
+                                             .secure(self.secure)
+    }
+
+    fn finish(&self, session: Session, response: &mut Response) -> io::Result<()> {
+        if session.destroyed {
+            if !session.is_new {
+                self.store.remove(&session.id)?;
+                response.headers.add("Set-Cookie", &SetCookie::removal(&self.cookie_name).path("/").to_string());
+            }
+            return Ok(());
+        }
+
+        if session.is_new && session.data.is_empty() {
+            return Ok(()); // nothing worth keeping yet
+        }
+
+        let mut id = session.id;
+        if session.regenerate && !session.is_new {
+            self.store.remove(&id)?;
+            id = random::token(ID_BYTES);
+        }
+
+        // Every request pushes the expiry further away
+        self.store.save(&id, &session.data, SystemTime::now() + self.ttl)?;
+
+        if session.is_new || session.regenerate {
+            response.headers.add("Set-Cookie", &self.cookie(&id).to_string());
+        }
+
+        Ok(())
+    }
+}
+
+impl<H: Handler> Handler for Sessions<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        if self.requests.fetch_add(1, Ordering::Relaxed).is_multiple_of(PURGE_EVERY) {
+            if let Err(e) = self.store.remove_expired() {
+                eprintln!("Couldn't remove expired sessions: {}", e);
+            }
+        }
+
+        let existing = request.cookie(&self.cookie_name)
+                              .filter(|id| is_valid_id(id))
+                              .and_then(|id| self.store.load(&id).map(|data| (id, data)));
+
+        request.session = Some(match existing {
+            Some((id, data)) => Session { id, data, ..Session::default() },
+            None => Session::new(),
+        });
+
+        let mut response = self.inner.handle(request);
+
+        if let Some(session) = request.session.take() {
+            if let Err(e) = self.finish(session, &mut response) {
+                eprintln!("Couldn't store session: {}", e);
+                return Response::error(500, "Couldn't store session");
+            }
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp::TempDir;
+
+    fn visits(request: &mut Request) -> Response {
+        let session = request.session.as_mut().unwrap();
+        let visits = session.get("visits").and_then(|v| v.parse().ok()).unwrap_or(0) + 1;
+
+        if request.path == "/logout" {
+            session.destroy();
+        } else if request.path == "/login" {
+            session.regenerate_id();
+        } else if request.path != "/peek" {
+            session.insert("visits", &visits.to_string());
+        }
+
+        Response::text(200, visits.to_string())
+    }
+
+    fn get(handler: &dyn Handler, path: &str, cookie: Option<&str>) -> Response {
+        let mut request = Request::new("GET", path);
+        if let Some(cookie) = cookie {
+            request.headers.add("Cookie", &format!("theme=dark; {}", cookie));
+        }
+        handler.handle(&mut request)
+    }
+
+    fn session_cookie(response: &Response) -> String {
+        response.header("Set-Cookie").unwrap().split(';').next().unwrap().to_string()
+    }
+
+    fn remembers_visits(sessions: &dyn Handler) {
+        assert_eq!(None, get(sessions, "/peek", None).header("Set-Cookie")); // nothing stored, no cookie
+
+        let first = get(sessions, "/", None);
+        assert_eq!(Some(&b"1"[..]), first.body.bytes());
+        let cookie = session_cookie(&first);
+        assert!(first.header("Set-Cookie").unwrap().contains("HttpOnly"));
+
+        let second = get(sessions, "/", Some(&cookie));
+        assert_eq!(Some(&b"2"[..]), second.body.bytes());
+        assert_eq!(None, second.header("Set-Cookie"));
+
+        let login = get(sessions, "/login", Some(&cookie));
+        let regenerated = session_cookie(&login);
+        assert_ne!(cookie, regenerated);
+        assert_eq!(Some(&b"1"[..]), get(sessions, "/peek", Some(&cookie)).body.bytes());
+        assert_eq!(Some(&b"3"[..]), get(sessions, "/peek", Some(&regenerated)).body.bytes());
+
+        let logout = get(sessions, "/logout", Some(&regenerated));
+        assert!(logout.header("Set-Cookie").unwrap().contains("Max-Age=0"));
+        assert_eq!(Some(&b"1"[..]), get(sessions, "/peek", Some(&regenerated)).body.bytes());
+    }
+
+    #[test]
+    fn memory_sessions() {
+        remembers_visits(&Sessions::new(visits, Box::new(MemoryStore::new())));
+    }
+
+    #[test]
+    fn file_sessions_survive_restarts() {
+        let directory = TempDir::new("sessions");
+        remembers_visits(&Sessions::new(visits, Box::new(FileStore::new(&directory).unwrap())));
+
+        let cookie = session_cookie(&get(&Sessions::new(visits, Box::new(FileStore::new(&directory).unwrap())), "/", None));
+        let restarted = Sessions::new(visits, Box::new(FileStore::new(&directory).unwrap()));
+        assert_eq!(Some(&b"2"[..]), get(&restarted, "/", Some(&cookie)).body.bytes());
+    }
+
+    #[test]
+    fn expired_sessions_are_gone() {
+        let store = MemoryStore::new();
+        let mut data = HashMap::new();
+        data.insert("user".to_string(), "alice".to_string());
+
+        store.save("a", &data, SystemTime::now() - Duration::from_secs(1)).unwrap();
+        store.save("b", &data, SystemTime::now() + Duration::from_secs(60)).unwrap();
+
+        assert_eq!(None, store.load("a"));
+        assert_eq!(Some(data), store.load("b"));
+    }
+}
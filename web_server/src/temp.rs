@@ -0,0 +1,46 @@
+// A directory for a test's files, removed with everything in it when the test ends, even when it
+// fails. Shared with the integration tests, so it only uses std.
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    // The name is only there to tell where a directory left by a killed run comes from
+    pub fn new(name: &str) -> TempDir {
+        let unique = CREATED.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("web_server_{}_{}_{}", name, process::id(), unique));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+// For the constructors that take Into<PathBuf>
+impl<'a> From<&'a TempDir> for PathBuf {
+    fn from(dir: &'a TempDir) -> PathBuf {
+        dir.path.clone()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,623 @@
+// HTML templates
+//
+//     {% extends "layout.html" %}
+//     {% block content %}
+//       <h1>{{ title }}</h1>                      escaped, unless written {{ title | raw }}
+//       {% if posts %}
+//         {% for post in posts %}
+//           <a href="{{ post.url }}">{{ post.title }}</a>
+//         {% endfor %}
+//       {% else %}
+//         {% include "empty.html" %}
+//       {% endif %}
+//     {% endblock %}
+//
+// Conditions are a value, 'not' a value, or two values compared with == or !=, where a value is
+// a variable or a "quoted string". Empty strings and lists, false, zero and missing variables
+// are false. A layout marks with blocks what the templates extending it may replace.
+//
+// Templates are read from a directory and compiled the first time they're used.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use escape;
+
+const MAX_DEPTH: usize = 32; // of includes and layouts, which could otherwise loop forever
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Text(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn map() -> Value {
+        Value::Map(BTreeMap::new())
+    }
+
+    // Adds an entry to a map, for building contexts: Value::map().with("title", "Blog")
+    pub fn with<V: Into<Value>>(mut self, key: &str, value: V) -> Value {
+        if let Value::Map(ref mut map) = self {
+            map.insert(key.to_string(), value.into());
+        }
+        self
+    }
+
+    fn field(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Map(ref map) => map.get(key),
+            Value::List(ref list) => key.parse::<usize>().ok().and_then(|i| list.get(i)),
+            _ => None,
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        match *self {
+            Value::Null => false,
+            Value::Bool(b) => b,
+            Value::Number(n) => n != 0,
+            Value::Text(ref text) => !text.is_empty(),
+            Value::List(ref list) => !list.is_empty(),
+            Value::Map(ref map) => !map.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(ref text) => write!(f, "{}", text),
+            Value::List(ref list) => {
+                let items: Vec<String> = list.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", items.join(", "))
+            },
+            Value::Map(_) => write!(f, "[map]"),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(text: &'a str) -> Value {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::Text(text)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Value {
+        Value::Number(n as i64)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(list: Vec<T>) -> Value {
+        Value::List(list.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+// Where and why a template couldn't be compiled or rendered
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateError {
+    pub file: String,
+    pub line: usize, // 0 when it's about the whole file
+    pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file, self.line, self.message)
+        }
+    }
+}
+
+impl error::Error for TemplateError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Variable(Vec<String>), // path through maps, e.g. post.author.name
+    Literal(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Is(Operand),
+    Not(Operand),
+    Equal(Operand, Operand),
+    NotEqual(Operand, Operand),
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Output { value: Operand, raw: bool, line: usize },
+    If { condition: Condition, then: Vec<Node>, otherwise: Vec<Node> },
+    For { name: String, list: Operand, body: Vec<Node>, line: usize },
+    Include { template: String, line: usize },
+    Block { name: String, body: Arc<Vec<Node>> },
+}
+
+#[derive(Debug)]
+pub struct Template {
+    name: String,
+    extends: Option<(String, usize)>,
+    nodes: Vec<Node>,
+    blocks: HashMap<String, Arc<Vec<Node>>>,
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Output(&'a str, usize),
+    Tag(&'a str, usize),
+}
+
+fn tokenize<'a>(name: &str, source: &'a str) -> Result<Vec<Token<'a>>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+
+    while let Some(start) = rest.find('{') {
+        let close = match rest[start..].get(..2) {
+            Some("{{") => "}}",
+            Some("{%") => "%}",
+            Some("{#") => "#}",
+            _ => {
+                // A lone brace is just text
+                let (text, after) = rest.split_at(start + 1);
+                tokens.push(Token::Text(text));
+                line += text.matches('\n').count();
+                rest = after;
+                continue;
+            },
+        };
+
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+            line += rest[..start].matches('\n').count();
+        }
+
+        let inside = &rest[start + 2..];
+        let end = inside.find(close).ok_or_else(|| TemplateError {
+            file: name.to_string(),
+            line,
+            message: format!("missing '{}'", close),
+        })?;
+
+        match close {
+            "}}" => tokens.push(Token::Output(inside[..end].trim(), line)),
+            "%}" => tokens.push(Token::Tag(inside[..end].trim(), line)),
+            _ => {}, // comment
+        }
+
+        line += inside[..end].matches('\n').count();
+        rest = &inside[end + 2..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    Ok(tokens)
+}
+
+type Closer<'a> = (&'a str, usize); // the tag ending a list of nodes, and its line
+
+struct Parser<'a> {
+    name: &'a str,
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    extends: Option<(String, usize)>,
+    blocks: HashMap<String, Arc<Vec<Node>>>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, line: usize, message: String) -> TemplateError {
+        TemplateError { file: self.name.to_string(), line, message }
+    }
+
+    // Nodes up to one of the given closing tags, which is returned along with its line
+    fn nodes(&mut self, closers: &[&str]) -> Result<(Vec<Node>, Option<Closer<'a>>), TemplateError> {
+        let mut nodes = Vec::new();
+
+        while self.pos < self.tokens.len() {
+            self.pos += 1;
+
+            match self.tokens[self.pos - 1] {
+                Token::Text(text) => nodes.push(Node::Text(text.to_string())),
+                Token::Output(expression, line) => {
+                    let (expression, raw) = match expression.split_once('|') {
+                        Some((expression, filter)) if filter.trim() == "raw" => (expression.trim(), true),
+                        Some((_, filter)) => return Err(self.error(line, format!("unknown filter '{}'", filter.trim()))),
+                        None => (expression, false),
+                    };
+                    nodes.push(Node::Output { value: self.operand(expression, line)?, raw, line });
+                },
+                Token::Tag(tag, line) => {
+                    let (keyword, rest) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+                    let rest = rest.trim();
+
+                    if closers.contains(&keyword) {
+                        if !rest.is_empty() && keyword != "block" && keyword != "endblock" {
+                            return Err(self.error(line, format!("unexpected '{}' after {}", rest, keyword)));
+                        }
+                        return Ok((nodes, Some((keyword, line))));
+                    }
+
+                    nodes.push(self.tag(keyword, rest, line)?);
+                },
+            }
+        }
+
+        Ok((nodes, None))
+    }
+
+    fn tag(&mut self, keyword: &str, rest: &str, line: usize) -> Result<Node, TemplateError> {
+        match keyword {
+            "if" => {
+                let condition = self.condition(rest, line)?;
+                let (then, closer) = self.nodes(&["else", "endif"])?;
+                let otherwise = match closer {
+                    Some(("else", _)) => self.closed(&["endif"], "if", line)?,
+                    Some(_) => Vec::new(),
+                    None => return Err(self.error(line, "if without endif".to_string())),
+                };
+                Ok(Node::If { condition, then, otherwise })
+            },
+            "for" => {
+                let words: Vec<&str> = rest.split_whitespace().collect();
+                let (name, list) = match words[..] {
+                    [name, "in", list] if is_identifier(name) => (name, list),
+                    _ => return Err(self.error(line, "expected 'for NAME in LIST'".to_string())),
+                };
+                let list = self.operand(list, line)?;
+                let body = self.closed(&["endfor"], "for", line)?;
+                Ok(Node::For { name: name.to_string(), list, body, line })
+            },
+            "include" => Ok(Node::Include { template: self.quoted(rest, line)?, line }),
+            "extends" => {
+                if self.extends.is_some() {
+                    return Err(self.error(line, "a template can extend only one layout".to_string()));
+                }
+                self.extends = Some((self.quoted(rest, line)?, line));
+                Ok(Node::Text(String::new()))
+            },
+            "block" => {
+                if !is_identifier(rest) {
+                    return Err(self.error(line, "expected 'block NAME'".to_string()));
+                }
+                let body = Arc::new(self.closed(&["endblock"], "block", line)?);
+                if self.blocks.insert(rest.to_string(), Arc::clone(&body)).is_some() {
+                    return Err(self.error(line, format!("block '{}' defined twice", rest)));
+                }
+                Ok(Node::Block { name: rest.to_string(), body })
+            },
+            _ => Err(self.error(line, format!("unknown tag '{}'", keyword))),
+        }
+    }
+
+    fn closed(&mut self, closers: &[&str], opener: &str, line: usize) -> Result<Vec<Node>, TemplateError> {
+        match self.nodes(closers)? {
+            (nodes, Some(_)) => Ok(nodes),
+            (_, None) => Err(self.error(line, format!("{} without {}", opener, closers[0]))),
+        }
+    }
+
+    fn condition(&self, text: &str, line: usize) -> Result<Condition, TemplateError> {
+        if let Some((left, right)) = text.split_once("!=") {
+            return Ok(Condition::NotEqual(self.operand(left.trim(), line)?, self.operand(right.trim(), line)?));
+        }
+        if let Some((left, right)) = text.split_once("==") {
+            return Ok(Condition::Equal(self.operand(left.trim(), line)?, self.operand(right.trim(), line)?));
+        }
+
+        match text.strip_prefix("not ") {
+            Some(operand) => Ok(Condition::Not(self.operand(operand.trim(), line)?)),
+            None => Ok(Condition::Is(self.operand(text, line)?)),
+        }
+    }
+
+    fn operand(&self, text: &str, line: usize) -> Result<Operand, TemplateError> {
+        if text.starts_with('"') {
+            return self.quoted(text, line).map(Operand::Literal);
+        }
+
+        let path: Vec<String> = text.split('.').map(String::from).collect();
+        if path.iter().all(|p| is_identifier(p) || p.parse::<usize>().is_ok()) && is_identifier(&path[0]) {
+            Ok(Operand::Variable(path))
+        } else {
+            Err(self.error(line, format!("invalid expression '{}'", text)))
+        }
+    }
+
+    fn quoted(&self, text: &str, line: usize) -> Result<String, TemplateError> {
+        match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            Some(inside) if !inside.contains('"') => Ok(inside.to_string()),
+            _ => Err(self.error(line, format!("expected a quoted string, found '{}'", text))),
+        }
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Template {
+    pub fn parse(name: &str, source: &str) -> Result<Template, TemplateError> {
+        let mut parser = Parser { name, tokens: tokenize(name, source)?, pos: 0, extends: None, blocks: HashMap::new() };
+
+        let nodes = match parser.nodes(&["else", "endif", "endfor", "endblock"])? {
+            (nodes, None) => nodes,
+            (_, Some((closer, line))) => return Err(parser.error(line, format!("unexpected {}", closer))),
+        };
+
+        Ok(Template { name: name.to_string(), extends: parser.extends, nodes, blocks: parser.blocks })
+    }
+}
+
+// Variables in scope: loop variables over the context they were rendered with
+struct Scope<'a> {
+    context: &'a Value,
+    locals: Vec<(String, &'a Value)>,
+}
+
+impl<'a> Scope<'a> {
+    fn lookup(&self, path: &[String]) -> Option<&'a Value> {
+        let first = self.locals.iter().rev()
+                        .find(|(name, _)| *name == path[0])
+                        .map(|&(_, value)| value)
+                        .or_else(|| self.context.field(&path[0]))?;
+
+        path[1..].iter().try_fold(first, |value, key| value.field(key))
+    }
+}
+
+// The blocks overriding those of the layouts below, with the template defining them
+type Overrides = HashMap<String, (Arc<Vec<Node>>, String)>;
+
+pub struct Templates {
+    directory: PathBuf,
+    compiled: Mutex<HashMap<String, Arc<Template>>>,
+}
+
+impl Templates {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Templates {
+        Templates { directory: directory.into(), compiled: Mutex::new(HashMap::new()) }
+    }
+
+    // Registers a template that doesn't come from a file, or replaces one
+    pub fn add(&self, name: &str, source: &str) -> Result<(), TemplateError> {
+        let template = Template::parse(name, source)?;
+        self.compiled.lock().unwrap().insert(name.to_string(), Arc::new(template));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        if let Some(template) = self.compiled.lock().unwrap().get(name) {
+            return Ok(Arc::clone(template));
+        }
+
+        let error = |message: String| TemplateError { file: name.to_string(), line: 0, message };
+        if name.split('/').any(|segment| segment.is_empty() || segment.starts_with('.')) {
+            return Err(error("invalid template name".to_string()));
+        }
+
+        let source = fs::read_to_string(self.directory.join(name)).map_err(|e| error(e.to_string()))?;
+        let template = Arc::new(Template::parse(name, &source)?);
+
+        self.compiled.lock().unwrap().insert(name.to_string(), Arc::clone(&template));
+        Ok(template)
+    }
+
+    pub fn render(&self, name: &str, context: &Value) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        self.render_template(&*self.get(name)?, &mut Scope { context, locals: Vec::new() }, &Overrides::new(), &mut out, 0)?;
+        Ok(out)
+    }
+
+    fn render_template<'a>(&self, template: &Template, scope: &mut Scope<'a>, overrides: &Overrides,
+                       out: &mut String, depth: usize) -> Result<(), TemplateError> {
+        match template.extends {
+            // Render the layout instead, with our blocks unless a template extending us overrode them
+            Some((ref layout, line)) => {
+                let mut overrides = overrides.clone();
+                for (name, body) in &template.blocks {
+                    overrides.entry(name.clone()).or_insert_with(|| (Arc::clone(body), template.name.clone()));
+                }
+
+                let layout = self.nested(layout, &template.name, line, depth)?;
+                self.render_template(&layout, scope, &overrides, out, depth + 1)
+            },
+            None => self.render_nodes(&template.nodes, &template.name, scope, overrides, out, depth),
+        }
+    }
+
+    fn nested(&self, name: &str, from: &str, line: usize, depth: usize) -> Result<Arc<Template>, TemplateError> {
+        if depth >= MAX_DEPTH {
+            return Err(TemplateError { file: from.to_string(), line, message: "templates nested too deep".to_string() });
+        }
+
+        self.get(name).map_err(|e| match e.line {
+            0 => TemplateError { file: from.to_string(), line, message: format!("can't use {}: {}", name, e.message) },
+            _ => e,
+        })
+    }
+
+    fn render_nodes<'a>(&self, nodes: &[Node], file: &str, scope: &mut Scope<'a>, overrides: &Overrides,
+                        out: &mut String, depth: usize) -> Result<(), TemplateError> {
+        let error = |line: usize, message: String| TemplateError { file: file.to_string(), line, message };
+
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => out.push_str(text),
+                Node::Output { ref value, raw, line } => {
+                    let text = match *value {
+                        Operand::Literal(ref text) => text.clone(),
+                        Operand::Variable(ref path) => match scope.lookup(path) {
+                            Some(value) => value.to_string(),
+                            None => return Err(error(line, format!("undefined variable '{}'", path.join(".")))),
+                        },
+                    };
+                    out.push_str(&if raw { text } else { escape::html(&text) });
+                },
+                Node::If { ref condition, ref then, ref otherwise } => {
+                    let branch = if evaluate(condition, scope) { then } else { otherwise };
+                    self.render_nodes(branch, file, scope, overrides, out, depth)?;
+                },
+                Node::For { ref name, ref list, ref body, line } => {
+                    let items = match *list {
+                        Operand::Variable(ref path) => scope.lookup(path),
+                        Operand::Literal(_) => None,
+                    };
+                    let items = match items {
+                        Some(Value::List(items)) => items,
+                        Some(Value::Null) | None => continue, // nothing to loop over
+                        Some(_) => return Err(error(line, "can only loop over lists".to_string())),
+                    };
+
+                    for item in items {
+                        scope.locals.push((name.clone(), item));
+                        let result = self.render_nodes(body, file, scope, overrides, out, depth);
+                        scope.locals.pop();
+                        result?;
+                    }
+                },
+                Node::Include { ref template, line } => {
+                    let included = self.nested(template, file, line, depth)?;
+                    let mut inner = Scope { context: scope.context, locals: scope.locals.clone() };
+                    self.render_template(&included, &mut inner, &Overrides::new(), out, depth + 1)?;
+                },
+                Node::Block { ref name, ref body } => match overrides.get(name) {
+                    Some((body, from)) => {
+                        let mut inner = Scope { context: scope.context, locals: scope.locals.clone() };
+                        self.render_nodes(body, from, &mut inner, overrides, out, depth)?;
+                    },
+                    None => self.render_nodes(body, file, scope, overrides, out, depth)?,
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn evaluate(condition: &Condition, scope: &Scope) -> bool {
+    let value = |operand: &Operand| match *operand {
+        Operand::Literal(ref text) => Some(Value::Text(text.clone())),
+        Operand::Variable(ref path) => scope.lookup(path).cloned(),
+    };
+    let text = |operand: &Operand| value(operand).map(|v| v.to_string());
+
+    match *condition {
+        Condition::Is(ref operand) => value(operand).is_some_and(|v| v.is_true()),
+        Condition::Not(ref operand) => !value(operand).is_some_and(|v| v.is_true()),
+        Condition::Equal(ref left, ref right) => text(left) == text(right),
+        Condition::NotEqual(ref left, ref right) => text(left) != text(right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp::TempDir;
+
+    // With the directory it would load templates from, to keep until the test is done
+    fn templates(files: &[(&str, &str)]) -> (TempDir, Templates) {
+        let directory = TempDir::new("templates");
+        let templates = Templates::new(&directory);
+        for &(name, source) in files {
+            templates.add(name, source).unwrap();
+        }
+        (directory, templates)
+    }
+
+    #[test]
+    fn interpolates_conditions_and_loops() {
+        let (_directory, templates) = templates(&[
+            ("list.html", "<h1>{{ title }}</h1>{# a comment #}\n\
+                           {% if posts %}<ul>{% for post in posts %}<li>{{ post.title }} by {{ author }}</li>{% endfor %}</ul>\
+                           {% else %}No posts{% endif %}\
+                           {% if not draft %}!{% endif %}{% if kind == \"blog\" %} blog{% endif %}{{ html | raw }}"),
+        ]);
+
+        let post = |title: &str| Value::map().with("title", title);
+        let context = Value::map().with("title", "Tom & Jerry")
+                                  .with("author", "<b>me</b>")
+                                  .with("posts", vec![post("One"), post("Two")])
+                                  .with("kind", "blog")
+                                  .with("html", "<hr>");
+
+        assert_eq!("<h1>Tom &amp; Jerry</h1>\n<ul><li>One by &lt;b&gt;me&lt;/b&gt;</li><li>Two by &lt;b&gt;me&lt;/b&gt;</li></ul>! blog<hr>",
+                   templates.render("list.html", &context).unwrap());
+
+        let empty = Value::map().with("title", "").with("posts", Vec::<Value>::new()).with("draft", true).with("html", "");
+        assert_eq!("<h1></h1>\nNo posts", templates.render("list.html", &empty).unwrap());
+    }
+
+    #[test]
+    fn renders_layouts_and_includes() {
+        let (_directory, templates) = templates(&[
+            ("layout.html", "<title>{% block title %}Site{% endblock %}</title>{% include \"nav.html\" %}<main>{% block content %}{% endblock %}</main>"),
+            ("nav.html", "<nav>{{ user }}</nav>"),
+            ("page.html", "{% extends \"layout.html\" %}{% block content %}Hello {{ user }}{% endblock %}"),
+            ("special.html", "{% extends \"page.html\" %}{% block title %}Special{% endblock %}"),
+        ]);
+        let context = Value::map().with("user", "ann");
+
+        assert_eq!("<title>Site</title><nav>ann</nav><main>Hello ann</main>", templates.render("page.html", &context).unwrap());
+        assert_eq!("<title>Special</title><nav>ann</nav><main>Hello ann</main>", templates.render("special.html", &context).unwrap());
+    }
+
+    #[test]
+    fn reports_file_and_line() {
+        let (_directory, templates) = templates(&[("page.html", "<p>\n{{ missing }}</p>"), ("loop.html", "{% include \"loop.html\" %}")]);
+
+        let error = |source: &str| Template::parse("bad.html", source).unwrap_err().to_string();
+        assert_eq!("bad.html:2: if without endif", error("<p>\n{% if x %}\n"));
+        assert_eq!("bad.html:1: unknown tag 'iff'", error("{% iff x %}"));
+        assert_eq!("bad.html:3: missing '}}'", error("\n\n{{ x"));
+        assert_eq!("bad.html:1: unexpected endfor", error("{% endfor %}"));
+
+        assert_eq!("page.html:2: undefined variable 'missing'", templates.render("page.html", &Value::map()).unwrap_err().to_string());
+        assert_eq!("loop.html:1: templates nested too deep", templates.render("loop.html", &Value::map()).unwrap_err().to_string());
+        assert!(templates.render("none.html", &Value::map()).unwrap_err().to_string().starts_with("none.html: "));
+        assert!(templates.render("../etc/passwd", &Value::map()).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+// HTTPS, with the certificate picked by the name clients ask for (SNI)
+//
+//     [tls]
+//     address = 0.0.0.0:8443
+//     cert = certs/default.pem     # chain for names no site has a certificate for, optional
+//     key = certs/default.key
+//     redirect = yes               # plain HTTP requests get redirected to HTTPS
+//
+//     [host docs.example.com]
+//     cert = certs/docs.pem        # also used for the host's aliases
+//     key = certs/docs.key
+//
+// Certificate files hold the PEM chain, the server's own certificate first; key files a PKCS#8,
+// PKCS#1 or SEC1 private key.
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::crypto::ring;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use rustls_pemfile;
+
+use config::{Config, ConfigError, Section};
+use handler::Handler;
+use http::{Request, Response};
+use vhost;
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(String, io::Error), // with the file's path
+    Invalid(String),
+    Config(ConfigError),
+    Rustls(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlsError::Io(ref path, ref e) => write!(f, "couldn't read {}: {}", path, e),
+            TlsError::Invalid(ref msg) => write!(f, "{}", msg),
+            TlsError::Config(ref e) => write!(f, "{}", e),
+            TlsError::Rustls(ref e) => write!(f, "TLS error: {}", e),
+        }
+    }
+}
+
+impl error::Error for TlsError {}
+
+impl From<ConfigError> for TlsError {
+    fn from(e: ConfigError) -> TlsError {
+        TlsError::Config(e)
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(e: rustls::Error) -> TlsError {
+        TlsError::Rustls(e)
+    }
+}
+
+// Reads a certificate chain and its private key
+pub fn load(cert: &str, key: &str) -> Result<Arc<CertifiedKey>, TlsError> {
+    let open = |path: &str| File::open(Path::new(path)).map(BufReader::new).map_err(|e| TlsError::Io(path.to_string(), e));
+
+    let chain = rustls_pemfile::certs(&mut open(cert)?).collect::<Result<Vec<_>, _>>()
+                                                       .map_err(|e| TlsError::Io(cert.to_string(), e))?;
+    if chain.is_empty() {
+        return Err(TlsError::Invalid(format!("no certificates in {}", cert)));
+    }
+
+    let private_key = match rustls_pemfile::private_key(&mut open(key)?) {
+        Ok(Some(private_key)) => private_key,
+        Ok(None) => return Err(TlsError::Invalid(format!("no private key in {}", key))),
+        Err(e) => return Err(TlsError::Io(key.to_string(), e)),
+    };
+    let signing_key = ring::sign::any_supported_type(&private_key)?;
+
+    Ok(Arc::new(CertifiedKey::new(chain, signing_key)))
+}
+
+// Certificates by server name, exact names first and then the longest '*.' wildcard
+#[derive(Debug, Default)]
+pub struct Certificates {
+    names: Vec<(String, Arc<CertifiedKey>)>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl Certificates {
+    pub fn new() -> Certificates {
+        Certificates::default()
+    }
+
+    pub fn add(mut self, names: &[&str], key: Arc<CertifiedKey>) -> Certificates {
+        for name in names {
+            self.names.push((name.trim_end_matches('.').to_ascii_lowercase(), Arc::clone(&key)));
+        }
+        self
+    }
+
+    // For clients not sending a name, or one nobody else has a certificate for
+    pub fn default_cert(mut self, key: Arc<CertifiedKey>) -> Certificates {
+        self.default = Some(key);
+        self
+    }
+
+    // The [tls] certificate and those of the [host] sections; None when there's no [tls] section
+    pub fn from_config(config: &Config) -> Result<Option<Certificates>, TlsError> {
+        let section = match config.section("tls") {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+        let pair = |section: &Section| match (section.get("cert"), section.get("key")) {
+            (Some(cert), Some(key)) => load(cert, key).map(Some),
+            (None, None) => Ok(None),
+            _ => Err(TlsError::from(ConfigError::Invalid { line: section.line, message: "cert and key go together".to_string() })),
+        };
+
+        let mut certificates = Certificates::new();
+        if let Some(key) = pair(section)? {
+            certificates = certificates.default_cert(key);
+        }
+
+        for host in config.sections("host") {
+            if let (Some(name), Some(key)) = (host.argument.as_ref(), pair(host)?) {
+                let aliases = host.get_list("aliases");
+                let names: Vec<&str> = Some(name.as_str()).into_iter().chain(aliases.iter().map(|a| a.as_str())).collect();
+                certificates = certificates.add(&names, key);
+            }
+        }
+
+        if certificates.names.is_empty() && certificates.default.is_none() {
+            return Err(TlsError::from(ConfigError::Invalid { line: section.line, message: "no certificates to serve".to_string() }));
+        }
+        Ok(Some(certificates))
+    }
+
+    pub fn server_config(self) -> Result<Arc<ServerConfig>, TlsError> {
+        let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                                     .with_safe_default_protocol_versions()?
+                                     .with_no_client_auth()
+                                     .with_cert_resolver(Arc::new(self));
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Arc::new(config))
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+        let exact = self.names.iter().find(|(n, _)| *n == name);
+        let wildcard = || {
+            self.names.iter()
+                .filter(|(n, _)| n.starts_with("*.") && name.ends_with(&n[1..]) && name.len() > n.len() - 1)
+                .max_by_key(|(n, _)| n.len())
+        };
+
+        exact.or_else(wildcard).map(|(_, key)| Arc::clone(key))
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        client_hello.server_name()
+                    .and_then(|name| self.find(name))
+                    .or_else(|| self.default.clone())
+    }
+}
+
+// Answers every request with a permanent redirect to the same URL over HTTPS
+pub struct HttpsRedirect {
+    port: u16,
+}
+
+impl HttpsRedirect {
+    pub fn new(port: u16) -> HttpsRedirect {
+        HttpsRedirect { port }
+    }
+}
+
+impl Handler for HttpsRedirect {
+    fn handle(&self, request: &mut Request) -> Response {
+        let host = match request.header("Host") {
+            Some(host) => vhost::normalize_host(host),
+            None => return Response::error(400, "Missing Host header"),
+        };
+        let authority = if self.port == 443 { host } else { format!("{}:{}", host, self.port) };
+
+        Response::new(301).with_header("Location", &format!("https://{}{}", authority, request.target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_to_https() {
+        let mut request = Request::new("GET", "/docs?page=2");
+        request.headers.add("Host", "Example.com:8080");
+
+        let response = HttpsRedirect::new(8443).handle(&mut request);
+        assert_eq!((301, Some("https://example.com:8443/docs?page=2")), (response.status, response.header("Location")));
+        assert_eq!(Some("https://example.com/docs?page=2"), HttpsRedirect::new(443).handle(&mut request).header("Location"));
+    }
+}
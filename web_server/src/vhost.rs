@@ -0,0 +1,159 @@
+// Virtual hosts: a different site for each Host header value
+//
+//     [host docs.example.com]
+//     aliases = docs.local *.docs.example.com   # '*.' matches any subdomain
+//     root = sites/docs                         # document root
+//     index = index.html index.htm
+//     log = logs/docs.log                       # access log
+//     default = yes                             # also answers requests for unknown hosts
+//
+// Route sections such as [proxy] or [cgi] belong to a single site when they have a
+// 'host = docs.example.com' entry, and to every site otherwise.
+
+use config::Section;
+use handler::Handler;
+use http::{Request, Response};
+
+enum HostPattern {
+    Exact(String),
+    Wildcard(String), // the suffix, leading dot included
+}
+
+impl HostPattern {
+    fn new(name: &str) -> HostPattern {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+        if name.starts_with("*.") {
+            HostPattern::Wildcard(name[1..].to_string())
+        } else {
+            HostPattern::Exact(name)
+        }
+    }
+}
+
+struct VirtualHost {
+    patterns: Vec<HostPattern>,
+    handler: Box<dyn Handler>,
+}
+
+#[derive(Default)]
+pub struct VirtualHosts {
+    hosts: Vec<VirtualHost>,
+    default: Option<usize>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> VirtualHosts {
+        VirtualHosts::default()
+    }
+
+    pub fn host<H: Handler + 'static>(mut self, names: &[&str], handler: H) -> VirtualHosts {
+        self.hosts.push(VirtualHost {
+            patterns: names.iter().map(|n| HostPattern::new(n)).collect(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    // Also answers requests without a Host header or for hosts nobody else serves
+    pub fn default_host<H: Handler + 'static>(mut self, names: &[&str], handler: H) -> VirtualHosts {
+        self.default = Some(self.hosts.len());
+        self.host(names, handler)
+    }
+
+    // Exact names win over wildcards, and longer wildcards over shorter ones
+    fn find(&self, host: &str) -> Option<&VirtualHost> {
+        let exact = self.hosts.iter().find(|h| h.patterns.iter().any(|p| match *p {
+            HostPattern::Exact(ref name) => name == host,
+            HostPattern::Wildcard(_) => false,
+        }));
+
+        let wildcard = || {
+            self.hosts.iter()
+                .filter_map(|h| {
+                    h.patterns.iter()
+                     .filter_map(|p| match *p {
+                         HostPattern::Wildcard(ref suffix) if host.ends_with(suffix.as_str()) && host.len() > suffix.len() => Some(suffix.len()),
+                         _ => None,
+                     })
+                     .max()
+                     .map(|len| (len, h))
+                })
+                .max_by_key(|&(len, _)| len)
+                .map(|(_, h)| h)
+        };
+
+        exact.or_else(wildcard)
+             .or_else(|| self.default.map(|i| &self.hosts[i]))
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: &mut Request) -> Response {
+        let host = request.header("Host").map(normalize_host).unwrap_or_default();
+
+        match self.find(&host) {
+            Some(site) => site.handler.handle(request),
+            None => Response::error(404, "No such site"),
+        }
+    }
+}
+
+// Lowercase, without port nor trailing dot: "Docs.Example.com.:8080" is "docs.example.com"
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let without_port = if host.starts_with('[') {
+        host.find(']').map_or(host, |end| &host[..end + 1]) // IPv6 literal
+    } else {
+        host.rsplit_once(':').map_or(host, |(name, _)| name)
+    };
+
+    without_port.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// Whether a route section applies to the site with the given name
+pub fn belongs_to(section: &Section, host: &str) -> bool {
+    section.get("host").is_none_or(|h| h.eq_ignore_ascii_case(host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(name: &'static str) -> impl Fn(&mut Request) -> Response + Send + Sync {
+        move |_: &mut Request| Response::text(200, name)
+    }
+
+    fn serve(hosts: &VirtualHosts, host: Option<&str>) -> Response {
+        let mut request = Request::new("GET", "/");
+        if let Some(host) = host {
+            request.headers.add("Host", host);
+        }
+        hosts.handle(&mut request)
+    }
+
+    fn served_by(hosts: &VirtualHosts, host: Option<&str>) -> String {
+        String::from_utf8(serve(hosts, host).body.into_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn picks_site_by_host() {
+        let hosts = VirtualHosts::new().host(&["tools.example.com", "*.example.com"], site("tools"))
+                                       .default_host(&["docs.example.com", "*.docs.example.com"], site("docs"));
+
+        assert_eq!("tools", served_by(&hosts, Some("Tools.Example.com:8080")));
+        assert_eq!("tools", served_by(&hosts, Some("wiki.example.com")));
+        assert_eq!("docs", served_by(&hosts, Some("docs.example.com.")));
+        assert_eq!("docs", served_by(&hosts, Some("v2.docs.example.com"))); // the longer wildcard wins
+        assert_eq!("docs", served_by(&hosts, Some("other.org")));
+        assert_eq!("docs", served_by(&hosts, None));
+    }
+
+    #[test]
+    fn unknown_hosts_without_default() {
+        let hosts = VirtualHosts::new().host(&["*.example.com"], site("tools"));
+
+        assert_eq!(404, serve(&hosts, Some("example.com")).status);
+        assert_eq!("[::1]", normalize_host("[::1]:8080"));
+    }
+}
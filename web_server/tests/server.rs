@@ -0,0 +1,156 @@
+// Talks to a real server on an ephemeral port through the client
+
+extern crate web_server;
+
+use std::io::Cursor;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use web_server::ThreadPool;
+use web_server::client::{Client, ClientError};
+use web_server::handler::Handler;
+use web_server::http::{Body, Request, Response};
+use web_server::metrics::{Metrics, Monitor};
+use web_server::rewrite::{Rewrites, Rules};
+use web_server::router::Router;
+use web_server::server::{Server, Shutdown};
+
+// Starts a server in the background and returns its base URL
+fn serve<H: Handler + 'static>(handler: H) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Server::new(listener, ThreadPool::new(4), handler).request_timeout(Duration::from_secs(5));
+    let url = format!("http://{}", server.local_addr().unwrap());
+
+    thread::spawn(move || server.run());
+    url
+}
+
+fn site() -> Router {
+    Router::new().get("/", |_: &mut Request| Response::html(200, "<h1>Hello!</h1>"))
+                 .get("/port", |request: &mut Request| {
+                     Response::text(200, request.remote_addr.unwrap().port().to_string())
+                 })
+                 .post("/echo", |request: &mut Request| Response::text(200, request.buffer_body().unwrap().to_vec()))
+                 .get("/stream", |_: &mut Request| {
+                     let body = Cursor::new(b"streamed without a length".to_vec());
+                     Response::text(200, Body::Stream(Box::new(body), None))
+                 })
+                 .get("/slow", |_: &mut Request| {
+                     thread::sleep(Duration::from_millis(500));
+                     Response::text(200, "finally")
+                 })
+                 .get("/panic", |_: &mut Request| -> Response { panic!("broken handler") })
+}
+
+#[test]
+fn gets_and_posts() {
+    let url = serve(site());
+    let client = Client::new();
+
+    let hello = client.get(&format!("{}/", url)).unwrap();
+    assert_eq!((200, Some("text/html; charset=utf-8")), (hello.status, hello.header("Content-Type")));
+    assert_eq!("<h1>Hello!</h1>", hello.text());
+
+    let echo = client.post(&format!("{}/echo", url), "text/plain", "ping").unwrap();
+    assert_eq!("ping", echo.text());
+
+    let head = client.request(Request::new("HEAD", &format!("{}/", url))).unwrap();
+    assert_eq!((200, 0), (head.status, head.body.len()));
+}
+
+#[test]
+fn reuses_connections() {
+    let url = serve(site());
+    let client = Client::new();
+
+    let first = client.get(&format!("{}/port", url)).unwrap();
+    let second = client.get(&format!("{}/port", url)).unwrap();
+    assert_eq!(Some("keep-alive"), first.header("Connection"));
+    assert_eq!(first.text(), second.text()); // same client port, so same connection
+
+    let other = Client::new().get(&format!("{}/port", url)).unwrap();
+    assert_ne!(first.text(), other.text());
+}
+
+#[test]
+fn decodes_chunked_bodies() {
+    let url = serve(site());
+    let response = Client::new().get(&format!("{}/stream", url)).unwrap();
+
+    assert_eq!(None, response.header("Transfer-Encoding"));
+    assert_eq!("streamed without a length", response.text());
+}
+
+#[test]
+fn follows_redirects_up_to_a_limit() {
+    let rules = Rules::new().redirect(301, "/old", "/")
+                            .redirect(302, "/loop/*", "/loop/$1x");
+    let url = serve(Rewrites::new(site(), rules));
+
+    let moved = Client::new().get(&format!("{}/old", url)).unwrap();
+    assert_eq!((200, "<h1>Hello!</h1>"), (moved.status, moved.text().as_str()));
+    assert_eq!(format!("{}/", url), moved.url.to_string());
+
+    let not_followed = Client::new().max_redirects(0).get(&format!("{}/old", url)).unwrap();
+    assert_eq!((301, Some("/")), (not_followed.status, not_followed.header("Location")));
+
+    match Client::new().max_redirects(3).get(&format!("{}/loop/x", url)) {
+        Err(ClientError::TooManyRedirects) => {},
+        other => panic!("expected too many redirects, got {:?}", other),
+    }
+}
+
+#[test]
+fn times_out() {
+    let url = serve(site());
+
+    match Client::new().timeout(Duration::from_millis(100)).get(&format!("{}/slow", url)) {
+        Err(ClientError::Timeout) => {},
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    assert_eq!("finally", Client::new().get(&format!("{}/slow", url)).unwrap().text());
+}
+
+#[test]
+fn answers_errors_consistently() {
+    let url = serve(site());
+    let client = Client::new();
+
+    let missing = client.get(&format!("{}/missing", url)).unwrap();
+    assert_eq!(404, missing.status);
+    assert!(missing.text().contains("<h1>Not Found</h1>"));
+
+    let mut request = Request::new("GET", &format!("{}/panic", url));
+    request.headers.add("Accept", "application/json");
+    let broken = client.request(request).unwrap();
+    assert_eq!((500, Some("application/json")), (broken.status, broken.header("Content-Type")));
+
+    // The worker survived the panic
+    assert_eq!(200, client.get(&format!("{}/", url)).unwrap().status);
+}
+
+#[test]
+fn reports_health_and_stops_on_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let pool = ThreadPool::new(2);
+    let shutdown = Shutdown::new();
+    let metrics = Metrics::new().pool(pool.stats()).shutdown(shutdown.clone());
+
+    let server = Server::new(listener, pool, Monitor::new(site(), Arc::new(metrics))).keep_alive(Duration::from_millis(200))
+                                                                                      .shutdown(shutdown.clone());
+    let url = format!("http://{}", server.local_addr().unwrap());
+    let running = thread::spawn(move || server.run());
+    let client = Client::new();
+
+    assert_eq!("ready", client.get(&format!("{}/readyz", url)).unwrap().text());
+    client.get(&format!("{}/", url)).unwrap();
+    let metrics = client.get(&format!("{}/metrics", url)).unwrap().text();
+    assert!(metrics.contains("http_requests_total{route=\"/\",status=\"200\"} 1\n"));
+    assert!(metrics.contains("thread_pool_workers 2\n"));
+
+    shutdown.request();
+    running.join().unwrap();
+    assert!(Client::new().get(&format!("{}/healthz", url)).is_err());
+}
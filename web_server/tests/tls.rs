@@ -0,0 +1,105 @@
+// HTTPS with self-signed certificates made up for each run
+
+extern crate rcgen;
+extern crate rustls;
+extern crate web_server;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use web_server::ThreadPool;
+use web_server::client::Client;
+use web_server::http::{Request, Response};
+use web_server::server::Server;
+use web_server::tls::{self, Certificates};
+
+use temp::TempDir;
+
+#[path = "../src/temp.rs"] // the unit tests' own
+mod temp;
+
+struct Site {
+    cert: CertificateDer<'static>,
+    key: Arc<rustls::sign::CertifiedKey>,
+}
+
+// A certificate for the given names, written out and read back as the server would
+fn site(dir: &Path, name: &str) -> Site {
+    let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+    let (cert, key) = (dir.join(format!("{}.pem", name)), dir.join(format!("{}.key", name)));
+    fs::write(&cert, generated.cert.pem()).unwrap();
+    fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+
+    Site {
+        cert: generated.cert.der().clone(),
+        key: tls::load(cert.to_str().unwrap(), key.to_str().unwrap()).unwrap(),
+    }
+}
+
+// Starts a server answering with whether the request came over TLS, returning both addresses
+fn serve(certificates: Certificates, redirect: bool) -> (String, String) {
+    let handler = |request: &mut Request| Response::text(200, if request.secure { "secure" } else { "plain" });
+    let server = Server::new(TcpListener::bind("127.0.0.1:0").unwrap(), ThreadPool::new(2), handler)
+                        .https(TcpListener::bind("127.0.0.1:0").unwrap(), certificates.server_config().unwrap())
+                        .redirect_to_https(redirect);
+    let addresses = (server.local_addr().unwrap().to_string(), server.https_addr().unwrap().to_string());
+
+    thread::spawn(move || server.run());
+    addresses
+}
+
+// Makes a request trusting only the given certificate, returning the raw response
+fn get(address: &str, name: &str, trusted: &CertificateDer<'static>) -> io::Result<String> {
+    let mut roots = RootCertStore::empty();
+    roots.add(trusted.clone()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                              .with_safe_default_protocol_versions().unwrap()
+                              .with_root_certificates(roots)
+                              .with_no_client_auth();
+
+    let connection = ClientConnection::new(Arc::new(config), ServerName::try_from(name.to_string()).unwrap()).unwrap();
+    let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+    let request = format!("GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", name);
+
+    let mut response = String::new();
+    stream.write_all(request.as_bytes())?;
+    stream.read_to_string(&mut response)?;
+
+    Ok(response)
+}
+
+#[test]
+fn picks_certificates_by_name() {
+    let dir = TempDir::new("tls_sni");
+    let (docs, blog) = (site(&dir, "docs.test"), site(&dir, "blog.test"));
+    let certificates = Certificates::new().add(&["docs.test"], Arc::clone(&docs.key))
+                                          .add(&["blog.test", "*.blog.test"], Arc::clone(&blog.key));
+    let (_, https) = serve(certificates, false);
+
+    let response = get(&https, "docs.test", &docs.cert).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nsecure"));
+    assert!(get(&https, "blog.test", &blog.cert).unwrap().ends_with("secure"));
+
+    // Asking for the blog gets the blog's certificate, which a client trusting only the docs' refuses
+    assert!(get(&https, "blog.test", &docs.cert).is_err());
+}
+
+#[test]
+fn redirects_plain_requests() {
+    let dir = TempDir::new("tls_redirect");
+    let localhost = site(&dir, "localhost");
+    let (plain, https) = serve(Certificates::new().default_cert(localhost.key), true);
+
+    let response = Client::new().max_redirects(0).get(&format!("http://{}/page?x=1", plain)).unwrap();
+    let port = https.rsplit(':').next().unwrap();
+    assert_eq!((301, Some(format!("https://127.0.0.1:{}/page?x=1", port))), (response.status, response.header("Location").map(String::from)));
+}